@@ -1,46 +1,255 @@
 use crate::cli::{BasicHistory, CLI};
+use crate::digest;
+use crate::draft;
 use crate::history;
+use crate::instructions;
+use crate::models::{
+    CodeBlock, Constraint, CopiedItem, Message, ModelPin, Persona, ReplyFormat, Sampling,
+    StreamEvent, TokenLogprob,
+};
 use crate::openai;
-use crate::openai::AVAILABLE_MODELS;
+use crate::openai::{send_request, AVAILABLE_MODELS};
+use crate::rate_limit::RateLimiter;
+use crate::recording::{self, Recorder, Recording};
+use crate::redact;
+use crate::response::{
+    self, CodeBlockCollector, ConfidenceRenderer, RawLogger, Sink, TerminalRenderer, UsageTracker,
+};
+use crate::serve;
+use crate::share::{self, Relay, Share};
+use crate::shutdown::Spool;
+use crate::staging::Staging;
+use crate::stats::{self, RequestStats};
 use crate::system_prompt::SystemPrompts;
+use crate::theme;
 
 use dirs::data_dir;
 use history::History;
+use regex::Regex;
 use tokio::runtime::Runtime;
 
-use std::sync::Arc;
+use futures_util::StreamExt;
+
+use std::collections::HashMap;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock};
+use std::time::Instant;
 use tokio::sync::Mutex;
 
 pub struct Application {
     pub tokio_rt: Runtime,
     pub context: openai::SharedContext,
     pub cli_history: BasicHistory,
-    pub session_history: History, // FIXME: Remove, we have SharedContext.
-    pub code_blocks: Vec<String>,
+    pub session_history: History,
+    pub code_blocks: Vec<CodeBlock>,
     pub model: String,
+    pub fallback_models: Vec<String>,
+    /// Set when a session tag or the project requires a model.
+    pub pinned_model: Option<ModelPin>,
     pub system_prompts: SystemPrompts,
     pub active_system_prompt: String,
+    /// Whether the selected system prompt is part of the system message,
+    /// which it is once picked with `/system_use`.
+    pub system_prompt_used: bool,
+    /// Instructions added with `/instructions add` for this session.
+    pub session_instructions: Vec<String>,
+    /// Language replies should be written in, see `/lang`.
+    pub reply_language: Option<String>,
     pub markdown: bool,
+    pub line_numbers: bool,
+    /// Copy the code block of replies with exactly one, see `/auto_copy`.
+    pub auto_copy: bool,
+    pub editor_draft: Option<String>,
+    pub staging: Staging,
+    pub offline: bool,
+    pub queue_offline: bool,
+    pub offline_queue: Vec<String>,
+    pub truncated: bool,
+    pub redact: bool,
+    pub abbreviations: HashMap<String, String>,
+    pub token_warning: usize,
+    pub sampling: Sampling,
+    pub presets: HashMap<String, Sampling>,
+    pub active_preset: Option<String>,
+    pub personas: HashMap<String, Persona>,
+    pub copied: Vec<CopiedItem>,
+    /// Values bound with `/command > $name`, usable as `$name` in messages.
+    pub variables: HashMap<String, String>,
+    /// Value produced by the last command, if it has one.
+    pub last_result: Option<String>,
+    /// Messages of earlier sessions read from the history file.
+    pub restored_history: Vec<Message>,
+    /// Color replies by token probability, see `/confidence`.
+    pub confidence: bool,
+    /// The grammar or schema replies must follow, see `/constrain`.
+    pub constraint: Option<Constraint>,
+    pub last_logprobs: Vec<TokenLogprob>,
+    /// Suggest follow-up questions after each reply, see `/followups`.
+    pub followups: bool,
+    pub followup_model: String,
+    pub suggested_followups: Vec<String>,
+    /// Critique and improve every reply, see `/reflect`.
+    pub reflect: bool,
+    pub show_critique: bool,
+    /// Documents added with `/digest`, searchable with `/digest find`.
+    pub documents: Vec<digest::Document>,
+    /// Print each prompt to stderr as sent, see `--echo`.
+    pub echo: bool,
+    /// Set while a dropped reply is being resumed.
+    resuming: bool,
+    /// Compact rendering for narrow terminals, see `--compact`.
+    pub compact: bool,
+    /// Print request bodies instead of sending them, see `--dry-run`.
+    pub dry_run: bool,
+    /// The reply replaced by the last `/retry`, for `/diff`.
+    pub previous_reply: Option<String>,
+    /// The session being shared with `/share`.
+    pub share: Option<Share>,
+    /// The web page of `chad-gpt serve`.
+    pub server: Option<serve::Server>,
+    /// Replies being recorded with `/record`.
+    pub recording: Option<Recording>,
+    /// Model whose quick answer is shown until the main one arrives, see
+    /// `/draft`.
+    pub draft_model: Option<String>,
+    /// Start a fresh session each day, see `CHAD_LLM_DAILY_SESSIONS`.
+    pub daily_sessions: bool,
+    /// The local date the current session belongs to.
+    session_day: String,
     cli: CLI,
 }
 
-pub const HISTORY_FILE: &str = "session_history.txt";
+pub const EDITOR_TEMPLATE_FILE: &str = "editor_template.txt";
+pub const ABBREVIATIONS_FILE: &str = "abbreviations.json";
+pub const DEFAULT_TOKEN_WARNING: usize = 8000;
+pub const PRESETS_FILE: &str = "presets.json";
+pub const PERSONAS_FILE: &str = "personas.json";
+pub const MODEL_PINS_FILE: &str = "model_pins.json";
+/// Session templates, `/context template <name>`.
+pub const SESSION_TEMPLATES_DIR: &str = "templates";
+/// Per-project settings in the working directory.
+pub const PROJECT_CONFIG_FILE: &str = ".chad-llm.json";
+pub const DEFAULT_HISTORY_EXCHANGES: usize = 5;
+pub const SESSION_TITLE_CHARS: usize = 40;
+pub const DEFAULT_PROMPT: &str = "[$green]{user} [$/]{preset}> ";
+pub const DEFAULT_COMPACT_PROMPT: &str = "{preset}> ";
+pub const DEFAULT_RPROMPT: &str = "[$dim]~{tokens} tokens | {model}[$/]";
+pub const DEFAULT_FOLLOWUP_MODEL: &str = "gpt-4o-mini";
+pub const DEFAULT_DRAFT_MODEL: &str = "gpt-4o-mini";
+
+const FOLLOWUP_PROMPT: &str = "Suggest exactly 3 short follow-up questions the user might ask \
+next about the conversation they send. Write one question per line, no numbering, nothing else.";
+
+const CRITIQUE_PROMPT: &str = "You are a demanding reviewer. List the mistakes, omissions \
+and unclear parts of the answer you are given, most important first. Do not rewrite it.";
+
+const IMPROVE_PROMPT: &str = "Rewrite the answer you are given so that it addresses the \
+critique. Reply with the improved answer only, without mentioning the critique.";
+
+const ARCHIVE_PROMPT: &str = "Write a title of at most eight words for the conversation \
+you are given on the first line, then a blank line, then a summary of it in a few sentences \
+covering what was asked, decided and left open. No markdown.";
+/// The end of long conversations is what gets summarized.
+const ARCHIVE_CHARS: usize = 48_000;
+
+/// Attempts to resume a reply cut off by a dropped connection.
+const MAX_RESUMES: usize = 3;
+
+const CONTINUE_PROMPT: &str =
+    "Your previous reply was cut off. Continue exactly where it stopped, \
+without repeating anything. If it stopped inside a code block, continue the code directly \
+without opening a new code fence.";
+
+pub fn get_data_dir() -> PathBuf {
+    let mut path = data_dir().unwrap();
+    path.push("chad-llm");
+    path
+}
+
+pub fn get_data_path(name: &str) -> PathBuf {
+    let mut path = get_data_dir();
+    for part in name.split('/') {
+        path.push(part);
+    }
+    path
+}
 
 impl Application {
     pub fn new() -> Self {
-        let mut path = data_dir().unwrap();
-        path.push("chad-llm/");
-        let _ = std::fs::create_dir(path.as_path());
+        let _ = std::fs::create_dir_all(get_data_dir());
         let mut app = Application {
             tokio_rt: Runtime::new().unwrap(),
             context: Arc::new(Mutex::new(Vec::new())),
             cli_history: BasicHistory::new(),
-            session_history: History::new(HISTORY_FILE),
+            session_history: History::new(),
             code_blocks: Vec::new(),
             model: AVAILABLE_MODELS[0].to_owned(),
+            fallback_models: std::env::var("CHAD_LLM_FALLBACK_MODELS")
+                .map(|v| {
+                    v.split(',')
+                        .map(|m| m.trim().to_owned())
+                        .filter(|m| !m.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            pinned_model: None,
             system_prompts: SystemPrompts::new(),
             active_system_prompt: "".to_owned(),
+            system_prompt_used: false,
+            session_instructions: Vec::new(),
+            reply_language: std::env::var("CHAD_LLM_REPLY_LANGUAGE")
+                .ok()
+                .filter(|l| !l.is_empty()),
             markdown: true,
+            line_numbers: std::env::var("CHAD_LLM_LINE_NUMBERS").is_ok_and(|v| v == "1"),
+            auto_copy: std::env::var("CHAD_LLM_AUTO_COPY").is_ok_and(|v| v == "1"),
+            editor_draft: None,
+            staging: Staging::new(),
+            offline: false,
+            queue_offline: true,
+            offline_queue: Vec::new(),
+            truncated: false,
+            redact: std::env::var("CHAD_LLM_REDACT").is_ok_and(|v| v == "1"),
+            abbreviations: Self::load_abbreviations(),
+            token_warning: std::env::var("CHAD_LLM_TOKEN_WARNING")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_TOKEN_WARNING),
+            sampling: Sampling {
+                seed: std::env::var("CHAD_LLM_SEED")
+                    .ok()
+                    .and_then(|v| v.parse().ok()),
+                ..Sampling::default()
+            },
+            presets: Self::load_presets(),
+            personas: Self::load_personas(),
+            active_preset: None,
+            copied: Vec::new(),
+            variables: HashMap::new(),
+            last_result: None,
+            restored_history: Vec::new(),
+            confidence: false,
+            constraint: None,
+            last_logprobs: Vec::new(),
+            followups: std::env::var("CHAD_LLM_FOLLOWUPS").is_ok_and(|v| v == "1"),
+            followup_model: std::env::var("CHAD_LLM_FOLLOWUP_MODEL")
+                .unwrap_or_else(|_| DEFAULT_FOLLOWUP_MODEL.to_owned()),
+            suggested_followups: Vec::new(),
+            reflect: std::env::var("CHAD_LLM_REFLECT").is_ok_and(|v| v == "1"),
+            show_critique: std::env::var("CHAD_LLM_SHOW_CRITIQUE").is_ok_and(|v| v == "1"),
+            documents: Vec::new(),
+            echo: false,
+            resuming: false,
+            compact: false,
+            dry_run: false,
+            previous_reply: None,
+            share: None,
+            server: None,
+            recording: None,
+            draft_model: std::env::var("CHAD_LLM_DRAFT_MODEL").ok(),
+            daily_sessions: std::env::var("CHAD_LLM_DAILY_SESSIONS").is_ok_and(|v| v == "1"),
+            session_day: stats::local_date(stats::now()),
             cli: CLI::new(),
         };
         app.active_system_prompt = match app
@@ -57,6 +266,1237 @@ impl Application {
                 .to_owned(),
             Some(_) => "default".to_owned(),
         };
+        app.apply_instructions();
         app
     }
+
+    pub fn instruction_layers(&self) -> Vec<instructions::Layer> {
+        let system_prompt = match self.system_prompt_used {
+            true => self
+                .system_prompts
+                .get(&self.active_system_prompt)
+                .map(|p| (self.active_system_prompt.as_str(), p.as_str())),
+            false => None,
+        };
+        instructions::layers(
+            system_prompt,
+            &self.session_instructions,
+            self.reply_language.as_deref(),
+        )
+    }
+
+    /// The system message composed of all instruction layers.
+    pub fn system_instructions(&self) -> String {
+        instructions::compose(&self.instruction_layers())
+    }
+
+    /// Puts the composed system message at the start of the context.
+    pub fn apply_instructions(&self) {
+        let system = self.system_instructions();
+        self.tokio_rt.block_on(async {
+            openai::set_system_prompt(&mut *self.context.lock().await, &system);
+        });
+    }
+
+    /// Puts the system message back after it was deleted, so the
+    /// conversation doesn't lose its instructions.
+    pub fn restore_system_message(&self) {
+        let system = self.system_instructions();
+        let missing = self.tokio_rt.block_on(async {
+            let locked = self.context.lock().await;
+            !locked.iter().any(|m| m.role == "system")
+        });
+        if missing && !system.is_empty() {
+            self.apply_instructions();
+            print!("Restored the system prompt.\r\n");
+        }
+    }
+    /// Reads the abbreviations file, a JSON object mapping each abbreviation
+    /// to its expansion, e.g. `{";;r": "Refactor the following code:"}`.
+    fn load_abbreviations() -> HashMap<String, String> {
+        let path = get_data_path(ABBREVIATIONS_FILE);
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            return HashMap::new();
+        };
+        match serde_json::from_str(&contents) {
+            Ok(x) => x,
+            Err(e) => {
+                print!("Failed to parse {}: {}\r\n", path.display(), e);
+                HashMap::new()
+            }
+        }
+    }
+
+    /// The built-in sampling presets, overridden by entries in the presets
+    /// file, e.g. `{"creative": {"temperature": 1.1, "top_p": 0.95}}`.
+    fn load_presets() -> HashMap<String, Sampling> {
+        let mut presets = HashMap::from([
+            (
+                "creative".to_owned(),
+                Sampling {
+                    temperature: 1.0,
+                    top_p: Some(0.95),
+                    seed: None,
+                },
+            ),
+            ("balanced".to_owned(), Sampling::default()),
+            (
+                "precise".to_owned(),
+                Sampling {
+                    temperature: 0.1,
+                    top_p: Some(0.5),
+                    seed: None,
+                },
+            ),
+        ]);
+
+        let path = get_data_path(PRESETS_FILE);
+        if let Ok(contents) = std::fs::read_to_string(&path) {
+            match serde_json::from_str::<HashMap<String, Sampling>>(&contents) {
+                Ok(x) => presets.extend(x),
+                Err(e) => print!("Failed to parse {}: {}\r\n", path.display(), e),
+            }
+        }
+        presets
+    }
+
+    /// Reads the personas file, e.g. `{"reviewer": {"color": "magenta",
+    /// "system_prompt": "You review code.", "model": "gpt-4o"}}`.
+    fn load_personas() -> HashMap<String, Persona> {
+        let path = get_data_path(PERSONAS_FILE);
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            return HashMap::new();
+        };
+        match serde_json::from_str(&contents) {
+            Ok(x) => x,
+            Err(e) => {
+                print!("Failed to parse {}: {}\r\n", path.display(), e);
+                HashMap::new()
+            }
+        }
+    }
+
+    /// The persona a message starting with `@name` is addressed to.
+    fn addressed_persona(&self, input: &str) -> Option<(String, Persona)> {
+        let name = input.strip_prefix('@')?.split_whitespace().next()?;
+        let name = name.trim_end_matches([',', ':']);
+        self.personas
+            .get(name)
+            .map(|p| (name.to_owned(), p.clone()))
+    }
+
+    fn persona_label(name: &str, persona: &Persona) -> String {
+        theme::format(&format!(
+            "[${}]{}:[$/]",
+            persona.color.as_deref().unwrap_or("magenta"),
+            name
+        ))
+    }
+
+    /// Works out which model, if any, the session must use given its
+    /// `tags`: the first tag listed in the pins file (e.g.
+    /// `{"prod-incident": "gpt-4o"}`), otherwise the `model` of the project
+    /// config. Switches to that model.
+    pub fn apply_model_pins(&mut self, tags: &[String]) {
+        let path = get_data_path(MODEL_PINS_FILE);
+        let pins: HashMap<String, String> = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                print!("Failed to parse {}: {}\r\n", path.display(), e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        let project_model = std::fs::read_to_string(PROJECT_CONFIG_FILE)
+            .ok()
+            .and_then(|c| serde_json::from_str::<serde_json::Value>(&c).ok())
+            .and_then(|c| c.get("model")?.as_str().map(String::from));
+
+        let pin = tags
+            .iter()
+            .find_map(|tag| {
+                pins.get(tag).map(|model| ModelPin {
+                    model: model.clone(),
+                    reason: format!("tag {}", tag),
+                })
+            })
+            .or_else(|| {
+                project_model.map(|model| ModelPin {
+                    model,
+                    reason: PROJECT_CONFIG_FILE.to_owned(),
+                })
+            });
+        if let Some(pin) = &pin {
+            if self.model != pin.model {
+                print!("Using {} as required by {}.\r\n", pin.model, pin.reason);
+                self.model = pin.model.clone();
+            }
+        }
+        self.pinned_model = pin;
+    }
+
+    /// The models to fall back on. A pinned model has none, so requests never
+    /// end up on another one.
+    fn fallbacks(&self) -> &[String] {
+        match self.pinned_model {
+            Some(_) => &[],
+            None => &self.fallback_models,
+        }
+    }
+
+    pub fn editor_template(&self) -> String {
+        std::fs::read_to_string(get_data_path(EDITOR_TEMPLATE_FILE)).unwrap_or_default()
+    }
+
+    pub fn last_user_message(&self) -> Option<String> {
+        let shared_context = &self.context;
+        self.tokio_rt.block_on(async {
+            let locked = shared_context.lock().await;
+            locked
+                .iter()
+                .rev()
+                .find(|m| m.role == "user")
+                .map(|m| m.content.clone())
+        })
+    }
+    /// Number (1-based) of the latest reply containing `content`.
+    pub fn reply_containing(&self, content: &str) -> Option<usize> {
+        let shared_context = &self.context;
+        self.tokio_rt.block_on(async {
+            let locked = shared_context.lock().await;
+            locked
+                .iter()
+                .filter(|m| m.role == "assistant")
+                .enumerate()
+                .filter(|(_, m)| m.content.contains(content.trim_end()))
+                .last()
+                .map(|(i, _)| i + 1)
+        })
+    }
+    /// Copies to the clipboard and remembers the item for `/copied`.
+    pub fn copy_to_clipboard(
+        &mut self,
+        source: String,
+        content: String,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        CLI::copy_to_clipboard(content.clone())?;
+        self.last_result = Some(content.clone());
+        self.copied.push(CopiedItem {
+            copied_at: Instant::now(),
+            source,
+            content,
+        });
+        Ok(())
+    }
+
+    /// Copies the code block a reply just ended with, unless it was cut
+    /// off.
+    fn auto_copy_block(&mut self) {
+        let Some(block) = self.code_blocks.last().filter(|b| !b.truncated) else {
+            return;
+        };
+        let source = format!(
+            "code block {} ({}), copied automatically",
+            self.code_blocks.len(),
+            block.language
+        );
+        match self.copy_to_clipboard(source, block.content.clone()) {
+            Ok(()) => print!("\r\n{}\r\n", theme::format("[$dim](copied)[$/]")),
+            Err(e) => eprint!("Failed to copy to clipboard: {}\r\n", e),
+        }
+    }
+
+    /// With daily sessions on, starts a fresh session once the day has
+    /// changed, archiving the previous one under a generated title and
+    /// summary. Returns whether it did.
+    pub fn rotate_daily_session(&mut self) -> bool {
+        let today = stats::local_date(stats::now());
+        if !self.daily_sessions || today == self.session_day {
+            return false;
+        }
+        let previous_day = std::mem::replace(&mut self.session_day, today);
+        let transcript: String = self.tokio_rt.block_on(async {
+            let locked = self.context.lock().await;
+            locked
+                .iter()
+                .filter(|m| m.role != "system")
+                .map(|m| format!("{}: {}\n\n", m.role, m.content))
+                .collect()
+        });
+        if transcript.is_empty() {
+            return false;
+        }
+
+        print!(
+            "{}\r\n",
+            theme::format(&format!(
+                "[$dim]Starting a fresh session for the new day, archiving the one from {}…[$/]",
+                previous_day
+            ))
+        );
+        let start = transcript
+            .char_indices()
+            .rev()
+            .nth(ARCHIVE_CHARS)
+            .map_or(0, |(i, _)| i);
+        let mut meta = self.session_history.meta().unwrap_or_default();
+        match self.tokio_rt.block_on(openai::complete(
+            &self.model,
+            Some(ARCHIVE_PROMPT),
+            &transcript[start..],
+        )) {
+            Ok(reply) => {
+                let (title, summary) = reply.trim().split_once('\n').unwrap_or((&reply, ""));
+                meta.title = Some(title.trim().trim_matches(['#', '"', '*']).trim().to_owned());
+                meta.summary = Some(summary.trim().to_owned()).filter(|s| !s.is_empty());
+            }
+            Err(e) => eprint!("Failed to summarize the session: {}\r\n", e),
+        }
+        if let Err(e) = self.session_history.set_meta(meta.clone()) {
+            eprint!("Failed to save session metadata: {}\r\n", e);
+        }
+
+        self.tokio_rt.block_on(async {
+            self.context.lock().await.retain(|m| m.role == "system");
+        });
+        self.session_history.start_new();
+        self.code_blocks.clear();
+        if let Some(title) = meta.title {
+            print!("Archived as \"{}\", find it with /sessions.\r\n", title);
+        }
+        true
+    }
+
+    /// A short title for the session: the start of its first message.
+    pub fn session_title(&self) -> String {
+        let first = self.tokio_rt.block_on(async {
+            let locked = self.context.lock().await;
+            locked.iter().find(|m| m.role == "user").map(|m| {
+                m.content
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_owned()
+            })
+        });
+        match first {
+            Some(first) if first.chars().count() > SESSION_TITLE_CHARS => {
+                let cut: String = first.chars().take(SESSION_TITLE_CHARS).collect();
+                format!("{}…", cut.trim_end())
+            }
+            Some(first) if !first.is_empty() => first,
+            _ => "new session".to_owned(),
+        }
+    }
+
+    /// The REPL prompt, from `CHAD_LLM_PROMPT`.
+    pub fn prompt(&self) -> String {
+        let template = std::env::var("CHAD_LLM_PROMPT").unwrap_or_else(|_| {
+            match self.compact {
+                true => DEFAULT_COMPACT_PROMPT,
+                false => DEFAULT_PROMPT,
+            }
+            .to_owned()
+        });
+        self.render_prompt(&template)
+    }
+
+    /// The right side of the REPL prompt, from `CHAD_LLM_RPROMPT`. Off in
+    /// compact mode unless set.
+    pub fn rprompt(&self) -> String {
+        let template = std::env::var("CHAD_LLM_RPROMPT").unwrap_or_else(|_| {
+            match self.compact {
+                true => "",
+                false => DEFAULT_RPROMPT,
+            }
+            .to_owned()
+        });
+        self.render_prompt(&template)
+    }
+
+    /// Fills in `{user}`, `{model}`, `{tokens}` (in the context), `{cwd}`,
+    /// `{time}` and `{preset}`.
+    fn render_prompt(&self, template: &str) -> String {
+        let preset = self
+            .active_preset
+            .as_ref()
+            .map(|p| format!("[$cyan]({})[$/] ", p))
+            .unwrap_or_default();
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let cwd = match dirs::home_dir().and_then(|home| cwd.strip_prefix(home).ok()) {
+            Some(rest) if rest.as_os_str().is_empty() => "~".to_owned(),
+            Some(rest) => format!("~/{}", rest.display()),
+            None => cwd.display().to_string(),
+        };
+        template
+            .replace("{preset}", &preset)
+            .replace("{user}", &whoami::realname())
+            .replace("{model}", &self.model)
+            .replace("{cwd}", &cwd)
+            .replace("{time}", &stats::clock(stats::now()))
+            .replace("{tokens}", &self.context_tokens().to_string())
+    }
+
+    /// Estimated tokens of everything in the context.
+    pub fn context_tokens(&self) -> usize {
+        self.tokio_rt.block_on(async {
+            self.context
+                .lock()
+                .await
+                .iter()
+                .map(|m| RateLimiter::estimate_tokens(&m.content))
+                .sum()
+        })
+    }
+
+    /// Replaces `$name` with the value of each bound variable. Unknown
+    /// names are left alone.
+    pub fn interpolate(&self, input: &str) -> String {
+        static VARIABLE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"\$([A-Za-z_][A-Za-z0-9_]*)").unwrap());
+        VARIABLE
+            .replace_all(input, |caps: &regex::Captures| {
+                self.variables
+                    .get(&caps[1])
+                    .cloned()
+                    .unwrap_or_else(|| caps[0].to_owned())
+            })
+            .into_owned()
+    }
+    /// Sends a prompt from a guest of the shared session. What the host
+    /// staged stays staged for the host's own next message.
+    pub fn send_guest_message(&mut self, input: &str) -> bool {
+        let staged = std::mem::replace(&mut self.staging, Staging::new());
+        let sent = self.send_message(input);
+        self.staging = staged;
+        sent
+    }
+
+    /// Sends `input` with anything staged. Returns whether it was sent.
+    pub fn send_message(&mut self, input: &str) -> bool {
+        let input = match self.staging.is_empty() {
+            true => input.to_owned(),
+            false => self.staging.build(input),
+        };
+        let input = match self.redact {
+            true => Self::redact_secrets(&input),
+            false => input,
+        };
+
+        let tokens = RateLimiter::estimate_tokens(&input);
+        if self.token_warning > 0 && tokens > self.token_warning && io::stdin().is_terminal() {
+            let choice = CLI::select(
+                &format!(
+                    "This message is about {} tokens (warning threshold {}). Send it?",
+                    tokens, self.token_warning
+                ),
+                &["No", "Yes"],
+                true,
+                &[0],
+            );
+            if choice.first() != Some(&1) {
+                print!("Not sent.\r\n");
+                return false;
+            }
+        }
+        if self.dry_run {
+            self.preview(&input);
+            return false;
+        }
+        self.staging.clear();
+        if self.echo {
+            eprintln!("{}", input);
+        }
+        if let Some(recording) = &self.recording {
+            recording.write(&recording::Entry::Prompt {
+                content: input.clone(),
+            });
+        }
+        if let Some(share) = &self.share {
+            share.send(share::Event::Message {
+                role: "user".to_owned(),
+                content: input.clone(),
+            });
+        }
+
+        let reply = match self.addressed_persona(&input) {
+            Some((name, persona)) => self.persona_reply(&name, &persona, &input),
+            None => self.stream_reply(&input, None),
+        };
+        if let Some((model, reply)) = reply {
+            let saved = self
+                .session_history
+                .save_message("user", &input, None)
+                .and_then(|_| {
+                    self.session_history
+                        .save_message("assistant", &reply, Some(&model))
+                });
+            if let Err(e) = saved {
+                eprint!("Failed to save transcript: {}\r\n", e);
+            }
+            if self.reflect {
+                self.reflect_last();
+            }
+            if self.followups && io::stdout().is_terminal() {
+                self.suggest_followups(&input, &reply);
+            }
+        }
+        true
+    }
+
+    /// Streams the reply of a persona: its system prompt and model stand in
+    /// for the session's own for this one message.
+    fn persona_reply(
+        &mut self,
+        name: &str,
+        persona: &Persona,
+        input: &str,
+    ) -> Option<(String, String)> {
+        let shared_context = &self.context;
+        let system = self.tokio_rt.block_on(async {
+            let mut locked = shared_context.lock().await;
+            let system = locked
+                .first()
+                .filter(|m| m.role == "system")
+                .map(|m| m.content.clone())
+                .unwrap_or_default();
+            openai::set_system_prompt(&mut locked, &persona.system_prompt);
+            system
+        });
+        let model = match &persona.model {
+            Some(model) if self.pinned_model.is_none() => {
+                std::mem::replace(&mut self.model, model.clone())
+            }
+            _ => self.model.clone(),
+        };
+
+        print!("{}\r\n", Self::persona_label(name, persona));
+        let reply = self.stream_reply(input, None);
+
+        self.model = model;
+        let shared_context = &self.context;
+        self.tokio_rt.block_on(async {
+            let mut locked = shared_context.lock().await;
+            openai::set_system_prompt(&mut locked, &system);
+            if reply.is_some() {
+                if let Some(last) = locked.last_mut() {
+                    last.metadata.persona = Some(name.to_owned());
+                }
+            }
+        });
+        reply
+    }
+
+    /// Lets `participants` take `turns` turns each discussing `topic`, every
+    /// one seeing what was said before, then has `moderator` sum it up.
+    /// The summary joins the conversation so it can be followed up on.
+    pub fn roundtable(
+        &mut self,
+        topic: &str,
+        participants: &[String],
+        moderator: Option<&str>,
+        turns: usize,
+    ) -> bool {
+        let mut transcript = String::new();
+        for turn in 1..=turns {
+            print!(
+                "{}\r\n",
+                theme::format(&format!("[$dim]--- turn {} of {} ---[$/]", turn, turns))
+            );
+            for name in participants {
+                let persona = self.personas[name].clone();
+                print!("{}\r\n", Self::persona_label(name, &persona));
+                let input = format!(
+                    "Round-table discussion on: {}\n\nSo far:\n{}\n\nYou are {}. Add your \
+                     contribution in a few short paragraphs, responding to the others.",
+                    topic,
+                    match transcript.is_empty() {
+                        true => "(nothing yet, you go first)",
+                        false => &transcript,
+                    },
+                    name
+                );
+                let model = persona.model.clone().unwrap_or_else(|| self.model.clone());
+                let Some(reply) =
+                    self.side_request_with(Some(&persona.system_prompt), &model, &input)
+                else {
+                    return false;
+                };
+                transcript.push_str(&format!("{}: {}\n\n", name, reply.trim()));
+            }
+        }
+
+        let summary_request = format!(
+            "Summarize this round-table discussion on: {}\n\n{}\nList where the participants \
+             agree, where they disagree and the conclusion.",
+            topic, transcript
+        );
+        let reply = match moderator.and_then(|m| self.personas.get(m).cloned()) {
+            Some(persona) => {
+                let name = moderator.unwrap();
+                print!("{}\r\n", Self::persona_label(name, &persona));
+                let model = persona.model.clone().unwrap_or_else(|| self.model.clone());
+                self.side_reply(Some(&persona.system_prompt), &model, &summary_request)
+            }
+            None => {
+                let model = self.model.clone();
+                self.side_reply(None, &model, &summary_request)
+            }
+        };
+        let Some((model, reply)) = reply else {
+            return false;
+        };
+
+        let shared_context = &self.context;
+        self.tokio_rt.block_on(async {
+            let mut locked = shared_context.lock().await;
+            locked.push(Message::new("user", &summary_request));
+            let mut summary = Message::new("assistant", &reply);
+            summary.metadata.persona = moderator.map(String::from);
+            locked.push(summary);
+        });
+        let saved = self
+            .session_history
+            .save_message("user", &summary_request, None)
+            .and_then(|_| {
+                self.session_history
+                    .save_message("assistant", &reply, Some(&model))
+            });
+        if let Err(e) = saved {
+            eprint!("Failed to save transcript: {}\r\n", e);
+        }
+        true
+    }
+
+    /// Has the model critique its last reply and then improve it. The
+    /// improved answer replaces the reply in the context; the critique is
+    /// only shown with `show_critique`.
+    pub fn reflect_last(&mut self) -> bool {
+        let shared_context = &self.context;
+        let exchange = self.tokio_rt.block_on(async {
+            let locked = shared_context.lock().await;
+            let n = locked.len();
+            (n >= 2 && locked[n - 1].role == "assistant" && locked[n - 2].role == "user")
+                .then(|| (locked[n - 2].content.clone(), locked[n - 1].content.clone()))
+        });
+        let Some((question, answer)) = exchange else {
+            print!("There is no reply to reflect on.\r\n");
+            return false;
+        };
+
+        let review = format!("Question:\n{}\n\nAnswer:\n{}", question, answer);
+        print!("{}\r\n", theme::format("[$dim]Reviewing the answer…[$/]"));
+        let critique = match self.show_critique {
+            true => self.side_request(Some(CRITIQUE_PROMPT), &review),
+            false => match self.tokio_rt.block_on(openai::complete(
+                &self.model,
+                Some(CRITIQUE_PROMPT),
+                &review,
+            )) {
+                Ok(x) => Some(x),
+                Err(e) => {
+                    eprint!("Request failed: {}\r\n", e);
+                    None
+                }
+            },
+        };
+        let Some(critique) = critique else {
+            return false;
+        };
+
+        print!("{}\r\n", theme::format("[$dim]Improved answer:[$/]"));
+        let model = self.model.clone();
+        let Some((model, improved)) = self.side_reply(
+            Some(IMPROVE_PROMPT),
+            &model,
+            &format!("{}\n\nCritique:\n{}", review, critique),
+        ) else {
+            return false;
+        };
+
+        let shared_context = &self.context;
+        self.tokio_rt.block_on(async {
+            let mut locked = shared_context.lock().await;
+            if let Some(last) = locked.last_mut().filter(|m| m.role == "assistant") {
+                last.content = improved.clone();
+            }
+        });
+        // The transcript keeps the original; the improved answer replaces it
+        // when a resumed session is loaded.
+        let saved = self.session_history.save_replacement(&improved, &model);
+        if let Err(e) = saved {
+            eprint!("Failed to save transcript: {}\r\n", e);
+        }
+        true
+    }
+
+    /// Asks the follow-up model for questions to continue with and lists
+    /// them for `/f`.
+    fn suggest_followups(&mut self, input: &str, reply: &str) {
+        // The end of a long reply is enough to go on.
+        let reply: String = {
+            let chars: Vec<char> = reply.chars().collect();
+            chars[chars.len().saturating_sub(4000)..].iter().collect()
+        };
+        let prompt = format!("User: {}\n\nAssistant: {}", input, reply);
+        let followup_model = match &self.pinned_model {
+            Some(pin) => &pin.model,
+            None => &self.followup_model,
+        };
+        let suggestions = match self.tokio_rt.block_on(openai::complete(
+            followup_model,
+            Some(FOLLOWUP_PROMPT),
+            &prompt,
+        )) {
+            Ok(x) => x,
+            Err(e) => {
+                eprint!("Failed to get follow-up suggestions: {}\r\n", e);
+                return;
+            }
+        };
+
+        self.suggested_followups = suggestions
+            .lines()
+            .map(|l| {
+                l.trim()
+                    .trim_start_matches(|c: char| c.is_ascii_digit() || ".)-* ".contains(c))
+                    .to_owned()
+            })
+            .filter(|l| !l.is_empty())
+            .take(3)
+            .collect();
+        if self.suggested_followups.is_empty() {
+            return;
+        }
+        print!("\r\n");
+        for (i, question) in self.suggested_followups.iter().enumerate() {
+            print!(
+                "{}\r\n",
+                theme::format(&format!("[$dim]  {}. {}[$/]", i + 1, question))
+            );
+        }
+        print!("{}\r\n", theme::format("[$dim]  /f <n> to ask[$/]"));
+    }
+
+    /// What conversation requests ask for besides the reply text.
+    fn reply_format(&self) -> ReplyFormat {
+        ReplyFormat {
+            logprobs: self.confidence,
+            constraint: self.constraint.clone(),
+        }
+    }
+
+    /// Prints the request body that sending `input` would produce.
+    pub fn preview(&self, input: &str) {
+        let body = self.tokio_rt.block_on(openai::preview_request(
+            input,
+            Arc::clone(&self.context),
+            &self.model,
+            self.sampling,
+            &self.reply_format(),
+        ));
+        match body.and_then(|b| serde_json::to_string_pretty(&b)) {
+            Ok(body) => print!("{}\r\n", body.replace('\n', "\r\n")),
+            Err(e) => eprint!("Failed to build the request: {}\r\n", e),
+        }
+    }
+
+    /// Masks secrets in an outgoing message. In a terminal the user can pick
+    /// findings that should be sent as is.
+    fn redact_secrets(input: &str) -> String {
+        let findings = redact::find_secrets(input);
+        if findings.is_empty() {
+            return input.to_owned();
+        }
+
+        let keep = match io::stdin().is_terminal() {
+            true => {
+                let previews: Vec<String> = findings.iter().map(|f| f.preview()).collect();
+                CLI::select(
+                    "Possible secrets found. Select any to send unredacted:",
+                    &previews,
+                    false,
+                    &[],
+                )
+            }
+            false => vec![],
+        };
+
+        let masked: Vec<&redact::Finding> = findings
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !keep.contains(i))
+            .map(|(_, f)| f)
+            .collect();
+        for finding in &masked {
+            print!("Redacted {}\r\n", finding.preview());
+        }
+        redact::redact(input, &masked)
+    }
+
+    /// Asks the model to carry on after a reply that hit the token limit and
+    /// appends the continuation to that reply, so the context holds a single
+    /// assistant message.
+    pub fn continue_response(&mut self) -> bool {
+        let shared_context = &self.context;
+        let previous = self.tokio_rt.block_on(async {
+            let locked = shared_context.lock().await;
+            locked
+                .last()
+                .filter(|m| m.role == "assistant")
+                .map(|m| m.content.clone())
+        });
+        let Some(previous) = previous else {
+            print!("There is no reply to continue.\r\n");
+            return false;
+        };
+
+        let open_block = response::open_code_block(&previous);
+        if open_block.is_some() && self.code_blocks.last().is_some_and(|b| b.truncated) {
+            // The continuation completes the partial block.
+            self.code_blocks.pop();
+        }
+        let Some((model, continuation)) = self.stream_reply(CONTINUE_PROMPT, open_block) else {
+            self.merge_continuation();
+            return false;
+        };
+        self.merge_continuation();
+
+        if let Err(e) = self
+            .session_history
+            .save_message("assistant", &continuation, Some(&model))
+        {
+            eprint!("Failed to save transcript: {}\r\n", e);
+        }
+        true
+    }
+
+    /// Folds a continuation request and its (possibly partial) reply at the
+    /// end of the context into the reply before them. Returns the text that
+    /// was appended.
+    fn merge_continuation(&self) -> String {
+        self.tokio_rt.block_on(async {
+            let mut locked = self.context.lock().await;
+            let n = locked.len();
+            let continuation = match n {
+                _ if n >= 3
+                    && locked[n - 1].role == "assistant"
+                    && locked[n - 2].content == CONTINUE_PROMPT =>
+                {
+                    let continuation = locked.pop().unwrap().content;
+                    locked.pop();
+                    continuation
+                }
+                _ if n >= 2 && locked[n - 1].content == CONTINUE_PROMPT => {
+                    locked.pop();
+                    String::new()
+                }
+                _ => return String::new(),
+            };
+            if let Some(last) = locked.last_mut().filter(|m| m.role == "assistant") {
+                last.content.push_str(&continuation);
+            }
+            continuation
+        })
+    }
+
+    /// Picks a reply up again after the connection dropped mid-stream: the
+    /// model is asked to continue from `partial` until it finishes or
+    /// `MAX_RESUMES` attempts have failed.
+    fn resume_stream(&mut self, model: String, mut partial: String) -> Option<(String, String)> {
+        self.resuming = true;
+        let mut resumed = None;
+        for attempt in 1..=MAX_RESUMES {
+            print!(
+                "\r\n{}\r\n",
+                theme::format(&format!(
+                    "[$dim](connection lost, resuming, attempt {} of {})[$/]",
+                    attempt, MAX_RESUMES
+                ))
+            );
+            std::thread::sleep(std::time::Duration::from_secs(attempt as u64));
+
+            let open_block = response::open_code_block(&partial);
+            if open_block.is_some() && self.code_blocks.last().is_some_and(|b| b.truncated) {
+                self.code_blocks.pop();
+            }
+            let reply = self.stream_reply(CONTINUE_PROMPT, open_block);
+            partial.push_str(&self.merge_continuation());
+            if reply.is_some() {
+                resumed = Some((model, partial));
+                break;
+            }
+        }
+        self.resuming = false;
+        resumed
+    }
+
+    /// Asks for a new reply to the last message. The old reply is dropped
+    /// from the context and kept in `previous_reply`.
+    pub fn retry_response(&mut self) -> bool {
+        let shared_context = &self.context;
+        let exchange = self.tokio_rt.block_on(async {
+            let mut locked = shared_context.lock().await;
+            let n = locked.len();
+            if n < 2 || locked[n - 1].role != "assistant" || locked[n - 2].role != "user" {
+                return None;
+            }
+            let reply = locked.pop().unwrap();
+            let input = locked.pop().unwrap();
+            Some((input, reply))
+        });
+        let Some((input, reply)) = exchange else {
+            print!("There is no reply to retry.\r\n");
+            return false;
+        };
+
+        match self.stream_reply(&input.content, None) {
+            Some((model, new_reply)) => {
+                self.previous_reply = Some(reply.content);
+                let saved = self.session_history.save_replacement(&new_reply, &model);
+                if let Err(e) = saved {
+                    eprint!("Failed to save transcript: {}\r\n", e);
+                }
+                true
+            }
+            None => {
+                // Put the old exchange back in place of the failed one.
+                let shared_context = &self.context;
+                self.tokio_rt.block_on(async {
+                    let mut locked = shared_context.lock().await;
+                    if locked.last().is_some_and(|m| m.role == "user") {
+                        locked.pop();
+                    }
+                    locked.push(input);
+                    locked.push(reply);
+                });
+                false
+            }
+        }
+    }
+
+    /// Prints messages the way they looked when they were sent: user
+    /// prompts after the usual prompt, replies through the markdown renderer.
+    pub fn render_messages(&self, messages: &[Message]) {
+        let mut first_block = 1;
+        for message in messages {
+            match message.role.as_str() {
+                "user" => print!(
+                    "{}{}\r\n",
+                    theme::format(&format!("[$green]{} [$/]> ", whoami::realname())),
+                    message.content.replace('\n', "\r\n")
+                ),
+                "assistant" => {
+                    if let Some(persona) = &message.metadata.persona {
+                        match self.personas.get(persona) {
+                            Some(p) => print!("{}\r\n", Self::persona_label(persona, p)),
+                            None => print!("{}:\r\n", persona),
+                        }
+                    } else if let Some(model) = &message.metadata.model {
+                        print!("{}\r\n", theme::format(&format!("[$cyan]({})[$/]", model)));
+                    }
+                    let mut renderer = TerminalRenderer::new(!self.markdown);
+                    if self.line_numbers {
+                        renderer.show_line_numbers(first_block);
+                    }
+                    renderer.token(&message.content);
+                    renderer.finish();
+                    first_block += response::count_code_blocks(&message.content);
+                    print!("\r\n\r\n");
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Renders the last exchanges of the restored history, as many as
+    /// `CHAD_LLM_HISTORY_EXCHANGES` says.
+    pub fn show_restored_history(&self) {
+        let exchanges = std::env::var("CHAD_LLM_HISTORY_EXCHANGES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_HISTORY_EXCHANGES);
+        let start = match exchanges {
+            0 => self.restored_history.len(),
+            n => self
+                .restored_history
+                .iter()
+                .enumerate()
+                .rev()
+                .filter(|(_, m)| m.role == "user")
+                .nth(n - 1)
+                .map_or(0, |(i, _)| i),
+        };
+
+        if start > 0 {
+            print!(
+                "{}\r\n",
+                theme::format(&format!(
+                    "[$dim]… {} older messages, /replay to see all[$/]",
+                    start
+                ))
+            );
+        }
+        self.render_messages(&self.restored_history[start..]);
+    }
+
+    /// Sends a one-off request that neither sees nor changes the
+    /// conversation: the context holds only `system` (if any) and `input`.
+    /// The reply is printed and returned.
+    pub fn side_request(&mut self, system: Option<&str>, input: &str) -> Option<String> {
+        let model = self.model.clone();
+        self.side_request_with(system, &model, input)
+    }
+
+    /// Like `side_request`, but asks `model` first.
+    pub fn side_request_with(
+        &mut self,
+        system: Option<&str>,
+        model: &str,
+        input: &str,
+    ) -> Option<String> {
+        self.side_reply(system, model, input)
+            .map(|(_, reply)| reply)
+    }
+
+    /// Like `side_request_with`, but also returns the model that answered.
+    fn side_reply(
+        &mut self,
+        system: Option<&str>,
+        model: &str,
+        input: &str,
+    ) -> Option<(String, String)> {
+        let model = match &self.pinned_model {
+            Some(pin) => pin.model.as_str(),
+            None => model,
+        };
+        let context: openai::SharedContext = Arc::new(Mutex::new(
+            system
+                .map(|s| Message::new("system", s))
+                .into_iter()
+                .collect(),
+        ));
+        let (model, stream) = match self.tokio_rt.block_on(send_request(
+            input,
+            context,
+            model,
+            self.fallbacks(),
+            self.sampling,
+            &ReplyFormat::default(),
+        )) {
+            Ok(x) => x,
+            Err(err) => {
+                eprint!("{} {}\r\n", theme::error("Request failed:"), err);
+                return None;
+            }
+        };
+
+        let mut renderer = TerminalRenderer::new(!self.markdown);
+        let mut logger = RawLogger::new();
+        let response = self.tokio_rt.block_on(response::process_response(
+            Box::pin(stream),
+            &mut [&mut renderer, &mut logger],
+        ));
+        print!("\r\n");
+        response.ok().map(|_| (model, logger.content))
+    }
+
+    /// Sends `input`, renders the reply and returns it along with the model
+    /// that answered. `open_block` is the
+    /// language and content of a code block left open by a previous reply,
+    /// which the reply is expected to continue.
+    fn stream_reply(
+        &mut self,
+        input: &str,
+        open_block: Option<(String, String)>,
+    ) -> Option<(String, String)> {
+        let draft_model = self.draft_model.clone().filter(|m| {
+            *m != self.model
+                && open_block.is_none()
+                && !self.confidence
+                && io::stdout().is_terminal()
+        });
+        // Timed from here, so time to first token includes connecting and
+        // waiting in the provider's queue.
+        let mut tracker = UsageTracker::new();
+        let format = self.reply_format();
+        let (response_stream, draft) = self.tokio_rt.block_on(async {
+            let main = send_request(
+                input,
+                Arc::clone(&self.context),
+                &self.model,
+                self.fallbacks(),
+                self.sampling,
+                &format,
+            );
+            let Some(draft_model) = &draft_model else {
+                return (main.await, None);
+            };
+            // The draft answers from its own copy of the context, taken
+            // before the main request adds the prompt to it.
+            let draft_context = Arc::new(Mutex::new(self.context.lock().await.clone()));
+            let plain = ReplyFormat::default();
+            let draft = send_request(
+                input,
+                draft_context,
+                draft_model,
+                &[],
+                self.sampling,
+                &plain,
+            );
+            let (main, draft) = tokio::join!(main, draft);
+            (main, draft.ok())
+        });
+        let (model, stream) = match response_stream {
+            Ok(x) => x,
+            Err(err) if err.kind() == io::ErrorKind::NotConnected => {
+                // The message never reached the API, take it back out of the
+                // context so it isn't sent twice.
+                let shared_context = &self.context;
+                self.tokio_rt.block_on(async {
+                    shared_context.lock().await.pop();
+                });
+
+                self.offline = true;
+                if self.queue_offline && !self.resuming && io::stdin().is_terminal() {
+                    self.offline_queue.push(input.to_owned());
+                    print!(
+                        "You are offline. Message queued ({} pending), it will be sent when the connection returns.\r\n",
+                        self.offline_queue.len()
+                    );
+                } else {
+                    eprint!("You are offline: could not reach the API. Check your connection and try again.\r\n");
+                }
+                return None;
+            }
+            Err(err) => {
+                eprint!("{} {}\r\n", theme::error("Request failed:"), err);
+                return None;
+            }
+        };
+
+        if model != self.model {
+            let status = format!("(answered by {})", model);
+            match self.compact {
+                true => print!("{}\r\n", crate::cli::fit_to_terminal(&status)),
+                false => print!("{}\r\n", status),
+            }
+        }
+
+        let mut code_blocks = std::mem::take(&mut self.code_blocks);
+        let mut renderer = TerminalRenderer::new(!self.markdown);
+        if self.line_numbers {
+            renderer.show_line_numbers(code_blocks.len() + 1);
+        }
+        let mut logger = RawLogger::new();
+        let mut collector = CodeBlockCollector::new(&mut code_blocks);
+        let mut relay = self.share.as_ref().map_or_else(Relay::none, Share::relay);
+        let mut live = self
+            .server
+            .as_ref()
+            .map_or_else(serve::Live::none, serve::Server::live);
+        let mut recorder = self
+            .recording
+            .as_ref()
+            .map_or_else(Recorder::none, Recording::recorder);
+        let mut spool = Spool::new(
+            self.session_history.session_id(),
+            &model,
+            (input != CONTINUE_PROMPT).then_some(input),
+        );
+
+        let stream: std::pin::Pin<Box<dyn tokio_stream::Stream<Item = StreamEvent>>> =
+            match (draft_model.zip(draft), open_block) {
+                (Some((draft_model, (_, draft))), _) => {
+                    self.tokio_rt
+                        .block_on(draft::show_until(&draft_model, draft, stream, &model))
+                }
+                (None, Some((language, content))) => {
+                    renderer.resume_code_block(&language, &content);
+                    collector.resume_code_block(&language, &content);
+                    let mut filter = response::LeadingFenceFilter::new();
+                    Box::pin(
+                        stream.flat_map(move |event| {
+                            futures_util::stream::iter(filter.process(event))
+                        }),
+                    )
+                }
+                (None, None) => Box::pin(stream),
+            };
+
+        let mut heatmap = ConfidenceRenderer::new();
+        let display: &mut dyn Sink = match self.confidence {
+            true => &mut heatmap,
+            false => &mut renderer,
+        };
+        let response = self.tokio_rt.block_on(response::process_response(
+            stream,
+            &mut [
+                display,
+                &mut logger,
+                &mut collector,
+                &mut tracker,
+                &mut relay,
+                &mut live,
+                &mut recorder,
+                &mut spool,
+            ],
+        ));
+        if self.confidence {
+            self.last_logprobs = heatmap.tokens;
+        }
+
+        self.code_blocks = code_blocks;
+
+        if response.is_ok() {
+            if let Some(ttft) = tracker.first_token() {
+                let record = RequestStats {
+                    timestamp: stats::now(),
+                    provider: openai::provider_of(&model),
+                    model: model.clone(),
+                    ttft_ms: ttft.as_millis() as u64,
+                    total_ms: tracker.elapsed.as_millis() as u64,
+                    prompt_tokens: tracker.usage.map_or(0, |u| u.prompt_tokens),
+                    cached_tokens: tracker.usage.map_or(0, |u| u.cached_tokens),
+                    completion_tokens: tracker.usage.map_or_else(
+                        || RateLimiter::estimate_tokens(&logger.content) as u64,
+                        |u| u.completion_tokens,
+                    ),
+                };
+                if let Err(e) = stats::record(&record) {
+                    eprint!("Failed to save statistics: {}\r\n", e);
+                }
+            }
+        }
+
+        match response {
+            Ok(finish_reason) => {
+                self.truncated = finish_reason.as_deref() == Some("length");
+                if self.auto_copy && response::count_code_blocks(&logger.content) == 1 {
+                    self.auto_copy_block();
+                }
+                match finish_reason.as_deref() {
+                    Some("content_filter") => {
+                        print!("\r\n(reply stopped by the content filter)\r\n")
+                    }
+                    Some("length") => print!(
+                        "\r\n(reply cut off: token limit reached, use /continue to get the rest)\r\n"
+                    ),
+                    _ => {}
+                }
+                Some((model, logger.content))
+            }
+            Err(err)
+                if err.kind() == io::ErrorKind::ConnectionAborted
+                    && !logger.content.is_empty()
+                    && !self.resuming =>
+            {
+                self.resume_stream(model, logger.content)
+            }
+            Err(err) => {
+                eprint!("Failed to process response: {}\r\n", err);
+                None
+            }
+        }
+    }
 }
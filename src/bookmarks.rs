@@ -0,0 +1,100 @@
+//! Replies marked with `/bookmark`, kept across sessions and collected by
+//! `/bookmarks export` into a single markdown digest.
+use crate::application::get_data_path;
+use crate::crypto;
+use crate::stats;
+use crate::storage;
+
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+pub const BOOKMARKS_FILE: &str = "bookmarks.jsonl";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub timestamp: u64,
+    pub session: String,
+    pub model: Option<String>,
+    /// The prompt the reply answered, if it is still in the context.
+    pub question: Option<String>,
+    pub answer: String,
+}
+
+impl Bookmark {
+    /// A one line summary for listings.
+    pub fn title(&self) -> String {
+        let text = self.question.as_deref().unwrap_or(&self.answer);
+        let line = text.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+        match line.chars().count() > 60 {
+            true => format!("{}…", line.chars().take(60).collect::<String>()),
+            false => line.to_owned(),
+        }
+    }
+}
+
+pub fn add(bookmark: &Bookmark) -> io::Result<()> {
+    // Not to append to a file `remove` is replacing.
+    let _lock = storage::lock(BOOKMARKS_FILE)?;
+    let path = get_data_path(BOOKMARKS_FILE);
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", crypto::seal(&serde_json::to_string(bookmark)?))
+}
+
+pub fn load() -> io::Result<Vec<Bookmark>> {
+    let content = match std::fs::read_to_string(get_data_path(BOOKMARKS_FILE)) {
+        Ok(x) => x,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(&crypto::open(line)?)?))
+        .collect()
+}
+
+/// Removes `bookmark`, keeping those added by other instances meanwhile.
+/// Returns whether it was still there.
+pub fn remove(bookmark: &Bookmark) -> io::Result<bool> {
+    let _lock = storage::lock(BOOKMARKS_FILE)?;
+    let mut bookmarks = load()?;
+    let Some(i) = bookmarks.iter().position(|b| b == bookmark) else {
+        return Ok(false);
+    };
+    bookmarks.remove(i);
+    let mut content = String::new();
+    for bookmark in &bookmarks {
+        content.push_str(&crypto::seal(&serde_json::to_string(bookmark)?));
+        content.push('\n');
+    }
+    storage::replace(BOOKMARKS_FILE, &content)?;
+    Ok(true)
+}
+
+/// All bookmarks as one markdown document, oldest first.
+pub fn markdown(bookmarks: &[Bookmark]) -> String {
+    let mut out = String::from("# Bookmarks\n");
+    for bookmark in bookmarks {
+        out.push_str(&format!(
+            "\n## {}\n\n_{} {}",
+            bookmark.title(),
+            stats::date(bookmark.timestamp),
+            stats::clock(bookmark.timestamp)
+        ));
+        if let Some(model) = &bookmark.model {
+            out.push_str(&format!(", {}", model));
+        }
+        out.push_str("_\n\n");
+        if let Some(question) = &bookmark.question {
+            for line in question.trim().lines() {
+                out.push_str(format!("> {}", line).trim_end());
+                out.push('\n');
+            }
+            out.push('\n');
+        }
+        out.push_str(bookmark.answer.trim());
+        out.push('\n');
+    }
+    out
+}
@@ -1,37 +1,79 @@
+use crate::history::HistControl;
+use crate::theme;
+
 use fuzzy_matcher::clangd::fuzzy_match;
 use std::ascii::AsciiExt;
-use std::collections::VecDeque;
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 use std::{
     env::{self, VarError},
     io::{self, IsTerminal, Write},
 };
 
+use clipboard::{ClipboardContext, ClipboardProvider};
 use crossterm::cursor::MoveUp;
 use crossterm::{
     cursor,
     event::KeyModifiers,
-    event::{self, Event, KeyCode},
+    event::{self, Event, KeyCode, MouseButton, MouseEventKind},
     execute,
     terminal::{self, ClearType},
 };
 use rand::{self, Rng};
 
+/// Prefix of the temporary files `$EDITOR` is run on, in the temp directory.
+pub const EDITOR_TEMP_PREFIX: &str = ".llm_tmp_";
+
 pub trait History<T> {
     fn read(&self, pos: usize) -> Option<String>;
     fn write(&mut self, val: &T);
+
+    /// The entry to offer as ghost text for `input`: the most recent entry
+    /// extending it, or failing that the best fuzzy match.
+    fn suggest(&self, input: &str) -> Option<String> {
+        if input.trim().is_empty() {
+            return None;
+        }
+
+        let mut best: Option<(i64, String)> = None;
+        let mut pos = 0;
+        while let Some(entry) = self.read(pos) {
+            pos += 1;
+            if entry.len() > input.len() && entry.starts_with(input) {
+                return Some(entry);
+            }
+            if let Some(score) = fuzzy_match(&entry, input) {
+                if entry != input && best.as_ref().is_none_or(|(s, _)| score > *s) {
+                    best = Some((score, entry));
+                }
+            }
+        }
+        best.map(|(_, entry)| entry)
+    }
 }
 
 pub struct BasicHistory {
     deque: VecDeque<String>,
+    control: HistControl,
 }
 
 impl BasicHistory {
     pub fn new() -> Self {
+        Self::with_control(HistControl::from_env())
+    }
+
+    pub fn with_control(control: HistControl) -> Self {
         Self {
             deque: VecDeque::new(),
+            control,
         }
     }
+
+    pub fn clear(&mut self) {
+        self.deque.clear();
+    }
 }
 
 impl<T: ToString> History<T> for BasicHistory {
@@ -41,18 +83,39 @@ impl<T: ToString> History<T> for BasicHistory {
 
     fn write(&mut self, val: &T) {
         let val = val.to_string();
+        if !self
+            .control
+            .accepts(self.deque.front().map(|s| s.as_str()), &val)
+        {
+            return;
+        }
         self.deque.push_front(val);
+        self.deque.truncate(self.control.max_entries);
     }
 }
 
 pub struct ReadLine<'a, T> {
     prompt: String,
+    /// Shown at the right edge of the line while the input leaves room.
+    rprompt: String,
+    /// Text the line starts with, editable like typed input.
+    initial: String,
     history: Option<&'a mut dyn History<T>>,
     completion: Option<&'a dyn Completion>,
+    abbreviations: Option<&'a HashMap<String, String>>,
+    /// Gives up the line when this returns true while it is empty.
+    interrupt: Option<&'a dyn Fn() -> bool>,
+    /// Set when Ctrl-D gives up an empty line, see `eof`.
+    eof: Option<&'a Cell<bool>>,
 }
 
 pub trait Completion {
     fn get(&self, input: &str) -> Option<String>;
+
+    /// Lets the user pick a whole input line, e.g. from a command palette.
+    fn palette(&self) -> Option<String> {
+        None
+    }
 }
 
 impl<'a, T> ReadLine<'a, T>
@@ -62,13 +125,28 @@ where
     pub fn new() -> Self {
         Self {
             prompt: String::new(),
+            rprompt: String::new(),
+            initial: String::new(),
             history: None,
             completion: None,
+            abbreviations: None,
+            interrupt: None,
+            eof: None,
         }
     }
 
     pub fn prompt<A: ToString>(mut self, prompt: A) -> Self {
-        self.prompt = vari::format(&prompt.to_string());
+        self.prompt = theme::format(&prompt.to_string());
+        self
+    }
+
+    pub fn rprompt<A: ToString>(mut self, rprompt: A) -> Self {
+        self.rprompt = theme::format(&rprompt.to_string());
+        self
+    }
+
+    pub fn initial<A: ToString>(mut self, initial: A) -> Self {
+        self.initial = initial.to_string();
         self
     }
 
@@ -85,6 +163,78 @@ where
         self
     }
 
+    pub fn abbreviations(mut self, abbreviations: &'a HashMap<String, String>) -> Self {
+        self.abbreviations = Some(abbreviations);
+        self
+    }
+
+    pub fn interrupt(mut self, interrupt: &'a dyn Fn() -> bool) -> Self {
+        self.interrupt = Some(interrupt);
+        self
+    }
+
+    /// Makes the line behave as in a shell: Ctrl-D on an empty line gives it
+    /// up and sets `eof`, and Ctrl-C only clears it.
+    pub fn eof(mut self, eof: &'a Cell<bool>) -> Self {
+        self.eof = Some(eof);
+        self
+    }
+
+    /// Expands the abbreviation ending at `cur_pos`, if there is one, and
+    /// returns the new cursor position.
+    fn expand_abbreviation(&self, read_so_far: &mut String, cur_pos: usize) -> usize {
+        let Some(abbreviations) = self.abbreviations else {
+            return cur_pos;
+        };
+        let start = read_so_far[..cur_pos]
+            .rfind(char::is_whitespace)
+            .map_or(0, |i| i + 1);
+        match abbreviations.get(&read_so_far[start..cur_pos]) {
+            Some(expansion) => {
+                read_so_far.replace_range(start..cur_pos, expansion);
+                start + expansion.len()
+            }
+            None => cur_pos,
+        }
+    }
+
+    /// Draws the right prompt after `used` columns of prompt and input, or
+    /// clears it when they would run into each other, then puts the cursor
+    /// back at `cursor`.
+    fn draw_rprompt(&self, used: usize, cursor: usize) {
+        if self.rprompt.is_empty() {
+            return;
+        }
+        let width = terminal::size().map_or(80, |(w, _)| w as usize);
+        let len = strip_ansi_escapes::strip(self.rprompt.clone()).len();
+        match used + 1 + len <= width {
+            true => {
+                execute!(io::stdout(), cursor::MoveToColumn((width - len) as u16)).unwrap();
+                print!("{}", self.rprompt);
+            }
+            false => {
+                execute!(
+                    io::stdout(),
+                    cursor::MoveToColumn(used as u16),
+                    terminal::Clear(ClearType::UntilNewLine)
+                )
+                .unwrap();
+            }
+        }
+        execute!(io::stdout(), cursor::MoveToColumn(cursor as u16)).unwrap();
+    }
+
+    fn open_palette(&self) -> Option<String> {
+        let completion = self.completion?;
+        print!("\r\n");
+        let line = completion.palette();
+        terminal::enable_raw_mode().expect("Failed to set terminal to raw mode.");
+        if let Some(line) = &line {
+            print!("\r{}{}\r\n", self.prompt, line);
+        }
+        line
+    }
+
     pub fn run(&mut self) -> Option<T>
     where
         <T as std::str::FromStr>::Err: std::fmt::Debug,
@@ -93,12 +243,15 @@ where
 
         let mut last_time = Instant::now();
         let mut typed_chars = 0;
-        let mut read_so_far = String::new();
+        let mut read_so_far = self.initial.clone();
         let mut in_paste = false;
-        let mut cur_pos: usize = 0;
+        let mut cur_pos: usize = read_so_far.len();
         let mut hist_pos: isize = -1;
+        let mut ghost: Option<String> = None;
 
-        print!("{}", self.prompt);
+        print!("{}{}", self.prompt, read_so_far);
+        let prompt_len = strip_ansi_escapes::strip(self.prompt.clone()).len();
+        self.draw_rprompt(prompt_len + cur_pos, prompt_len + cur_pos);
         io::stdout().flush().unwrap();
 
         loop {
@@ -115,7 +268,28 @@ where
                             if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
                         {
                             write!(std::io::stdout(), "^C\r\n").unwrap();
-                            return None;
+                            if self.eof.is_some() {
+                                read_so_far.clear();
+                                cur_pos = 0;
+                                hist_pos = -1;
+                                ghost = None;
+                                print!("{}", self.prompt);
+                            } else {
+                                terminal::disable_raw_mode()
+                                    .expect("Failed to remove terminal to raw mode.");
+                                return None;
+                            }
+                        }
+                        KeyCode::Char('d')
+                            if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+                        {
+                            if let Some(eof) = self.eof.filter(|_| read_so_far.is_empty()) {
+                                eof.set(true);
+                                print!("\r\n");
+                                terminal::disable_raw_mode()
+                                    .expect("Failed to remove terminal to raw mode.");
+                                return None;
+                            }
                         }
                         KeyCode::Char('w') | KeyCode::Backspace
                             if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
@@ -162,6 +336,26 @@ where
                             CLI::clear();
                             write!(std::io::stdout(), "\r{}{}", self.prompt, read_so_far).unwrap();
                         }
+                        KeyCode::Char('p')
+                            if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+                        {
+                            if let Some(line) = self.open_palette() {
+                                read_so_far = line;
+                                break;
+                            }
+                            print!("\r{}{}", self.prompt, read_so_far);
+                        }
+                        KeyCode::Char('e')
+                            if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+                        {
+                            if let Some(line) = ghost.take() {
+                                read_so_far = line;
+                            }
+                            cur_pos = read_so_far.len();
+                            execute!(io::stdout(), terminal::Clear(ClearType::CurrentLine))
+                                .unwrap();
+                            print!("\r{}{}", self.prompt, read_so_far);
+                        }
                         KeyCode::Char(c) => {
                             if typed_chars > 5 && elapsed < 10 {
                                 in_paste = true;
@@ -169,6 +363,9 @@ where
                             last_time = now;
                             typed_chars += 1;
 
+                            if c == ' ' && !in_paste {
+                                cur_pos = self.expand_abbreviation(&mut read_so_far, cur_pos);
+                            }
                             read_so_far.insert(cur_pos, c);
                             cur_pos += 1;
 
@@ -183,6 +380,13 @@ where
                             )
                             .unwrap();
                         }
+                        KeyCode::Tab if read_so_far.is_empty() || read_so_far == "/" => {
+                            if let Some(line) = self.open_palette() {
+                                read_so_far = line;
+                                break;
+                            }
+                            print!("\r{}{}", self.prompt, read_so_far);
+                        }
                         KeyCode::Tab => {
                             if let Some(completion) = self.completion {
                                 let so_far: String = read_so_far.chars().take(cur_pos).collect();
@@ -203,6 +407,13 @@ where
                                 execute!(io::stdout(), cursor::MoveLeft(1)).unwrap();
                             }
                         }
+                        KeyCode::Right if cur_pos == read_so_far.len() && ghost.is_some() => {
+                            read_so_far = ghost.take().unwrap();
+                            cur_pos = read_so_far.len();
+                            execute!(io::stdout(), terminal::Clear(ClearType::CurrentLine))
+                                .unwrap();
+                            print!("\r{}{}", self.prompt, read_so_far);
+                        }
                         KeyCode::Right if !key_event.modifiers.contains(KeyModifiers::CONTROL) => {
                             if cur_pos < read_so_far.len() {
                                 cur_pos += 1;
@@ -308,6 +519,10 @@ where
                             }
                         }
                         KeyCode::Enter => {
+                            if !in_paste && cur_pos == read_so_far.len() {
+                                cur_pos = self.expand_abbreviation(&mut read_so_far, cur_pos);
+                                print!("\r{}{}", self.prompt, read_so_far);
+                            }
                             print!("\r\n");
                             io::stdout().flush().unwrap();
 
@@ -349,8 +564,44 @@ where
                         }
                         _ => {}
                     }
+
+                    // Show the history suggestion dimmed after the cursor.
+                    let mut ghost_len = 0;
+                    if let Some(hist) = &self.history {
+                        execute!(io::stdout(), terminal::Clear(ClearType::UntilNewLine)).unwrap();
+                        ghost = match cur_pos == read_so_far.len() && !in_paste {
+                            true => hist.suggest(&read_so_far),
+                            false => None,
+                        };
+                        if let Some(line) = &ghost {
+                            ghost_len = match line.strip_prefix(read_so_far.as_str()) {
+                                Some(rest) => {
+                                    print!("\x1b[2m{}\x1b[0m", rest);
+                                    rest.len()
+                                }
+                                None => {
+                                    print!("\x1b[2m  [{}]\x1b[0m", line);
+                                    line.len() + 4
+                                }
+                            };
+                            execute!(
+                                io::stdout(),
+                                cursor::MoveToColumn((prompt_len + cur_pos) as u16)
+                            )
+                            .unwrap();
+                        }
+                    }
+                    self.draw_rprompt(
+                        prompt_len + read_so_far.len() + ghost_len,
+                        prompt_len + cur_pos,
+                    );
                     io::stdout().flush().unwrap();
                 }
+            } else if read_so_far.is_empty() && self.interrupt.is_some_and(|interrupt| interrupt())
+            {
+                print!("\r\n");
+                terminal::disable_raw_mode().expect("Failed to remove terminal to raw mode.");
+                return None;
             }
         }
         io::stdout().flush().unwrap();
@@ -369,6 +620,52 @@ where
 
 pub struct CLI;
 
+pub const EDITOR_COMMENT: &str = "#:";
+
+/// Whether `CLI::select` captures the mouse for clicking and scrolling.
+pub static MOUSE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Terminals commonly drop OSC 52 sequences longer than this.
+const OSC52_MAX_BYTES: usize = 100_000;
+/// screen passes DCS strings through in pieces of at most this size.
+const SCREEN_DCS_CHUNK: usize = 76;
+
+/// Terminals narrower than this get the compact rendering profile.
+pub const COMPACT_WIDTH: u16 = 60;
+
+/// Whether the terminal is too narrow for the full layout.
+pub fn narrow_terminal() -> bool {
+    terminal::size().is_ok_and(|(width, _)| width < COMPACT_WIDTH)
+}
+
+/// Saves the terminal title on the terminal's title stack, for
+/// `restore_title`.
+pub fn save_title() {
+    print!("\x1b[22;0t");
+    io::stdout().flush().unwrap();
+}
+
+/// Sets the window/tab title (OSC 2).
+pub fn set_title(title: &str) {
+    let title: String = title.chars().filter(|c| !c.is_control()).collect();
+    print!("\x1b]2;{}\x07", title);
+    io::stdout().flush().unwrap();
+}
+
+/// Puts back the title saved by `save_title`.
+pub fn restore_title() {
+    print!("\x1b[23;0t");
+    io::stdout().flush().unwrap();
+}
+
+/// `s` cut to fit on one terminal line.
+pub fn fit_to_terminal(s: &str) -> String {
+    match terminal::size() {
+        Ok((width, _)) => truncate_string(s, (width as usize).max(4)),
+        Err(_) => s.to_owned(),
+    }
+}
+
 fn truncate_string(s: &str, max_len: usize) -> String {
     if s.chars().count() > max_len {
         s.chars().take(max_len - 3).collect::<String>() + "..."
@@ -384,7 +681,73 @@ impl CLI {
     }
 
     pub fn clear() {
-        print!("\x1B[2J\x1B[H");
+        execute!(
+            io::stdout(),
+            terminal::Clear(ClearType::All),
+            cursor::MoveTo(0, 0)
+        )
+        .unwrap();
+    }
+
+    /// Copies to the local clipboard, or through the terminal with OSC 52
+    /// when there is none, as over SSH.
+    pub fn copy_to_clipboard(contents: String) -> Result<(), Box<dyn std::error::Error>> {
+        let remote = env::var_os("SSH_CONNECTION").is_some()
+            && env::var_os("DISPLAY").is_none()
+            && env::var_os("WAYLAND_DISPLAY").is_none();
+        if remote {
+            return Self::osc52_copy(&contents);
+        }
+        let copied = ClipboardProvider::new()
+            .and_then(|mut clipboard: ClipboardContext| clipboard.set_contents(contents.clone()));
+        match copied {
+            Ok(()) => Ok(()),
+            Err(_) => Self::osc52_copy(&contents),
+        }
+    }
+
+    /// Asks the terminal to set the clipboard. Inside tmux the sequence is
+    /// passed through, inside screen it is sent in chunks small enough for
+    /// its DCS buffer.
+    fn osc52_copy(contents: &str) -> Result<(), Box<dyn std::error::Error>> {
+        use base64::Engine;
+        let encoded = base64::engine::general_purpose::STANDARD.encode(contents);
+        if encoded.len() > OSC52_MAX_BYTES {
+            return Err(format!(
+                "no clipboard available and {} bytes is too much to copy through the terminal",
+                contents.len()
+            )
+            .into());
+        }
+
+        let sequence = format!("\x1b]52;c;{}\x07", encoded);
+        let sequence = if env::var_os("TMUX").is_some() {
+            format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+        } else if env::var("TERM").is_ok_and(|t| t.starts_with("screen")) {
+            sequence
+                .as_bytes()
+                .chunks(SCREEN_DCS_CHUNK)
+                .map(|chunk| format!("\x1bP{}\x1b\\", String::from_utf8_lossy(chunk)))
+                .collect()
+        } else {
+            sequence
+        };
+
+        // Write to the terminal itself so it works with stdout redirected.
+        match std::fs::OpenOptions::new().write(true).open("/dev/tty") {
+            Ok(mut tty) => tty.write_all(sequence.as_bytes())?,
+            Err(_) => {
+                let mut stdout = io::stdout();
+                stdout.write_all(sequence.as_bytes())?;
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_clipboard() -> Result<String, Box<dyn std::error::Error>> {
+        let mut clipboard: ClipboardContext = ClipboardProvider::new()?;
+        clipboard.get_contents()
     }
 
     fn get_editor() -> Result<String, VarError> {
@@ -400,17 +763,21 @@ impl CLI {
             Err(error) => return Err(error),
         }
 
-        Ok("vi".to_string())
+        if cfg!(windows) {
+            Ok("notepad".to_string())
+        } else {
+            Ok("vi".to_string())
+        }
     }
 
-    pub fn editor(original: &str) -> Option<String> {
+    fn run_editor(original: &str) -> Option<(bool, String)> {
         let mut fp = tempfile::env::temp_dir();
         let s: String = rand::rng()
             .sample_iter(&rand::distr::Alphanumeric)
             .take(7)
             .map(char::from)
             .collect();
-        fp.push(format!(".llm_tmp_{}", s));
+        fp.push(format!("{}{}", EDITOR_TEMP_PREFIX, s));
         match std::fs::write(&fp, original) {
             Ok(()) => {}
             Err(_) => return None,
@@ -429,25 +796,57 @@ impl CLI {
             .stderr(std::process::Stdio::inherit())
             .status()
             .unwrap();
-
-        if !status.success() {
-            return None;
-        }
         let _ = terminal::enable_raw_mode();
 
-        let new = match std::fs::read(fp) {
+        let new = std::fs::read(&fp);
+        let _ = std::fs::remove_file(&fp);
+        let new = match new {
             Ok(s) => s,
             Err(_) => return None,
         };
         let new = String::from_utf8(new).unwrap();
 
-        if new == original {
+        Some((status.success(), new))
+    }
+
+    pub fn editor(original: &str) -> Option<String> {
+        let (success, new) = Self::run_editor(original)?;
+
+        if !success || new == original {
             None
         } else {
             Some(new)
         }
     }
 
+    /// Opens the editor with `header` prepended as comment lines, which are
+    /// stripped from the result. Returns `Err` with whatever text was left in
+    /// the buffer when the user aborts, so it can be offered again later.
+    pub fn editor_with_header(header: &str, original: &str) -> Result<String, String> {
+        let mut contents = String::new();
+        for line in header.lines() {
+            contents.push_str(&format!("{} {}\n", EDITOR_COMMENT, line));
+        }
+        contents.push_str(original);
+
+        let (success, new) = match Self::run_editor(&contents) {
+            Some(x) => x,
+            None => return Err(original.to_owned()),
+        };
+
+        let new = new
+            .lines()
+            .filter(|line| !line.starts_with(EDITOR_COMMENT))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if !success || new.trim().is_empty() {
+            Err(new)
+        } else {
+            Ok(new)
+        }
+    }
+
     pub fn select<T: ToString + std::fmt::Debug>(
         prompt: &str,
         options: &[T],
@@ -460,6 +859,10 @@ impl CLI {
         let mut current_pos = selected.first().copied().unwrap_or(0);
         let mut query = String::new();
         let visible_count = 10.min(options.len());
+        let mouse = MOUSE_ENABLED.load(Ordering::Relaxed);
+        if mouse {
+            execute!(io::stdout(), event::EnableMouseCapture).unwrap();
+        }
         write!(std::io::stdout(), "{}\r", prompt).unwrap();
 
         for _ in 0..=visible_count {
@@ -553,6 +956,7 @@ impl CLI {
                 offset = current_pos.saturating_sub(visible_count - 1);
             }
 
+            let top = cursor::position().map_or(0, |(_, row)| row);
             draw(
                 &mut stdout,
                 &filtered_options,
@@ -564,7 +968,44 @@ impl CLI {
             );
 
             if event::poll(Duration::from_millis(500)).unwrap() {
-                if let Event::Key(key_event) = event::read().unwrap() {
+                let event = event::read().unwrap();
+                if let Event::Mouse(mouse_event) = event {
+                    match mouse_event.kind {
+                        MouseEventKind::ScrollUp if current_pos > 0 => {
+                            current_pos -= 1;
+                            if current_pos < offset {
+                                offset = current_pos;
+                            }
+                        }
+                        MouseEventKind::ScrollDown
+                            if current_pos < filtered_options.len().saturating_sub(1) =>
+                        {
+                            current_pos += 1;
+                            if current_pos >= offset + visible_count {
+                                offset = current_pos - visible_count + 1;
+                            }
+                        }
+                        MouseEventKind::Down(MouseButton::Left) if mouse_event.row >= top => {
+                            let clicked = offset + (mouse_event.row - top) as usize;
+                            if clicked < (offset + visible_count).min(filtered_options.len()) {
+                                current_pos = clicked;
+                                let orig_idx = filtered_options[clicked].0;
+                                if single {
+                                    // A click picks the option right away.
+                                    selected_indices.clear();
+                                    selected_indices.push(orig_idx);
+                                    break;
+                                } else if selected_indices.contains(&orig_idx) {
+                                    selected_indices.retain(|&x| x != orig_idx);
+                                } else {
+                                    selected_indices.push(orig_idx);
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                if let Event::Key(key_event) = event {
                     match key_event.code {
                         KeyCode::Up => {
                             if current_pos > 0 {
@@ -650,9 +1091,94 @@ impl CLI {
         }
         stdout.flush().unwrap();
 
+        if mouse {
+            execute!(io::stdout(), event::DisableMouseCapture).unwrap();
+        }
         terminal::disable_raw_mode().expect("Failed to remove terminal to raw mode.");
 
         selected_indices.sort_unstable();
         selected_indices
     }
+
+    /// Shows `text` in `$PAGER` (`less` by default), or prints it when there
+    /// is no pager to run.
+    pub fn page(text: &str) {
+        let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_owned());
+        let mut words = pager.split_whitespace();
+        let _ = terminal::disable_raw_mode();
+        let child = words.next().and_then(|program| {
+            std::process::Command::new(program)
+                .args(words)
+                .stdin(std::process::Stdio::piped())
+                .spawn()
+                .ok()
+        });
+        match child {
+            Some(mut child) => {
+                if let Some(mut stdin) = child.stdin.take() {
+                    // Fails when the pager is quit before reading it all.
+                    let _ = stdin.write_all(text.as_bytes());
+                }
+                let _ = child.wait();
+            }
+            None => print!("{}\r\n", text.replace('\n', "\r\n")),
+        }
+    }
+
+    /// Shows `prompt` and waits for one of `keys` to be pressed. Esc and
+    /// Ctrl-C give `None`.
+    pub fn read_key(prompt: &str, keys: &str) -> Option<char> {
+        terminal::enable_raw_mode().expect("Failed to set terminal to raw mode.");
+        print!("{} ", prompt);
+        io::stdout().flush().unwrap();
+        let key = loop {
+            let Ok(Event::Key(key_event)) = event::read() else {
+                continue;
+            };
+            if key_event.kind != event::KeyEventKind::Press {
+                continue;
+            }
+            match key_event.code {
+                KeyCode::Esc => break None,
+                KeyCode::Char('c') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                    break None
+                }
+                KeyCode::Char(ch) if keys.contains(ch.to_ascii_lowercase()) => {
+                    break Some(ch.to_ascii_lowercase())
+                }
+                _ => {}
+            }
+        };
+        print!("{}\r\n", key.map(String::from).unwrap_or_default());
+        terminal::disable_raw_mode().expect("Failed to remove terminal to raw mode.");
+        key
+    }
+}
+
+/// Reads all of piped stdin, or any other reader, with Windows' CRLF line
+/// endings turned into LF.
+pub fn read_piped(mut reader: impl io::Read) -> io::Result<String> {
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+    Ok(input.replace("\r\n", "\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_piped;
+
+    #[test]
+    fn piped_crlf_input_becomes_lf() {
+        let input = "first line\r\nsecond line\r\n\r\n```rust\r\nfn main() {}\r\n```\r\n";
+        assert_eq!(
+            read_piped(input.as_bytes()).unwrap(),
+            "first line\nsecond line\n\n```rust\nfn main() {}\n```\n"
+        );
+    }
+
+    #[test]
+    fn piped_lf_and_lone_cr_are_kept() {
+        let input = "unix\nprogress 50%\rprogress 100%\n";
+        assert_eq!(read_piped(input.as_bytes()).unwrap(), input);
+    }
 }
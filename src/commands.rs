@@ -1,14 +1,39 @@
-use crate::application::{Application, HISTORY_FILE};
-use crate::cli::{Completion, CLI};
+use crate::application::{get_data_path, Application, DEFAULT_DRAFT_MODEL, SESSION_TEMPLATES_DIR};
+use crate::bookmarks::{self, Bookmark};
+use crate::cli::{self, Completion, CLI};
+use crate::digest;
+use crate::export;
+use crate::git;
+use crate::import;
+use crate::instructions;
+use crate::language;
+use crate::lint;
+use crate::models::{CodeBlock, Constraint, Message, Sampling, TokenLogprob};
+use crate::notes;
 use crate::openai;
+use crate::pane;
+use crate::purge;
+use crate::rate_limit::RateLimiter;
+use crate::recording::Recording;
+use crate::redact;
+use crate::response::{self, ConfidenceRenderer};
+use crate::runner;
+use crate::scaffold;
+use crate::share;
+use crate::shell_hook;
+use crate::staging::StagedPart;
+use crate::stats;
+use crate::theme;
 
-use clipboard::{ClipboardContext, ClipboardProvider};
 use fuzzy_matcher::clangd::fuzzy_match;
+use regex::Regex;
 
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::fs::remove_file;
+use std::io::{self, IsTerminal};
 use std::rc::Rc;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
 fn get_input_or_select<'a>(
     args: &[&str],
@@ -40,6 +65,25 @@ impl Completion for CommandRegistry {
             .max_by_key(|&(_, score)| score)
             .map(|(cmd, _)| format!("/{}", cmd))
     }
+
+    fn palette(&self) -> Option<String> {
+        let mut names = self.get_available_commands();
+        names.sort_unstable();
+        let width = names.iter().map(|n| n.len()).max().unwrap_or(0);
+        let entries: Vec<String> = names
+            .iter()
+            .map(|name| {
+                format!(
+                    "{:width$}  {}",
+                    name,
+                    self.get_description(name).unwrap_or_default()
+                )
+            })
+            .collect();
+
+        let v = CLI::select("Run a command:", &entries, true, &[]);
+        v.first().map(|&i| format!("/{}", names[i]))
+    }
 }
 
 #[derive(Debug)]
@@ -48,6 +92,8 @@ pub enum CommandError {
     InvalidModel,
     UpdateFailed,
     InvalidSystemPrompt,
+    InvalidArguments,
+    ReadFailed,
     Aborted,
 }
 
@@ -60,8 +106,13 @@ pub trait Command {
     ) -> Result<(), CommandError>;
 }
 
+struct RegisteredCommand {
+    description: &'static str,
+    command: Box<dyn Command>,
+}
+
 pub struct CommandRegistry {
-    commands: HashMap<&'static str, Box<dyn Command>>,
+    commands: HashMap<&'static str, RegisteredCommand>,
 }
 
 impl CommandRegistry {
@@ -79,25 +130,356 @@ impl CommandRegistry {
         v
     }
 
-    pub fn register_command<C: Command + 'static>(&mut self, name: &'static str, command: C) {
-        self.commands.insert(name, Box::new(command));
+    pub fn get_description(&self, name: &str) -> Option<&'static str> {
+        self.commands.get(name).map(|c| c.description)
+    }
+
+    pub fn register_command<C: Command + 'static>(
+        &mut self,
+        name: &'static str,
+        description: &'static str,
+        command: C,
+    ) {
+        self.commands.insert(
+            name,
+            RegisteredCommand {
+                description,
+                command: Box::new(command),
+            },
+        );
     }
 
     pub fn register_default_commands(&mut self) {
-        self.register_command("exit", CommandExit);
-        self.register_command("quit", CommandExit);
-        self.register_command("clear", CommandClear);
-        self.register_command("cls", CommandClear);
-        self.register_command("copy", CommandCopy);
-        self.register_command("copy_all", CommandCopyAll);
-        self.register_command("clear_history", CommandClearHistory);
-        self.register_command("delete", CommandDelete);
-        self.register_command("help", CommandHelp);
-        self.register_command("set_model", CommandSetModel);
-        self.register_command("system_edit", CommandSystemEdit);
-        self.register_command("system_remove", CommandSystemRemove);
-        self.register_command("system_use", CommandSystemUse);
-        self.register_command("markdown", CommandMarkdown);
+        self.register_command("exit", "Quit chad-llm", CommandExit);
+        self.register_command("quit", "Quit chad-llm", CommandExit);
+        self.register_command("clear", "Clear the screen", CommandClear);
+        self.register_command("cls", "Clear the screen", CommandClear);
+        self.register_command(
+            "copy",
+            "Copy code blocks to the clipboard, lines of one with N:start-end, or all in a language with --lang",
+            CommandCopy,
+        );
+        self.register_command(
+            "save_code",
+            "Save a code block (the last one by default) to a file",
+            CommandSaveCode,
+        );
+        self.register_command(
+            "send_pane",
+            "Paste a code block into a tmux pane or kitty window",
+            CommandSendPane,
+        );
+        self.register_command(
+            "copy_all",
+            "Copy all code blocks to the clipboard",
+            CommandCopyAll,
+        );
+        self.register_command(
+            "run",
+            "Run a python, bash or node code block and show its output",
+            CommandRun,
+        );
+        self.register_command(
+            "debug_last_cmd",
+            "Ask what went wrong with the last command run in your shell",
+            CommandDebugLastCmd,
+        );
+        self.register_command(
+            "pr",
+            "Draft a pull request for this branch against [base], then copy it or open it with gh",
+            CommandPr,
+        );
+        self.register_command(
+            "split_commits",
+            "Plan how to split the staged changes into commits, then make them one by one",
+            CommandSplitCommits,
+        );
+        self.register_command(
+            "share",
+            "Share this session on a socket [path|host:port] [--write], 'stop' to end it",
+            CommandShare,
+        );
+        self.register_command(
+            "vars",
+            "List variables bound with /command > $name",
+            CommandVars,
+        );
+        self.register_command(
+            "copied",
+            "Copy an earlier clipboard item again",
+            CommandCopied,
+        );
+        self.register_command(
+            "clear_history",
+            "Delete the saved conversation transcripts",
+            CommandClearHistory,
+        );
+        self.register_command(
+            "tag",
+            "Tag this session: add <tags>, remove <tags>, or list",
+            CommandTag,
+        );
+        self.register_command(
+            "sessions",
+            "Pick an earlier session to continue (--tag <tag>), or prune old ones",
+            CommandSessions,
+        );
+        self.register_command(
+            "purge",
+            "Securely delete transcripts, input history and statistics; --all for everything",
+            CommandPurge,
+        );
+        self.register_command(
+            "clear_input_history",
+            "Delete the saved prompt input history",
+            CommandClearInputHistory,
+        );
+        self.register_command(
+            "delete",
+            "Delete messages from the context, e.g. 3-7, assistant:last or before 5; --all includes the system message",
+            CommandDelete,
+        );
+        self.register_command(
+            "lint_context",
+            "Flag context messages that likely hurt replies",
+            CommandLintContext,
+        );
+        self.register_command(
+            "find",
+            "Search messages of the current session",
+            CommandFind,
+        );
+        self.register_command(
+            "context",
+            "List the context, or show message N; --verbose adds request IDs and models",
+            CommandContext,
+        );
+        self.register_command(
+            "copy_msg",
+            "Copy message N of the context to the clipboard",
+            CommandCopyMsg,
+        );
+        self.register_command(
+            "bookmark",
+            "Bookmark reply N (the last one by default) across sessions",
+            CommandBookmark,
+        );
+        self.register_command(
+            "bookmarks",
+            "List bookmarks, or: show N | remove N | export [file]",
+            CommandBookmarks,
+        );
+        self.register_command("help", "List available commands", CommandHelp);
+        self.register_command(
+            "set_model",
+            "Change the model (--refresh reloads the model list)",
+            CommandSetModel,
+        );
+        self.register_command(
+            "system_edit",
+            "Edit or create a system prompt",
+            CommandSystemEdit,
+        );
+        self.register_command(
+            "system_remove",
+            "Remove a system prompt",
+            CommandSystemRemove,
+        );
+        self.register_command(
+            "lang",
+            "Set the language replies are written in (ro, en, de, ... or off)",
+            CommandLang,
+        );
+        self.register_command(
+            "instructions",
+            "Show the composed system message, or add/clear session instructions",
+            CommandInstructions,
+        );
+        self.register_command(
+            "note",
+            "Keep notes about this project in every session's context: add <text> | list | rm N",
+            CommandNote,
+        );
+        self.register_command(
+            "system_use",
+            "Switch the active system prompt",
+            CommandSystemUse,
+        );
+        self.register_command("markdown", "Toggle markdown rendering", CommandMarkdown);
+        self.register_command(
+            "add",
+            "Stage a file, the clipboard or a note for the next message",
+            CommandAdd,
+        );
+        self.register_command(
+            "digest",
+            "Summarize a large file into the context, or stage parts matching a query",
+            CommandDigest,
+        );
+        self.register_command(
+            "paste",
+            "Stage the clipboard contents for the next message",
+            CommandPaste,
+        );
+        self.register_command("staged", "Show or clear staged parts", CommandStaged);
+        self.register_command(
+            "inject",
+            "Insert a user or assistant message without sending",
+            CommandInject,
+        );
+        self.register_command(
+            "import",
+            "Load a conversation exported by another tool",
+            CommandImport,
+        );
+        self.register_command(
+            "export",
+            "Export conversations as fine-tuning JSONL",
+            CommandExport,
+        );
+        self.register_command(
+            "scaffold",
+            "Write the files in the last reply under a directory; --force overwrites",
+            CommandScaffold,
+        );
+        self.register_command("usage", "Show token usage and cache hits", CommandUsage);
+        self.register_command(
+            "fallback",
+            "Show or set the model fallback chain",
+            CommandFallback,
+        );
+        self.register_command("queue", "Show or configure the offline queue", CommandQueue);
+        self.register_command(
+            "continue",
+            "Continue a reply that was cut off",
+            CommandContinue,
+        );
+        self.register_command(
+            "redact",
+            "Toggle secret redaction for outgoing messages",
+            CommandRedact,
+        );
+        self.register_command(
+            "token_warning",
+            "Show or set the token count that asks for confirmation before sending",
+            CommandTokenWarning,
+        );
+        self.register_command(
+            "creative",
+            "Use the creative sampling preset",
+            CommandPreset("creative"),
+        );
+        self.register_command(
+            "balanced",
+            "Use the balanced sampling preset",
+            CommandPreset("balanced"),
+        );
+        self.register_command(
+            "precise",
+            "Use the precise sampling preset",
+            CommandPreset("precise"),
+        );
+        self.register_command(
+            "replay",
+            "Print earlier sessions and the whole conversation again",
+            CommandReplay,
+        );
+        self.register_command(
+            "retry",
+            "Ask for a new reply to the last message",
+            CommandRetry,
+        );
+        self.register_command(
+            "diff",
+            "Show what changed between the last two replies",
+            CommandDiff,
+        );
+        self.register_command(
+            "personas",
+            "List the personas to address with @name",
+            CommandPersonas,
+        );
+        self.register_command(
+            "reflect",
+            "Critique and improve the last reply, or toggle auto/critique",
+            CommandReflect,
+        );
+        self.register_command(
+            "roundtable",
+            "Let the personas discuss a topic, then summarize",
+            CommandRoundtable,
+        );
+        self.register_command(
+            "followups",
+            "Toggle suggested follow-up questions after each reply",
+            CommandFollowups,
+        );
+        self.register_command(
+            "record",
+            "Record replies with their timing for chad-gpt replay: <file> | stop",
+            CommandRecord,
+        );
+        self.register_command(
+            "draft",
+            "Show a fast model's draft until the main answer arrives: [model | off]",
+            CommandDraft,
+        );
+        self.register_command("f", "Ask suggested follow-up N", CommandFollowup);
+        self.register_command(
+            "ask",
+            "Ask a one-off question outside the conversation",
+            CommandAsk,
+        );
+        self.register_command(
+            "translate",
+            "Translate the last message (or the clipboard) to a language",
+            CommandTranslate,
+        );
+        self.register_command(
+            "proofread",
+            "Fix grammar and spelling of the last message (or the clipboard)",
+            CommandProofread,
+        );
+        self.register_command(
+            "sweep",
+            "Re-run the last prompt at several temperature or top_p values",
+            CommandSweep,
+        );
+        self.register_command(
+            "preview",
+            "Show the request a message would send, without sending it",
+            CommandPreview,
+        );
+        self.register_command(
+            "stats",
+            "Show latency and throughput per model, optionally for the last N days",
+            CommandStats,
+        );
+        self.register_command(
+            "constrain",
+            "Constrain replies to a JSON schema (.json) or GBNF grammar file, or 'off'",
+            CommandConstrain,
+        );
+        self.register_command(
+            "confidence",
+            "Toggle coloring replies by token probability; 'last' lists uncertain tokens",
+            CommandConfidence,
+        );
+        self.register_command(
+            "line_numbers",
+            "Toggle line numbers and headers on code blocks",
+            CommandLineNumbers,
+        );
+        self.register_command(
+            "auto_copy",
+            "Toggle copying the code block of replies with exactly one",
+            CommandAutoCopy,
+        );
+        self.register_command(
+            "mouse",
+            "Toggle mouse support in selection menus",
+            CommandMouse,
+        );
     }
 
     pub fn execute_command(
@@ -107,7 +489,7 @@ impl CommandRegistry {
         app: Rc<RefCell<Application>>,
     ) -> Result<(), CommandError> {
         match self.commands.get(&name) {
-            Some(x) => x.handle_command(self, args, app),
+            Some(x) => x.command.handle_command(self, args, app),
             None => Err(CommandError::CommandNotFound),
         }
     }
@@ -133,7 +515,7 @@ impl Command for CommandClear {
         _args: Vec<&str>,
         _app: Rc<RefCell<Application>>,
     ) -> Result<(), CommandError> {
-        print!("\x1B[2J\x1B[1;1H\r\n");
+        CLI::clear();
         Ok(())
     }
 }
@@ -143,123 +525,306 @@ impl Command for CommandCopy {
     fn handle_command(
         &self,
         _registry: &CommandRegistry,
-        _args: Vec<&str>,
+        mut args: Vec<&str>,
         app: Rc<RefCell<Application>>,
     ) -> Result<(), CommandError> {
-        let app = app.borrow_mut();
+        let mut app = app.borrow_mut();
         if app.code_blocks.is_empty() {
             print!("No code blocks to copy.\r\n");
             return Ok(());
         }
 
-        let selections: Vec<&str> = app.code_blocks.iter().map(|s| s.as_str()).collect();
-        let res = CLI::select("Select code block to copy", &selections, false, &[]);
+        // Either `N` or `N:start-end` per argument, numbered from 1.
+        let mut picks: Vec<(usize, Option<(usize, usize)>)> = Vec::new();
+        if let Some(i) = args.iter().position(|a| *a == "--lang") {
+            let language = args.get(i + 1).ok_or(CommandError::InvalidArguments)?;
+            let language = language::resolve_alias(language).to_lowercase();
+            args.drain(i..i + 2);
+            let whole_session = match args.iter().position(|a| *a == "--all") {
+                Some(i) => {
+                    args.remove(i);
+                    true
+                }
+                None => false,
+            };
+            // Blocks of the last reply are the last ones collected.
+            let first = match whole_session {
+                true => 0,
+                false => {
+                    let reply = context_messages(&app)
+                        .into_iter()
+                        .rev()
+                        .find(|m| m.role == "assistant")
+                        .map(|m| response::count_code_blocks(&m.content))
+                        .unwrap_or(0);
+                    app.code_blocks.len().saturating_sub(reply)
+                }
+            };
+            picks = (first..app.code_blocks.len())
+                .filter(|&i| {
+                    language::resolve_alias(&app.code_blocks[i].language).to_lowercase() == language
+                })
+                .map(|i| (i, None))
+                .collect();
+            if picks.is_empty() {
+                match whole_session {
+                    true => print!("No {} code blocks in this session.\r\n", language),
+                    false => print!("No {} code blocks in the last reply.\r\n", language),
+                }
+                return Ok(());
+            }
+        }
+        for arg in args {
+            let pick = parse_block_range(arg).ok_or(CommandError::InvalidArguments)?;
+            if pick.0 >= app.code_blocks.len() {
+                print!("There is no code block {}.\r\n", pick.0 + 1);
+                return Err(CommandError::InvalidArguments);
+            }
+            picks.push(pick);
+        }
+        if picks.is_empty() {
+            let selections: Vec<String> = app
+                .code_blocks
+                .iter()
+                .map(|b| match b.truncated {
+                    true => format!("(truncated) {}", b.content),
+                    false => b.content.clone(),
+                })
+                .collect();
+            picks = CLI::select("Select code block to copy", &selections, false, &[])
+                .into_iter()
+                .map(|i| (i, None))
+                .collect();
+        }
 
         let mut selection = String::new();
-        for i in res {
-            selection.push_str(&format!("{}\n", selections[i]));
+        for &(i, range) in &picks {
+            let content = &app.code_blocks[i].content;
+            match range {
+                Some((start, end)) => {
+                    for line in content.lines().skip(start - 1).take(end + 1 - start) {
+                        selection.push_str(line);
+                        selection.push('\n');
+                    }
+                }
+                None => selection.push_str(&format!("{}\n", content)),
+            }
         }
 
-        let mut clipboard: ClipboardContext = ClipboardProvider::new().unwrap();
-        clipboard.set_contents(selection).unwrap();
+        let source = match picks.as_slice() {
+            [] => return Ok(()),
+            [(i, range)] => {
+                let block = &app.code_blocks[*i];
+                let lines = range
+                    .map(|(start, end)| format!("lines {}-{} of ", start, end))
+                    .unwrap_or_default();
+                match app.reply_containing(&block.content) {
+                    Some(reply) => format!(
+                        "{}code block {} ({}) of reply {}",
+                        lines,
+                        i + 1,
+                        block.language,
+                        reply
+                    ),
+                    None => format!("{}code block {} ({})", lines, i + 1, block.language),
+                }
+            }
+            _ => format!("{} code blocks", picks.len()),
+        };
+        if let Err(e) = app.copy_to_clipboard(source, selection) {
+            eprint!("Failed to copy to clipboard: {}\r\n", e);
+            return Err(CommandError::UpdateFailed);
+        }
         print!("Code block copied to clipboard\r\n");
         Ok(())
     }
 }
 
-struct CommandCopyAll;
-impl Command for CommandCopyAll {
+struct CommandSaveCode;
+impl Command for CommandSaveCode {
     fn handle_command(
         &self,
         _registry: &CommandRegistry,
-        _args: Vec<&str>,
+        args: Vec<&str>,
         app: Rc<RefCell<Application>>,
     ) -> Result<(), CommandError> {
-        let app = app.borrow_mut();
+        let app = app.borrow();
         if app.code_blocks.is_empty() {
-            print!("No code blocks to copy.\r\n");
+            print!("No code blocks to save.\r\n");
             return Ok(());
         }
+        let index = match args.first() {
+            Some(arg) => arg
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .filter(|&i| i < app.code_blocks.len())
+                .ok_or(CommandError::InvalidArguments)?,
+            None => app.code_blocks.len() - 1,
+        };
+        let block = &app.code_blocks[index];
 
-        let mut clipboard: ClipboardContext = ClipboardProvider::new().unwrap();
-        let all_code = app.code_blocks.join("\n\n");
-        clipboard.set_contents(all_code.clone()).unwrap();
-        print!("All code blocks copied to clipboard\r\n");
+        let path = match args.get(1) {
+            Some(path) => path.to_string(),
+            None => {
+                // Name it after the reply the block came from and the
+                // message that asked for it.
+                let messages = context_messages(&app);
+                let position = messages.iter().rposition(|m| {
+                    m.role == "assistant" && m.content.contains(block.content.trim_end())
+                });
+                let (reply, request) = match position {
+                    Some(i) => (
+                        messages[i].content.as_str(),
+                        messages[..i]
+                            .iter()
+                            .rev()
+                            .find(|m| m.role == "user")
+                            .map_or("", |m| m.content.as_str()),
+                    ),
+                    None => ("", ""),
+                };
+                let suggestion = scaffold::suggest_name(block, index + 1, reply, request);
+                match cli::ReadLine::<String>::new()
+                    .prompt("Save as: ")
+                    .initial(suggestion)
+                    .run()
+                {
+                    Some(path) if !path.trim().is_empty() => path.trim().to_owned(),
+                    _ => {
+                        print!("Aborted!\r\n");
+                        return Err(CommandError::Aborted);
+                    }
+                }
+            }
+        };
+
+        if std::path::Path::new(&path).exists()
+            && CLI::read_key(&format!("{} exists. Overwrite? [y/n]", path), "yn") != Some('y')
+        {
+            print!("Not saved.\r\n");
+            return Err(CommandError::Aborted);
+        }
+        let result = match std::path::Path::new(&path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => std::fs::create_dir_all(parent),
+            _ => Ok(()),
+        }
+        .and_then(|_| std::fs::write(&path, &block.content));
+        if let Err(e) = result {
+            eprint!("Failed to write {}: {}\r\n", path, e);
+            return Err(CommandError::UpdateFailed);
+        }
+        print!("Saved code block {} to {}.\r\n", index + 1, path);
         Ok(())
     }
 }
 
-struct CommandClearHistory;
-impl Command for CommandClearHistory {
-    fn handle_command(
-        &self,
-        _registry: &CommandRegistry,
-        _args: Vec<&str>,
-        _app: Rc<RefCell<Application>>,
-    ) -> Result<(), CommandError> {
-        if let Err(e) = remove_file(HISTORY_FILE) {
-            eprint!("Failed to clear history: {}\r\n", e);
-        } else {
-            print!("History cleared.\r\n");
+/// Parses `N` or `N:start-end` (1-based, inclusive) into a block index and
+/// line range.
+fn parse_block_range(arg: &str) -> Option<(usize, Option<(usize, usize)>)> {
+    let (block, range) = match arg.split_once(':') {
+        Some((block, range)) => (block, Some(range)),
+        None => (arg, None),
+    };
+    let block = block.parse::<usize>().ok()?.checked_sub(1)?;
+    let Some(range) = range else {
+        return Some((block, None));
+    };
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+        None => {
+            let line = range.parse().ok()?;
+            (line, line)
         }
-        Ok(())
-    }
+    };
+    (start >= 1 && start <= end).then_some((block, Some((start, end))))
 }
 
-struct CommandDelete;
-impl Command for CommandDelete {
+struct CommandSendPane;
+impl Command for CommandSendPane {
     fn handle_command(
         &self,
         _registry: &CommandRegistry,
-        _args: Vec<&str>,
+        args: Vec<&str>,
         app: Rc<RefCell<Application>>,
     ) -> Result<(), CommandError> {
-        let app = app.borrow_mut();
-        let shared_context = &app.context;
-        let messages = app.tokio_rt.block_on(async {
-            let locked = shared_context.lock().await;
-            locked.clone()
-        });
-
-        let mut messages_choice = Vec::<String>::new();
-        for msg in messages {
-            let msg = format!("{}: {}", msg.role, msg.content);
-            messages_choice.push(msg);
+        let Some(&target) = args.first() else {
+            print!("Usage: /send_pane <tmux pane | kitty:<match>> [N | N:start-end]\r\n");
+            return Err(CommandError::InvalidArguments);
+        };
+        let app = app.borrow();
+        if app.code_blocks.is_empty() {
+            print!("No code blocks to send.\r\n");
+            return Ok(());
         }
 
-        let mut selections = CLI::select("Select messages to delete", &messages_choice, false, &[]);
-        selections.sort_by(|a, b| b.cmp(a));
-
-        app.tokio_rt.block_on(async {
-            let mut locked = shared_context.lock().await;
-            for i in selections {
-                locked.remove(i);
+        let (i, range) = match args.get(1) {
+            Some(arg) => parse_block_range(arg).ok_or(CommandError::InvalidArguments)?,
+            None => {
+                let selections: Vec<&str> =
+                    app.code_blocks.iter().map(|b| b.content.as_str()).collect();
+                match CLI::select("Select code block to send", &selections, true, &[]).first() {
+                    Some(&i) => (i, None),
+                    None => return Err(CommandError::Aborted),
+                }
             }
-            locked.clone()
-        });
+        };
+        let Some(block) = app.code_blocks.get(i) else {
+            print!("There is no code block {}.\r\n", i + 1);
+            return Err(CommandError::InvalidArguments);
+        };
+        let text = match range {
+            Some((start, end)) => block
+                .content
+                .lines()
+                .skip(start - 1)
+                .take(end + 1 - start)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            None => block.content.clone(),
+        };
 
+        if let Err(e) = pane::send(target, &text) {
+            eprint!("Failed to send to {}: {}\r\n", target, e);
+            return Err(CommandError::UpdateFailed);
+        }
+        print!("Sent to {}, press Enter there to run it.\r\n", target);
         Ok(())
     }
 }
 
-struct CommandHelp;
-impl Command for CommandHelp {
+struct CommandCopyAll;
+impl Command for CommandCopyAll {
     fn handle_command(
         &self,
-        registry: &CommandRegistry,
+        _registry: &CommandRegistry,
         _args: Vec<&str>,
-        _app: Rc<RefCell<Application>>,
+        app: Rc<RefCell<Application>>,
     ) -> Result<(), CommandError> {
-        print!("Available commands:\r\n");
-        for name in registry.get_available_commands() {
-            print!("- {}\r\n", name);
+        let mut app = app.borrow_mut();
+        if app.code_blocks.is_empty() {
+            print!("No code blocks to copy.\r\n");
+            return Ok(());
         }
+
+        let all_code = app
+            .code_blocks
+            .iter()
+            .map(|b| b.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let source = format!("all {} code blocks", app.code_blocks.len());
+        if let Err(e) = app.copy_to_clipboard(source, all_code) {
+            eprint!("Failed to copy to clipboard: {}\r\n", e);
+            return Err(CommandError::UpdateFailed);
+        }
+        print!("All code blocks copied to clipboard\r\n");
         Ok(())
     }
 }
 
-struct CommandSetModel;
-impl Command for CommandSetModel {
+struct CommandRun;
+impl Command for CommandRun {
     fn handle_command(
         &self,
         _registry: &CommandRegistry,
@@ -267,53 +832,113 @@ impl Command for CommandSetModel {
         app: Rc<RefCell<Application>>,
     ) -> Result<(), CommandError> {
         let mut app = app.borrow_mut();
+        let runnable: Vec<usize> = (0..app.code_blocks.len())
+            .filter(|&i| runner::interpreter(&app.code_blocks[i].language).is_some())
+            .collect();
 
-        let mut available_models: Vec<String> = vec![];
-
-        app.tokio_rt.block_on(async {
-            available_models = match openai::get_models().await {
-                Some(x) => x,
-                None => {
-                    print!("Failed to fetch available models from OpenAI.\r\n");
-                    openai::AVAILABLE_MODELS
-                        .iter()
-                        .map(|m| m.to_string())
-                        .collect()
+        let block = match args.first() {
+            Some(arg) if arg.starts_with('$') => {
+                let Some(code) = app.variables.get(&arg[1..]) else {
+                    print!("{} is not set.\r\n", arg);
+                    return Err(CommandError::InvalidArguments);
+                };
+                let Some(language) =
+                    language::detect(code).filter(|l| runner::interpreter(l).is_some())
+                else {
+                    print!("Can't tell how to run {}.\r\n", arg);
+                    return Err(CommandError::InvalidArguments);
+                };
+                CodeBlock {
+                    language: language.to_owned(),
+                    content: code.to_owned(),
+                    truncated: false,
                 }
             }
-        });
+            Some(arg) => {
+                let i = arg
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| i.checked_sub(1))
+                    .ok_or(CommandError::InvalidArguments)?;
+                if !runnable.contains(&i) {
+                    print!("Code block {} can't be run.\r\n", arg);
+                    return Err(CommandError::InvalidArguments);
+                }
+                app.code_blocks[i].clone()
+            }
+            None => {
+                if runnable.is_empty() {
+                    print!("No python, bash or node code blocks to run.\r\n");
+                    return Ok(());
+                }
+                let options: Vec<String> = runnable
+                    .iter()
+                    .map(|&i| {
+                        let block = &app.code_blocks[i];
+                        let first_line = block.content.lines().next().unwrap_or_default();
+                        format!("{} ({}): {}", i + 1, block.language, first_line)
+                    })
+                    .collect();
+                match CLI::select("Select code block to run", &options, true, &[]).first() {
+                    Some(&i) => app.code_blocks[runnable[i]].clone(),
+                    None => return Ok(()),
+                }
+            }
+        };
 
-        let model_idx;
-        if args.len() != 0 {
-            match available_models.iter().position(|r| r == args[0]) {
-                Some(x) => model_idx = x,
-                None => {
-                    return Err(CommandError::InvalidModel);
+        let (program, _) = runner::interpreter(&block.language).unwrap();
+        print!("{}\r\n", block.content.replace('\n', "\r\n"));
+        let choice = CLI::select(
+            &format!("Run this with {} on your machine?", program),
+            &["No", "Yes"],
+            true,
+            &[0],
+        );
+        if choice.first() != Some(&1) {
+            return Err(CommandError::Aborted);
+        }
+
+        let timeout = runner::timeout();
+        let output =
+            match app
+                .tokio_rt
+                .block_on(runner::run(&block.language, &block.content, timeout))
+            {
+                Ok(x) => x,
+                Err(e) => {
+                    eprint!("Failed to run code block: {}\r\n", e);
+                    return Err(CommandError::UpdateFailed);
                 }
             };
-        } else {
-            let initial = available_models
-                .iter()
-                .position(|r| *r == app.model)
-                .unwrap();
-            model_idx = *CLI::select(
-                &format!("Select a model to use. You are using {}.", app.model),
-                &available_models,
-                true,
-                &[initial],
-            )
-            .get(0)
-            .unwrap_or(&0);
+
+        for (name, text) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+            if !text.is_empty() {
+                print!(
+                    "--- {} ---\r\n{}\r\n",
+                    name,
+                    text.trim_end().replace('\n', "\r\n")
+                );
+            }
+        }
+        match (output.timed_out, output.status) {
+            (true, _) => print!("Timed out after {}s.\r\n", timeout.as_secs()),
+            (false, Some(code)) => print!("Exited with status {}.\r\n", code),
+            (false, None) => print!("Killed by a signal.\r\n"),
         }
 
-        app.model = available_models[model_idx].clone();
-        print!("Model changed to {}!\r\n", app.model);
+        app.last_result = Some(output.stdout.clone());
+
+        let choice = CLI::select("Send the output to the model?", &["No", "Yes"], true, &[0]);
+        if choice.first() == Some(&1) {
+            app.send_message(&output.to_message());
+            print!("\r\n");
+        }
         Ok(())
     }
 }
 
-struct CommandSystemEdit;
-impl Command for CommandSystemEdit {
+struct CommandVars;
+impl Command for CommandVars {
     fn handle_command(
         &self,
         _registry: &CommandRegistry,
@@ -321,49 +946,1165 @@ impl Command for CommandSystemEdit {
         app: Rc<RefCell<Application>>,
     ) -> Result<(), CommandError> {
         let mut app = app.borrow_mut();
+        if args.first() == Some(&"clear") {
+            app.variables.clear();
+            return Ok(());
+        }
+        if app.variables.is_empty() {
+            print!("No variables set. Bind one with e.g. /copy 2 > $snippet\r\n");
+            return Ok(());
+        }
 
-        let available_prompts = app.system_prompts.get_available();
-        let name = match get_input_or_select(
-            &args,
-            &available_prompts
-                .iter()
-                .map(|s| s.as_str())
-                .collect::<Vec<_>>(),
-            "Select a system prompt:",
-            Some(&app.active_system_prompt),
-        ) {
-            Some(name) => name,
-            None => return Err(CommandError::Aborted),
-        };
-
-        let existing_data = match app.system_prompts.get(&name) {
-            Some(x) => x.clone(),
-            _ => "You are a helpful virtual assistant.".to_string(),
-        };
-
-        if let Some(inp) = CLI::editor(&existing_data) {
-            match app.system_prompts.update_or_create(&name, &inp) {
-                Ok(_) => {
-                    print!("Prompt updated.\r\n");
-                    Ok(())
-                }
-                Err(e) => {
-                    print!("Failed to update. Reason: {}\r\n", e);
-                    Err(CommandError::UpdateFailed)
-                }
-            }
-        } else {
-            Err(CommandError::Aborted)
+        let mut names: Vec<&String> = app.variables.keys().collect();
+        names.sort();
+        for name in names {
+            let value = &app.variables[name];
+            print!(
+                "${} ({} lines): {}\r\n",
+                name,
+                value.lines().count(),
+                value.lines().next().unwrap_or_default()
+            );
         }
+        Ok(())
     }
 }
 
-struct CommandSystemRemove;
-impl Command for CommandSystemRemove {
+struct CommandCopied;
+impl Command for CommandCopied {
     fn handle_command(
         &self,
         _registry: &CommandRegistry,
-        args: Vec<&str>,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        if app.copied.is_empty() {
+            print!("Nothing has been copied yet.\r\n");
+            return Ok(());
+        }
+
+        let items: Vec<String> = app
+            .copied
+            .iter()
+            .rev()
+            .map(|item| {
+                let first_line = item.content.lines().next().unwrap_or_default();
+                format!(
+                    "{} ago, {}: {}",
+                    format_age(item.copied_at.elapsed()),
+                    item.source,
+                    first_line
+                )
+            })
+            .collect();
+        let Some(&i) = CLI::select("Select item to copy again", &items, true, &[]).first() else {
+            return Ok(());
+        };
+
+        let index = app.copied.len() - 1 - i;
+        let item = app.copied.remove(index);
+        if let Err(e) = app.copy_to_clipboard(item.source, item.content) {
+            eprint!("Failed to copy to clipboard: {}\r\n", e);
+            return Err(CommandError::UpdateFailed);
+        }
+        print!("Copied to clipboard\r\n");
+        Ok(())
+    }
+}
+
+fn format_age(age: std::time::Duration) -> String {
+    match age.as_secs() {
+        s if s < 60 => format!("{}s", s),
+        s if s < 60 * 60 => format!("{}m", s / 60),
+        s => format!("{}h", s / (60 * 60)),
+    }
+}
+
+struct CommandClearHistory;
+impl Command for CommandClearHistory {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        if let Err(e) = app.session_history.clear_transcripts() {
+            eprint!("Failed to clear history: {}\r\n", e);
+        } else {
+            app.restored_history.clear();
+            print!("Conversation history cleared.\r\n");
+        }
+        Ok(())
+    }
+}
+
+struct CommandTag;
+impl Command for CommandTag {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        let mut meta = app.session_history.meta().map_err(|e| {
+            eprint!("Failed to read session metadata: {}\r\n", e);
+            CommandError::ReadFailed
+        })?;
+        let tags = args
+            .iter()
+            .skip(1)
+            .flat_map(|a| a.split(','))
+            .map(|t| t.trim().to_lowercase())
+            .filter(|t| !t.is_empty());
+
+        match args.first() {
+            None | Some(&"list") => {}
+            Some(&"add") => {
+                for tag in tags {
+                    if !meta.tags.contains(&tag) {
+                        meta.tags.push(tag);
+                    }
+                }
+            }
+            Some(&"remove") => {
+                let tags: Vec<String> = tags.collect();
+                meta.tags.retain(|t| !tags.contains(t));
+            }
+            _ => return Err(CommandError::InvalidArguments),
+        }
+
+        if !args.is_empty() && args[0] != "list" {
+            if let Err(e) = app.session_history.set_meta(meta.clone()) {
+                eprint!("Failed to save session metadata: {}\r\n", e);
+                return Err(CommandError::UpdateFailed);
+            }
+        }
+        match meta.tags.is_empty() {
+            true => print!("This session has no tags.\r\n"),
+            false => print!("Tags: {}\r\n", meta.tags.join(", ")),
+        }
+        app.apply_model_pins(&meta.tags);
+        Ok(())
+    }
+}
+
+struct CommandSessions;
+impl Command for CommandSessions {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        if args.first() == Some(&"prune") {
+            return prune_sessions(&app.borrow(), &args[1..]);
+        }
+        let tag = match args.as_slice() {
+            [] => None,
+            ["--tag", tag] => Some(tag.to_lowercase()),
+            _ => return Err(CommandError::InvalidArguments),
+        };
+
+        let mut app = app.borrow_mut();
+        let mut sessions = app.session_history.list_sessions().map_err(|e| {
+            eprint!("Failed to load sessions: {}\r\n", e);
+            CommandError::ReadFailed
+        })?;
+        let current = app.session_history.session_id().to_owned();
+        sessions.retain(|s| s.id != current);
+        if let Some(tag) = &tag {
+            sessions.retain(|s| s.meta.tags.contains(tag));
+        }
+        if sessions.is_empty() {
+            print!("No sessions found.\r\n");
+            return Ok(());
+        }
+
+        // Newest first.
+        sessions.reverse();
+        let options: Vec<String> = sessions
+            .iter()
+            .map(|s| {
+                let first = s.meta.title.as_deref().unwrap_or_else(|| {
+                    s.messages
+                        .iter()
+                        .find(|m| m.role == "user")
+                        .and_then(|m| m.content.lines().next())
+                        .unwrap_or_default()
+                });
+                let tags = match s.meta.tags.is_empty() {
+                    true => String::new(),
+                    false => format!(" [{}]", s.meta.tags.join(", ")),
+                };
+                format!(
+                    "{} ({} messages){}: {}",
+                    stats::date(s.started),
+                    s.messages.len(),
+                    tags,
+                    first
+                )
+            })
+            .collect();
+        let Some(&i) = CLI::select("Select a session to continue", &options, true, &[]).first()
+        else {
+            return Ok(());
+        };
+
+        let session = sessions.swap_remove(i);
+        let shared_context = &app.context;
+        app.tokio_rt.block_on(async {
+            let mut locked = shared_context.lock().await;
+            locked.retain(|m| m.role == "system");
+            locked.extend(
+                session
+                    .messages
+                    .iter()
+                    .filter(|m| m.role != "system")
+                    .cloned(),
+            );
+        });
+        app.session_history.resume(&session.id);
+        app.apply_model_pins(&session.meta.tags);
+        app.render_messages(&session.messages);
+        if let Some(summary) = &session.meta.summary {
+            print!("Summary: {}\r\n", summary.replace('\n', "\r\n"));
+        }
+        Ok(())
+    }
+}
+
+/// Parses durations like `30d`, `12h` or `2w` into seconds.
+fn parse_age(age: &str) -> Option<u64> {
+    let unit = match age.chars().last()? {
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(age[..age.len() - 1].parse::<u64>().ok()? * unit)
+}
+
+/// `/sessions prune [--older-than <age>] [--max-mb <size>] [--archive]`
+fn prune_sessions(app: &Application, args: &[&str]) -> Result<(), CommandError> {
+    let mut older_than = None;
+    let mut max_bytes = None;
+    let mut archive = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--older-than" => {
+                older_than = Some(
+                    args.next()
+                        .and_then(|a| parse_age(a))
+                        .ok_or(CommandError::InvalidArguments)?,
+                )
+            }
+            "--max-mb" => {
+                let mb: u64 = args
+                    .next()
+                    .and_then(|a| a.parse().ok())
+                    .ok_or(CommandError::InvalidArguments)?;
+                max_bytes = Some(mb * 1024 * 1024);
+            }
+            "--archive" => archive = true,
+            _ => return Err(CommandError::InvalidArguments),
+        }
+    }
+    if older_than.is_none() && max_bytes.is_none() {
+        print!("Usage: /sessions prune [--older-than 30d] [--max-mb 50] [--archive]\r\n");
+        return Err(CommandError::InvalidArguments);
+    }
+
+    match app.session_history.prune(older_than, max_bytes, archive) {
+        Ok((removed, archive)) => {
+            print!("Pruned {} sessions.\r\n", removed.len());
+            if let Some(path) = archive {
+                print!("Archived to {}\r\n", path.display());
+            }
+            Ok(())
+        }
+        Err(e) => {
+            eprint!("Failed to prune sessions: {}\r\n", e);
+            Err(CommandError::UpdateFailed)
+        }
+    }
+}
+
+struct CommandPurge;
+impl Command for CommandPurge {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let all = args.contains(&"--all");
+        let files = purge::targets(all);
+        if files.is_empty() {
+            print!("Nothing to purge.\r\n");
+            return Ok(());
+        }
+        for file in &files {
+            print!("  {}\r\n", file.display());
+        }
+        let choice = CLI::select(
+            &format!("Securely delete these {} files?", files.len()),
+            &["No", "Yes"],
+            true,
+            &[0],
+        );
+        if choice.first() != Some(&1) {
+            return Err(CommandError::Aborted);
+        }
+
+        let (removed, failed) = purge::purge(&files);
+        for (file, e) in &failed {
+            eprint!("Failed to delete {}: {}\r\n", file.display(), e);
+        }
+
+        // Drop what this session still holds in memory too.
+        let mut app = app.borrow_mut();
+        app.cli_history.clear();
+        app.restored_history.clear();
+        app.editor_draft = None;
+        app.copied.clear();
+        app.variables.clear();
+        if all {
+            app.system_prompts.forget();
+        }
+        print!("Removed {} files.\r\n", removed.len());
+        Ok(())
+    }
+}
+
+struct CommandClearInputHistory;
+impl Command for CommandClearInputHistory {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        if let Err(e) = app.session_history.clear_inputs() {
+            eprint!("Failed to clear input history: {}\r\n", e);
+        } else {
+            app.cli_history.clear();
+            print!("Input history cleared.\r\n");
+        }
+        Ok(())
+    }
+}
+
+struct CommandDelete;
+impl Command for CommandDelete {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        mut args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let app = app.borrow_mut();
+        let shared_context = &app.context;
+        let messages = app.tokio_rt.block_on(async {
+            let locked = shared_context.lock().await;
+            locked.clone()
+        });
+        // The system message is left alone unless asked for with --all.
+        let all = match args.iter().position(|a| *a == "--all") {
+            Some(i) => {
+                args.remove(i);
+                true
+            }
+            None => false,
+        };
+
+        let mut selections = match args.is_empty() {
+            true => {
+                let shown: Vec<usize> = (0..messages.len())
+                    .filter(|&i| all || messages[i].role != "system")
+                    .collect();
+                let mut messages_choice = Vec::<String>::new();
+                for &i in &shown {
+                    let msg = format!("{}: {}", messages[i].role, messages[i].content);
+                    messages_choice.push(msg);
+                }
+                CLI::select("Select messages to delete", &messages_choice, false, &[])
+                    .into_iter()
+                    .map(|choice| shown[choice])
+                    .collect()
+            }
+            false => {
+                let mut selections = delete_selection(&args, &messages).map_err(|e| {
+                    print!("{}\r\n", e);
+                    CommandError::InvalidArguments
+                })?;
+                if !all && selections.iter().any(|&i| messages[i].role == "system") {
+                    selections.retain(|&i| messages[i].role != "system");
+                    print!("Kept the system message, use --all to delete it too.\r\n");
+                }
+                print!("Deleted {} message(s).\r\n", selections.len());
+                selections
+            }
+        };
+        selections.sort_by(|a, b| b.cmp(a));
+
+        app.tokio_rt.block_on(async {
+            let mut locked = shared_context.lock().await;
+            for i in selections {
+                locked.remove(i);
+            }
+            locked.clone()
+        });
+        // Deleting the system message was asked for, don't put it back.
+        if !all {
+            app.restore_system_message();
+        }
+
+        Ok(())
+    }
+}
+
+/// Resolves `/delete` arguments to context indices: 1-based indices and
+/// ranges (`3`, `3-7`, `2,5`), `role:last` or `role:all` (`assistant:last`),
+/// and `before N` / `after N`.
+fn delete_selection(args: &[&str], messages: &[Message]) -> Result<Vec<usize>, String> {
+    let len = messages.len();
+    let index = |arg: &str| -> Result<usize, String> {
+        arg.trim_start_matches('#')
+            .parse::<usize>()
+            .ok()
+            .filter(|&n| n >= 1 && n <= len)
+            .ok_or_else(|| format!("No message #{}, the context has {}.", arg, len))
+    };
+
+    let mut selected = std::collections::BTreeSet::new();
+    let mut args = args
+        .iter()
+        .flat_map(|a| a.split(','))
+        .filter(|a| !a.is_empty());
+    while let Some(arg) = args.next() {
+        match arg {
+            "before" | "after" => {
+                let n = index(args.next().ok_or("Expected a message index.")?)?;
+                match arg {
+                    "before" => selected.extend(0..n - 1),
+                    _ => selected.extend(n..len),
+                }
+            }
+            _ if arg.contains(':') => {
+                let (role, which) = arg.split_once(':').unwrap();
+                let mut of_role = messages
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, m)| m.role == role)
+                    .map(|(i, _)| i);
+                match which {
+                    "last" => selected.extend(of_role.next_back()),
+                    "all" => selected.extend(of_role),
+                    _ => return Err(format!("Expected {}:last or {}:all.", role, role)),
+                }
+            }
+            _ => {
+                let (start, end) = match arg.split_once('-') {
+                    Some((start, end)) => (index(start)?, index(end)?),
+                    None => (index(arg)?, index(arg)?),
+                };
+                if start > end {
+                    return Err(format!("{} is an empty range.", arg));
+                }
+                selected.extend(start - 1..end);
+            }
+        }
+    }
+    Ok(selected.into_iter().collect())
+}
+
+/// Parses a 1-based message index as printed by `/find` and `/context`.
+fn message_index(args: &[&str], len: usize) -> Option<usize> {
+    args.first()
+        .and_then(|a| a.trim_start_matches('#').parse::<usize>().ok())
+        .filter(|&n| n >= 1 && n <= len)
+        .map(|n| n - 1)
+}
+
+fn context_messages(app: &Application) -> Vec<Message> {
+    app.tokio_rt
+        .block_on(async { app.context.lock().await.clone() })
+}
+
+struct CommandFind;
+impl Command for CommandFind {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        if args.is_empty() {
+            return Err(CommandError::InvalidArguments);
+        }
+        let app = app.borrow();
+        let pattern = Regex::new(&format!("(?i){}", regex::escape(&args.join(" ")))).unwrap();
+        let show = |message: &Message| {
+            for line in message
+                .content
+                .lines()
+                .filter(|l| pattern.is_match(l))
+                .take(3)
+            {
+                let highlight = format!("{}$0\x1b[0m", theme::palette().highlight);
+                let line = pattern.replace_all(line.trim(), highlight.as_str());
+                print!("    {}\r\n", line);
+            }
+        };
+
+        let context = context_messages(&app);
+        let mut found = 0;
+        for (i, message) in context.iter().enumerate() {
+            if pattern.is_match(&message.content) {
+                print!("#{} {}:\r\n", i + 1, message.role);
+                show(message);
+                found += 1;
+            }
+        }
+
+        // Messages deleted from the context are still in the transcript.
+        let transcript = match app.session_history.load_current() {
+            Ok(x) => x,
+            Err(e) => {
+                eprint!("Failed to load transcript: {}\r\n", e);
+                vec![]
+            }
+        };
+        let mut earlier = transcript
+            .iter()
+            .filter(|m| pattern.is_match(&m.content))
+            .filter(|m| !context.iter().any(|c| c.content == m.content))
+            .peekable();
+        if earlier.peek().is_some() {
+            print!("No longer in the context:\r\n");
+        }
+        for message in earlier {
+            print!("- {}:\r\n", message.role);
+            show(message);
+            found += 1;
+        }
+
+        if found == 0 {
+            print!("No matches.\r\n");
+        }
+        Ok(())
+    }
+}
+
+const SUMMARIZE_PROMPT: &str = "Summarize the message you are given in a few sentences, \
+keeping only what could matter for the rest of the conversation.";
+
+struct CommandLintContext;
+impl Command for CommandLintContext {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let app = app.borrow();
+        let context = context_messages(&app);
+        let mut flagged: Vec<(usize, Vec<String>)> = Vec::new();
+        for finding in lint::lint(&context) {
+            match flagged.last_mut() {
+                Some((index, reasons)) if *index == finding.index => reasons.push(finding.reason),
+                _ => flagged.push((finding.index, vec![finding.reason])),
+            }
+        }
+        if flagged.is_empty() {
+            print!("Nothing to flag.\r\n");
+            return Ok(());
+        }
+
+        let mut delete = Vec::new();
+        for (index, reasons) in flagged {
+            let message = &context[index];
+            print!(
+                "#{} {}: {}\r\n",
+                index + 1,
+                message.role,
+                message.content.lines().next().unwrap_or_default()
+            );
+            print!(
+                "{}\r\n",
+                theme::format(&format!("[$yellow]  {}[$/]", reasons.join("; ")))
+            );
+            let Some(key) = CLI::read_key("  [d]elete, [s]ummarize, [k]eep, [q]uit?", "dskq")
+            else {
+                break;
+            };
+            match key {
+                'd' => delete.push(index),
+                's' => {
+                    let summary = match app.tokio_rt.block_on(openai::complete(
+                        &app.model,
+                        Some(SUMMARIZE_PROMPT),
+                        &message.content,
+                    )) {
+                        Ok(x) => x,
+                        Err(e) => {
+                            eprint!("Failed to summarize: {}\r\n", e);
+                            continue;
+                        }
+                    };
+                    print!("  {}\r\n", summary.replace('\n', "\r\n  "));
+                    app.tokio_rt.block_on(async {
+                        let mut locked = app.context.lock().await;
+                        if let Some(m) = locked.get_mut(index) {
+                            m.content = format!("(Summary of an earlier message) {}", summary);
+                        }
+                    });
+                }
+                'q' => break,
+                _ => {}
+            }
+        }
+
+        if !delete.is_empty() {
+            app.tokio_rt.block_on(async {
+                let mut locked = app.context.lock().await;
+                for &index in delete.iter().rev() {
+                    locked.remove(index);
+                }
+            });
+            print!("Deleted {} message(s).\r\n", delete.len());
+            app.restore_system_message();
+        }
+        Ok(())
+    }
+}
+
+/// The request ID, model snapshot, seed and fingerprint of a reply, for
+/// reporting or reproducing it.
+fn reply_details(message: &Message) -> Option<String> {
+    let metadata = &message.metadata;
+    let details: Vec<String> = [
+        ("request", metadata.request_id.clone()),
+        (
+            "model",
+            metadata.snapshot.clone().or(metadata.model.clone()),
+        ),
+        ("seed", metadata.seed.map(|s| s.to_string())),
+        ("fingerprint", metadata.fingerprint.clone()),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| Some(format!("{} {}", name, value?)))
+    .collect();
+    (!details.is_empty()).then(|| details.join(", "))
+}
+
+struct CommandContext;
+impl Command for CommandContext {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let app = app.borrow();
+        let context = context_messages(&app);
+        let verbose = args.contains(&"--verbose");
+        let args: Vec<&str> = args.into_iter().filter(|a| *a != "--verbose").collect();
+        let details = |message: &Message| {
+            if let Some(details) = reply_details(message).filter(|_| verbose) {
+                print!(
+                    "   {}\r\n",
+                    theme::format(&format!("[$dim]{}[$/]", details))
+                );
+            }
+        };
+        if args.is_empty() {
+            for (i, message) in context.iter().enumerate() {
+                print!(
+                    "#{} {}: {}\r\n",
+                    i + 1,
+                    message.role,
+                    message.content.lines().next().unwrap_or_default()
+                );
+                details(message);
+            }
+            return Ok(());
+        }
+
+        let Some(i) = message_index(&args, context.len()) else {
+            print!("No such message.\r\n");
+            return Err(CommandError::InvalidArguments);
+        };
+        app.render_messages(&context[i..=i]);
+        details(&context[i]);
+        Ok(())
+    }
+}
+
+struct CommandCopyMsg;
+impl Command for CommandCopyMsg {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        let context = context_messages(&app);
+        let Some(i) = message_index(&args, context.len()) else {
+            print!("No such message.\r\n");
+            return Err(CommandError::InvalidArguments);
+        };
+        let source = format!("message #{}", i + 1);
+        if let Err(e) = app.copy_to_clipboard(source, context[i].content.clone()) {
+            eprint!("Failed to copy to clipboard: {}\r\n", e);
+            return Err(CommandError::UpdateFailed);
+        }
+        print!("Copied to clipboard\r\n");
+        Ok(())
+    }
+}
+
+struct CommandBookmark;
+impl Command for CommandBookmark {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let app = app.borrow();
+        let context = context_messages(&app);
+        let index = match args.is_empty() {
+            true => context.iter().rposition(|m| m.role == "assistant"),
+            false => message_index(&args, context.len()),
+        };
+        let Some(i) = index.filter(|&i| context[i].role == "assistant") else {
+            print!("No such reply.\r\n");
+            return Err(CommandError::InvalidArguments);
+        };
+        let bookmark = Bookmark {
+            timestamp: stats::now(),
+            session: app.session_history.session_id().to_owned(),
+            model: context[i].metadata.model.clone(),
+            question: context[..i]
+                .iter()
+                .rfind(|m| m.role == "user")
+                .map(|m| m.content.clone()),
+            answer: context[i].content.clone(),
+        };
+        if let Err(e) = bookmarks::add(&bookmark) {
+            eprint!("Failed to save bookmark: {}\r\n", e);
+            return Err(CommandError::UpdateFailed);
+        }
+        print!("Bookmarked message #{}: {}\r\n", i + 1, bookmark.title());
+        Ok(())
+    }
+}
+
+struct CommandBookmarks;
+impl Command for CommandBookmarks {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        _app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let all = match bookmarks::load() {
+            Ok(x) => x,
+            Err(e) => {
+                eprint!("Failed to load bookmarks: {}\r\n", e);
+                return Err(CommandError::UpdateFailed);
+            }
+        };
+        let index = || message_index(&args[1..], all.len());
+        match args.first().copied() {
+            None => {
+                if all.is_empty() {
+                    print!("No bookmarks yet, add one with /bookmark.\r\n");
+                }
+                for (i, bookmark) in all.iter().enumerate() {
+                    print!(
+                        "#{} {} {}\r\n",
+                        i + 1,
+                        theme::format(&format!("[$dim]{}[$/]", stats::date(bookmark.timestamp))),
+                        bookmark.title()
+                    );
+                }
+            }
+            Some("show") => {
+                let Some(i) = index() else {
+                    print!("No such bookmark.\r\n");
+                    return Err(CommandError::InvalidArguments);
+                };
+                CLI::page(&bookmarks::markdown(&all[i..=i]));
+            }
+            Some("remove") => {
+                let Some(i) = index() else {
+                    print!("No such bookmark.\r\n");
+                    return Err(CommandError::InvalidArguments);
+                };
+                match bookmarks::remove(&all[i]) {
+                    Ok(true) => print!("Removed bookmark: {}\r\n", all[i].title()),
+                    Ok(false) => print!("That bookmark was already removed.\r\n"),
+                    Err(e) => {
+                        eprint!("Failed to save bookmarks: {}\r\n", e);
+                        return Err(CommandError::UpdateFailed);
+                    }
+                }
+            }
+            Some("export") => {
+                let path = args.get(1).copied().unwrap_or("chad-llm-bookmarks.md");
+                if let Err(e) = std::fs::write(path, bookmarks::markdown(&all)) {
+                    eprint!("Failed to write {}: {}\r\n", path, e);
+                    return Err(CommandError::UpdateFailed);
+                }
+                print!("Exported {} bookmark(s) to {}.\r\n", all.len(), path);
+            }
+            Some(_) => {
+                print!("Usage: /bookmarks [show N | remove N | export [file]]\r\n");
+                return Err(CommandError::InvalidArguments);
+            }
+        }
+        Ok(())
+    }
+}
+
+struct CommandHelp;
+impl Command for CommandHelp {
+    fn handle_command(
+        &self,
+        registry: &CommandRegistry,
+        _args: Vec<&str>,
+        _app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        print!("Available commands:\r\n");
+        let mut names = registry.get_available_commands();
+        names.sort_unstable();
+        for name in names {
+            print!(
+                "- {}: {}\r\n",
+                name,
+                registry.get_description(name).unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+}
+
+struct CommandSetModel;
+impl Command for CommandSetModel {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+
+        let mut args = args;
+        let refresh = match args.iter().position(|a| *a == "--refresh") {
+            Some(i) => {
+                args.remove(i);
+                true
+            }
+            None => false,
+        };
+        // The cached list opens the selector right away; a stale one is
+        // refreshed in the background for next time.
+        let cached = match refresh {
+            true => None,
+            false => openai::cached_models(),
+        };
+        let mut available_models: Vec<String> = match cached {
+            Some((models, stale)) => {
+                if stale {
+                    app.tokio_rt.spawn(openai::refresh_models());
+                }
+                models
+            }
+            None => match app.tokio_rt.block_on(openai::refresh_models()) {
+                Some(x) => x,
+                None => {
+                    print!("Failed to fetch available models from OpenAI.\r\n");
+                    openai::AVAILABLE_MODELS
+                        .iter()
+                        .map(|m| m.to_string())
+                        .collect()
+                }
+            },
+        };
+        available_models.extend(app.tokio_rt.block_on(openai::ollama_models()));
+
+        let model_idx;
+        if args.len() != 0 {
+            match available_models.iter().position(|r| r == args[0]) {
+                Some(x) => model_idx = x,
+                None => {
+                    return Err(CommandError::InvalidModel);
+                }
+            };
+        } else {
+            let initial = available_models
+                .iter()
+                .position(|r| *r == app.model)
+                .unwrap_or(0);
+            model_idx = *CLI::select(
+                &format!("Select a model to use. You are using {}.", app.model),
+                &available_models,
+                true,
+                &[initial],
+            )
+            .get(0)
+            .unwrap_or(&0);
+        }
+
+        if let Some(pin) = app
+            .pinned_model
+            .as_ref()
+            .filter(|p| p.model != available_models[model_idx])
+        {
+            print!("This session must use {} ({}).\r\n", pin.model, pin.reason);
+            return Err(CommandError::InvalidModel);
+        }
+        app.model = available_models[model_idx].clone();
+        print!("Model changed to {}!\r\n", app.model);
+        Ok(())
+    }
+}
+
+struct CommandSystemEdit;
+impl Command for CommandSystemEdit {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+
+        let available_prompts = app.system_prompts.get_available();
+        let name = match get_input_or_select(
+            &args,
+            &available_prompts
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>(),
+            "Select a system prompt:",
+            Some(&app.active_system_prompt),
+        ) {
+            Some(name) => name,
+            None => return Err(CommandError::Aborted),
+        };
+
+        let existing_data = match app.system_prompts.get(&name) {
+            Some(x) => x.clone(),
+            _ => "You are a helpful virtual assistant.".to_string(),
+        };
+
+        if let Some(inp) = CLI::editor(&existing_data) {
+            match app.system_prompts.update_or_create(&name, &inp) {
+                Ok(_) => {
+                    print!("Prompt updated.\r\n");
+                    Ok(())
+                }
+                Err(e) => {
+                    print!("Failed to update. Reason: {}\r\n", e);
+                    Err(CommandError::UpdateFailed)
+                }
+            }
+        } else {
+            Err(CommandError::Aborted)
+        }
+    }
+}
+
+struct CommandSystemRemove;
+impl Command for CommandSystemRemove {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+
+        let available_prompts = app.system_prompts.get_available();
+        let name = match get_input_or_select(
+            &args,
+            &available_prompts
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>(),
+            "Select a system prompt:",
+            Some(&app.active_system_prompt),
+        ) {
+            Some(name) => name,
+            None => return Err(CommandError::Aborted),
+        };
+
+        app.system_prompts.remove(&name);
+
+        Ok(())
+    }
+}
+
+struct CommandLang;
+impl Command for CommandLang {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        match args.first() {
+            None => match &app.reply_language {
+                Some(language) => print!(
+                    "Replies are written in {}.\r\n",
+                    instructions::language_name(language)
+                ),
+                None => print!("No reply language set.\r\n"),
+            },
+            Some(&"off") => {
+                app.reply_language = None;
+                app.apply_instructions();
+                print!("Replies follow the language of your messages again.\r\n");
+            }
+            Some(_) => {
+                let language = args.join(" ");
+                print!(
+                    "Replies will be written in {}.\r\n",
+                    instructions::language_name(&language)
+                );
+                app.reply_language = Some(language);
+                app.apply_instructions();
+            }
+        }
+        Ok(())
+    }
+}
+
+struct CommandNote;
+impl Command for CommandNote {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let app = app.borrow();
+        let mut all = notes::load();
+        match args.first().copied() {
+            Some("add") => {
+                let text = args[1..].join(" ");
+                let text = text.trim_matches(|c| c == '"' || c == '\'').trim();
+                if text.is_empty() {
+                    return Err(CommandError::InvalidArguments);
+                }
+                all.push(text.to_owned());
+            }
+            Some("rm" | "remove") => {
+                let Some(i) = message_index(&args[1..], all.len()) else {
+                    print!("No such note.\r\n");
+                    return Err(CommandError::InvalidArguments);
+                };
+                print!("Removed: {}\r\n", all.remove(i));
+            }
+            Some("list") | None => {
+                let (_, left_out) = notes::budgeted();
+                if all.is_empty() {
+                    print!("No notes for this project, add one with /note add <text>.\r\n");
+                }
+                for (i, note) in all.iter().enumerate() {
+                    match i < left_out {
+                        true => print!("#{} {} (left out)\r\n", i + 1, note),
+                        false => print!("#{} {}\r\n", i + 1, note),
+                    }
+                }
+                if left_out > 0 {
+                    print!(
+                        "{} older note(s) exceed the token budget (CHAD_LLM_NOTES_TOKENS) and are left out of the context.\r\n",
+                        left_out
+                    );
+                }
+                return Ok(());
+            }
+            Some(_) => {
+                print!("Usage: /note [add <text> | list | rm N]\r\n");
+                return Err(CommandError::InvalidArguments);
+            }
+        }
+
+        if let Err(e) = notes::save(&all) {
+            eprint!("Failed to save {}: {}\r\n", notes::NOTES_FILE, e);
+            return Err(CommandError::UpdateFailed);
+        }
+        app.apply_instructions();
+        if args.first() == Some(&"add") {
+            print!("Noted, {} note(s) for this project.\r\n", all.len());
+        }
+        Ok(())
+    }
+}
+
+struct CommandInstructions;
+impl Command for CommandInstructions {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        match args.first() {
+            Some(&"add") => {
+                let text = args[1..].join(" ");
+                if text.is_empty() {
+                    return Err(CommandError::InvalidArguments);
+                }
+                app.session_instructions.push(text);
+                app.apply_instructions();
+                print!("Added to this session's instructions.\r\n");
+            }
+            Some(&"clear") => {
+                app.session_instructions.clear();
+                app.apply_instructions();
+                print!("Cleared this session's instructions.\r\n");
+            }
+            Some(_) => {
+                print!("Usage: /instructions [add <text> | clear]\r\n");
+                return Err(CommandError::InvalidArguments);
+            }
+            None => {
+                let layers = app.instruction_layers();
+                if layers.is_empty() {
+                    print!("No instructions, the conversation has no system message.\r\n");
+                }
+                for layer in layers {
+                    print!(
+                        "{}\r\n{}\r\n\r\n",
+                        theme::format(&format!("[$cyan]# {}[$/]", layer.name)),
+                        layer.content.replace('\n', "\r\n")
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct CommandSystemUse;
+impl Command for CommandSystemUse {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
         app: Rc<RefCell<Application>>,
     ) -> Result<(), CommandError> {
         let mut app = app.borrow_mut();
@@ -382,14 +2123,1340 @@ impl Command for CommandSystemRemove {
             None => return Err(CommandError::Aborted),
         };
 
-        app.system_prompts.remove(&name);
+        if app.system_prompts.get(&name).is_none() {
+            return Err(CommandError::InvalidSystemPrompt);
+        }
+        app.active_system_prompt = name;
+        app.system_prompt_used = true;
+        app.apply_instructions();
+
+        Ok(())
+    }
+}
+
+struct CommandMarkdown;
+impl Command for CommandMarkdown {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        app.markdown = !app.markdown;
+        println!(
+            "Markdown parsing is now {}.",
+            match app.markdown {
+                true => "enabled",
+                false => "disabled",
+            }
+        );
+        return Ok(());
+    }
+}
+
+/// Lines of the clipboard shown before asking to stage it.
+const PASTE_PREVIEW_LINES: usize = 15;
+
+/// Shows the start of `contents` with its size and asks whether to stage it,
+/// edit it first or leave it. Returns what to stage.
+fn confirm_paste(mut contents: String) -> Option<String> {
+    loop {
+        let lines: Vec<&str> = contents.lines().collect();
+        print!(
+            "{}\r\n",
+            theme::format(&format!(
+                "[$dim]--- clipboard: {} lines, ~{} tokens ---[$/]",
+                lines.len(),
+                RateLimiter::estimate_tokens(&contents)
+            ))
+        );
+        for line in lines.iter().take(PASTE_PREVIEW_LINES) {
+            print!("{}\r\n", cli::fit_to_terminal(line));
+        }
+        let more = lines.len().saturating_sub(PASTE_PREVIEW_LINES);
+        let key = match more {
+            0 => CLI::read_key("[s]tage, [e]dit, [c]ancel?", "sec"),
+            _ => {
+                print!(
+                    "{}\r\n",
+                    theme::format(&format!("[$dim]... {} more lines[$/]", more))
+                );
+                CLI::read_key("[s]tage, [v]iew all, [e]dit, [c]ancel?", "svec")
+            }
+        };
+        match key {
+            Some('s') => return Some(contents),
+            Some('v') => CLI::page(&contents),
+            Some('e') => {
+                if let Some(edited) = CLI::editor(&contents) {
+                    if edited.trim().is_empty() {
+                        return None;
+                    }
+                    contents = edited;
+                }
+            }
+            _ => return None,
+        }
+    }
+}
+
+fn stage_clipboard(app: &Rc<RefCell<Application>>) -> Result<(), CommandError> {
+    match CLI::read_clipboard() {
+        Ok(contents) if contents.trim().is_empty() => {
+            print!("The clipboard is empty.\r\n");
+            Ok(())
+        }
+        Ok(contents) => {
+            let contents = match io::stdin().is_terminal() {
+                true => match confirm_paste(contents) {
+                    Some(contents) => contents,
+                    None => {
+                        print!("Not staged.\r\n");
+                        return Ok(());
+                    }
+                },
+                false => contents,
+            };
+            let part = StagedPart::Clipboard(contents);
+            print!("Staged {}.\r\n", part.summary());
+            app.borrow_mut().staging.add(part);
+            Ok(())
+        }
+        Err(err) => {
+            eprint!("Failed to read clipboard: {}\r\n", err);
+            Err(CommandError::ReadFailed)
+        }
+    }
+}
+
+struct CommandAdd;
+impl Command for CommandAdd {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let part = match args.first() {
+            Some(&"clipboard") => return stage_clipboard(&app),
+            Some(&"note") => {
+                let note = args[1..].join(" ");
+                let note = note.trim_matches('"').to_owned();
+                if note.is_empty() {
+                    return Err(CommandError::InvalidArguments);
+                }
+                StagedPart::Note(note)
+            }
+            Some(_) => {
+                let path = args.join(" ");
+                match std::fs::read_to_string(&path) {
+                    Ok(contents) => StagedPart::File { path, contents },
+                    Err(err) => {
+                        eprint!("Failed to read {}: {}\r\n", path, err);
+                        return Err(CommandError::ReadFailed);
+                    }
+                }
+            }
+            None => {
+                print!("Usage: /add <file> | /add clipboard | /add note <text>\r\n");
+                return Err(CommandError::InvalidArguments);
+            }
+        };
+
+        print!("Staged {}.\r\n", part.summary());
+        app.borrow_mut().staging.add(part);
+        Ok(())
+    }
+}
+
+const DIGEST_EXCERPTS: usize = 3;
+
+struct CommandDigest;
+impl Command for CommandDigest {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        match args.first() {
+            None => {
+                print!("Usage: /digest <file> | /digest find <query>\r\n");
+                Err(CommandError::InvalidArguments)
+            }
+            Some(&"find") => {
+                let query = args[1..].join(" ");
+                if query.is_empty() {
+                    return Err(CommandError::InvalidArguments);
+                }
+                let mut parts = Vec::new();
+                for document in &app.documents {
+                    for (index, contents) in document.search(&query, DIGEST_EXCERPTS) {
+                        parts.push(StagedPart::Excerpt {
+                            path: document.path.clone(),
+                            index,
+                            contents: contents.to_owned(),
+                        });
+                    }
+                }
+                if parts.is_empty() {
+                    print!("No digested document mentions that.\r\n");
+                    return Ok(());
+                }
+                for part in parts.into_iter().take(DIGEST_EXCERPTS) {
+                    print!("Staged {}.\r\n", part.summary());
+                    app.staging.add(part);
+                }
+                Ok(())
+            }
+            Some(_) => {
+                let path = args.join(" ");
+                let contents = match std::fs::read_to_string(&path) {
+                    Ok(x) => x,
+                    Err(err) => {
+                        eprint!("Failed to read {}: {}\r\n", path, err);
+                        return Err(CommandError::ReadFailed);
+                    }
+                };
+                // The chunks are sent without going through the message
+                // redaction, mask secrets before splitting so none is cut in two.
+                let contents = match app.redact {
+                    true => redact::redact_all(&contents),
+                    false => contents,
+                };
+                let document = digest::Document::new(&path, &contents);
+                let digest =
+                    match app
+                        .tokio_rt
+                        .block_on(digest::summarize(&app.model, &document, |step| {
+                            print!("{}…\r\n", step)
+                        })) {
+                        Ok(x) => x,
+                        Err(err) => {
+                            eprint!("Failed to digest {}: {}\r\n", path, err);
+                            return Err(CommandError::Aborted);
+                        }
+                    };
+
+                let message = format!(
+                    "Digest of `{}` ({} parts, the full text can be retrieved on request):\n\n{}",
+                    path,
+                    document.chunks.len(),
+                    digest
+                );
+                app.tokio_rt.block_on(async {
+                    app.context
+                        .lock()
+                        .await
+                        .push(Message::new("user", &message));
+                });
+                print!("{}\r\n", digest.replace('\n', "\r\n"));
+                print!(
+                    "Added the digest of {} to the context. Use /digest find <query> to stage parts of the full text.\r\n",
+                    path
+                );
+                app.documents.retain(|d| d.path != path);
+                app.documents.push(document);
+                Ok(())
+            }
+        }
+    }
+}
+
+struct CommandPaste;
+impl Command for CommandPaste {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        stage_clipboard(&app)
+    }
+}
+
+struct CommandStaged;
+impl Command for CommandStaged {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        if args.first() == Some(&"clear") {
+            app.staging.clear();
+            print!("Staging area cleared.\r\n");
+            return Ok(());
+        }
+
+        if app.staging.is_empty() {
+            print!("Nothing staged.\r\n");
+            return Ok(());
+        }
+
+        print!("Staged parts (sent with your next message):\r\n");
+        for (i, part) in app.staging.parts().iter().enumerate() {
+            print!("{}. {}\r\n", i + 1, part.summary());
+        }
+        Ok(())
+    }
+}
+
+struct CommandInject;
+impl Command for CommandInject {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let app = app.borrow_mut();
+
+        let messages = match args.first() {
+            Some(&role) if role == "user" || role == "assistant" => {
+                let content = args[1..].join(" ");
+                if content.is_empty() {
+                    return Err(CommandError::InvalidArguments);
+                }
+                vec![Message::new(role, &content)]
+            }
+            Some(&"template") if args.len() == 2 => {
+                // Session templates are JSON arrays of messages stored in
+                // the data directory, e.g. templates/few_shot.json.
+                let path = get_data_path(&format!("{}/{}.json", SESSION_TEMPLATES_DIR, args[1]));
+                let contents = match std::fs::read_to_string(&path) {
+                    Ok(x) => x,
+                    Err(err) => {
+                        eprint!("Failed to read {}: {}\r\n", path.display(), err);
+                        return Err(CommandError::ReadFailed);
+                    }
+                };
+                match serde_json::from_str::<Vec<Message>>(&contents) {
+                    Ok(x) => x,
+                    Err(err) => {
+                        eprint!("Failed to parse {}: {}\r\n", path.display(), err);
+                        return Err(CommandError::ReadFailed);
+                    }
+                }
+            }
+            _ => {
+                print!(
+                    "Usage: /inject user <text> | /inject assistant <text> | /inject template <name>\r\n"
+                );
+                return Err(CommandError::InvalidArguments);
+            }
+        };
+
+        let count = messages.len();
+        let shared_context = &app.context;
+        app.tokio_rt.block_on(async {
+            let mut locked = shared_context.lock().await;
+            locked.extend(messages);
+        });
+
+        print!("Injected {} message(s) into the context.\r\n", count);
+        Ok(())
+    }
+}
+
+struct CommandImport;
+impl Command for CommandImport {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let app = app.borrow_mut();
+        if args.is_empty() {
+            print!("Usage: /import <file>\r\n");
+            return Err(CommandError::InvalidArguments);
+        }
+
+        let path = args.join(" ");
+        let messages = match import::import_file(&path) {
+            Ok(x) => x,
+            Err(err) => {
+                eprint!("Failed to import {}: {}\r\n", path, err);
+                return Err(CommandError::ReadFailed);
+            }
+        };
+
+        let count = messages.len();
+        let system_prompt = app.system_instructions();
+        let shared_context = &app.context;
+        app.tokio_rt.block_on(async {
+            let mut locked = shared_context.lock().await;
+            *locked = messages;
+            // Keep the active system prompt unless the import brought its own.
+            if locked.first().is_none_or(|m| m.role != "system") {
+                openai::set_system_prompt(&mut locked, &system_prompt);
+            }
+        });
+
+        print!("Imported {} message(s) from {}.\r\n", count, path);
+        Ok(())
+    }
+}
+
+struct CommandExport;
+impl Command for CommandExport {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let app = app.borrow_mut();
+        if args.first() != Some(&"jsonl") {
+            print!("Usage: /export jsonl [all] [no_system] [file]\r\n");
+            return Err(CommandError::InvalidArguments);
+        }
+
+        let mut all = false;
+        let mut strip_system = false;
+        let mut path = "chad-llm-export.jsonl".to_owned();
+        for &arg in &args[1..] {
+            match arg {
+                "all" => all = true,
+                "no_system" => strip_system = true,
+                _ => path = arg.to_owned(),
+            }
+        }
+
+        let shared_context = &app.context;
+        let current = app.tokio_rt.block_on(async {
+            let locked = shared_context.lock().await;
+            locked.clone()
+        });
+
+        let mut conversations = vec![];
+        if all {
+            // Older sessions only live in the transcripts.
+            match app.session_history.load_earlier_sessions() {
+                Ok(sessions) => conversations.extend(sessions),
+                Err(e) => eprint!("Failed to load history: {}\r\n", e),
+            }
+        }
+        conversations.push(current);
+
+        let jsonl = match export::to_jsonl(&conversations, strip_system) {
+            Ok(x) => x,
+            Err(e) => {
+                eprint!("Failed to serialize conversation: {}\r\n", e);
+                return Err(CommandError::UpdateFailed);
+            }
+        };
+        if let Err(e) = std::fs::write(&path, jsonl) {
+            eprint!("Failed to write {}: {}\r\n", path, e);
+            return Err(CommandError::UpdateFailed);
+        }
+
+        print!("Exported to {}.\r\n", path);
+        Ok(())
+    }
+}
+
+struct CommandDebugLastCmd;
+impl Command for CommandDebugLastCmd {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let Some(last) = shell_hook::load() else {
+            print!(
+                "No shell command found. Add eval \"$(chad-gpt shell-hook bash)\" (or zsh, fish) to your shell's rc file.\r\n"
+            );
+            return Err(CommandError::ReadFailed);
+        };
+        print!("$ {}\r\n", last.command.replace('\n', "\r\n"));
+        match last.status {
+            Some(status) => print!("Exited with status {}.\r\n", status),
+            None => print!("Exit status unknown, the shell hook isn't set up.\r\n"),
+        }
+
+        let mut app = app.borrow_mut();
+        let dir = last
+            .dir
+            .clone()
+            .filter(|dir| dir.is_dir())
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_default();
+        let output = match CLI::read_key(
+            &format!(
+                "Run it again in {} to capture its output? [y/n]",
+                dir.display()
+            ),
+            "yn",
+        ) {
+            Some('y') => {
+                let timeout = runner::timeout();
+                match app
+                    .tokio_rt
+                    .block_on(runner::run_shell(&last.command, &dir, timeout))
+                {
+                    Ok(output) => Some(output),
+                    Err(e) => {
+                        eprint!("Failed to run the command: {}\r\n", e);
+                        None
+                    }
+                }
+            }
+            Some(_) => None,
+            None => return Err(CommandError::Aborted),
+        };
+
+        let environment = shell_hook::environment(&last.command);
+        app.send_message(&shell_hook::message(&last, output.as_ref(), &environment));
+        print!("\r\n");
+        Ok(())
+    }
+}
+
+const PR_PROMPT: &str = "Write a pull request title and description for the branch \
+described below by its commits and the files it changes. Put the title on the first line, \
+then a blank line, then the description in markdown: what changes and why, then anything \
+reviewers should check. Reply with the title and description only.";
+
+struct CommandPr;
+impl Command for CommandPr {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let Some(base) = args
+            .first()
+            .map(|b| b.to_string())
+            .or_else(git::default_branch)
+        else {
+            print!("Can't tell which branch to compare with, use /pr <base>.\r\n");
+            return Err(CommandError::InvalidArguments);
+        };
+        let range = format!("{}..HEAD", base);
+        let log = git::git(&["log", "--format=- %s%n%n%b", &range]);
+        let stat = git::git(&["diff", "--stat", &format!("{}...HEAD", base)]);
+        let (log, stat) = match (log, stat) {
+            (Ok(log), Ok(stat)) => (log, stat),
+            (Err(e), _) | (_, Err(e)) => {
+                eprint!("Failed to read the branch from git: {}\r\n", e);
+                return Err(CommandError::ReadFailed);
+            }
+        };
+        if log.trim().is_empty() {
+            print!(
+                "There are no commits on this branch that aren't on {}.\r\n",
+                base
+            );
+            return Err(CommandError::Aborted);
+        }
+
+        let mut app = app.borrow_mut();
+        let input = format!("Commits:\n{}\nChanged files:\n{}", log, stat);
+        let Some(draft) = app.side_request(Some(PR_PROMPT), &input) else {
+            return Err(CommandError::UpdateFailed);
+        };
+        app.last_result = Some(draft.clone());
+        print!("\r\n");
+
+        match CLI::read_key("[c]opy, [g]h pr create, [q]uit?", "cgq") {
+            Some('c') => {
+                if let Err(e) = app.copy_to_clipboard("pull request draft".to_owned(), draft) {
+                    eprint!("Failed to copy to clipboard: {}\r\n", e);
+                    return Err(CommandError::UpdateFailed);
+                }
+                print!("Copied the draft to the clipboard.\r\n");
+            }
+            Some('g') => {
+                let (title, body) = draft.split_once('\n').unwrap_or((&draft, ""));
+                let title = title.trim().trim_start_matches('#').trim();
+                let create = tempfile::NamedTempFile::new()
+                    .and_then(|file| {
+                        std::fs::write(file.path(), body.trim())?;
+                        Ok(file)
+                    })
+                    .and_then(|file| {
+                        std::process::Command::new("gh")
+                            .args(["pr", "create", "--title", title, "--base"])
+                            .arg(base.strip_prefix("origin/").unwrap_or(&base))
+                            .arg("--body-file")
+                            .arg(file.path())
+                            .status()
+                    });
+                match create {
+                    Ok(status) if status.success() => {}
+                    Ok(status) => {
+                        print!("gh exited with {}.\r\n", status);
+                        return Err(CommandError::UpdateFailed);
+                    }
+                    Err(e) => {
+                        eprint!("Failed to run gh: {}\r\n", e);
+                        return Err(CommandError::UpdateFailed);
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+const SPLIT_PROMPT: &str = "You are given the staged changes of a git repository as \
+numbered hunks. Group them into a few logical commits, each one coherent and in an order that \
+builds. Reply with JSON only, no code fences: {\"commits\": [{\"message\": \"<commit message>\", \
+\"hunks\": [<hunk numbers>]}]}. Use every hunk exactly once.";
+
+/// Commit messages and hunk indices, from the model's plan. Hunks it left
+/// out get a commit of their own at the end.
+fn split_plan(reply: &str, hunks: usize) -> Option<Vec<(String, Vec<usize>)>> {
+    let json = reply
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```");
+    let plan: serde_json::Value = serde_json::from_str(json).ok()?;
+    let mut used = vec![false; hunks];
+    let mut commits = Vec::new();
+    for commit in plan.get("commits")?.as_array()? {
+        let message = commit.get("message")?.as_str()?.trim().to_owned();
+        let mut indices: Vec<usize> = commit
+            .get("hunks")?
+            .as_array()?
+            .iter()
+            .filter_map(|n| n.as_u64()?.checked_sub(1).map(|i| i as usize))
+            .filter(|&i| i < hunks && !std::mem::replace(&mut used[i], true))
+            .collect();
+        indices.sort();
+        if !indices.is_empty() {
+            commits.push((message, indices));
+        }
+    }
+    let rest: Vec<usize> = (0..hunks).filter(|&i| !used[i]).collect();
+    if !rest.is_empty() {
+        commits.push(("Remaining changes".to_owned(), rest));
+    }
+    Some(commits)
+}
+
+struct CommandSplitCommits;
+impl Command for CommandSplitCommits {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let diff = match git::git(&["diff", "--cached"]) {
+            Ok(diff) => diff,
+            Err(e) => {
+                eprint!("Failed to read the staged changes: {}\r\n", e);
+                return Err(CommandError::ReadFailed);
+            }
+        };
+        let hunks = git::hunks(&diff);
+        if hunks.is_empty() {
+            print!("Nothing is staged.\r\n");
+            return Err(CommandError::Aborted);
+        }
+
+        let app = app.borrow();
+        let numbered: String = hunks
+            .iter()
+            .enumerate()
+            .map(|(i, h)| format!("### Hunk {} ({})\n{}\n", i + 1, h.path, h.body))
+            .collect();
+        print!("Planning commits for {} hunks…\r\n", hunks.len());
+        let reply =
+            match app
+                .tokio_rt
+                .block_on(openai::complete(&app.model, Some(SPLIT_PROMPT), &numbered))
+            {
+                Ok(x) => x,
+                Err(e) => {
+                    eprint!("Failed to plan the commits: {}\r\n", e);
+                    return Err(CommandError::UpdateFailed);
+                }
+            };
+        let Some(plan) = split_plan(&reply, hunks.len()) else {
+            print!(
+                "The model didn't reply with a plan:\r\n{}\r\n",
+                reply.replace('\n', "\r\n")
+            );
+            return Err(CommandError::UpdateFailed);
+        };
+
+        for (n, (message, indices)) in plan.iter().enumerate() {
+            let mut files: Vec<&str> = indices.iter().map(|&i| hunks[i].path.as_str()).collect();
+            files.dedup();
+            print!("{}. {}\r\n", n + 1, message);
+            print!(
+                "{}\r\n",
+                theme::format(&format!(
+                    "[$dim]   {} hunk(s) in {}[$/]",
+                    indices.len(),
+                    files.join(", ")
+                ))
+            );
+        }
+        if CLI::read_key("Make these commits? [y/n]", "yn") != Some('y') {
+            return Err(CommandError::Aborted);
+        }
+
+        // Unstage everything, then stage and commit one group at a time.
+        // Whatever isn't committed is staged again at the end.
+        if let Err(e) = git::git(&["reset", "-q"]) {
+            eprint!("Failed to unstage the changes: {}\r\n", e);
+            return Err(CommandError::UpdateFailed);
+        }
+        let mut committed = vec![false; hunks.len()];
+        for (message, indices) in &plan {
+            let group: Vec<&git::Hunk> = indices.iter().map(|&i| &hunks[i]).collect();
+            if let Err(e) = git::git_with_input(&["apply", "--cached", "-"], &git::patch(&group)) {
+                eprint!("Failed to stage \"{}\": {}\r\n", message, e);
+                break;
+            }
+            print!("\r\n{}\r\n", message);
+            if let Ok(stat) = git::git(&["diff", "--cached", "--stat"]) {
+                print!("{}", stat.replace('\n', "\r\n"));
+            }
+            let mut message = message.clone();
+            let key = loop {
+                match CLI::read_key("[c]ommit, [e]dit message, [s]kip, [q]uit?", "cesq") {
+                    Some('e') => {
+                        if let Some(edited) = CLI::editor(&message) {
+                            message = edited.trim().to_owned();
+                            print!("{}\r\n", message.replace('\n', "\r\n"));
+                        }
+                    }
+                    key => break key,
+                }
+            };
+            if key == Some('c') {
+                match git::git(&["commit", "-q", "-m", &message]) {
+                    Ok(_) => indices.iter().for_each(|&i| committed[i] = true),
+                    Err(e) => eprint!("Failed to commit: {}\r\n", e),
+                }
+            }
+            let _ = git::git(&["reset", "-q"]);
+            if !matches!(key, Some('c') | Some('s')) {
+                break;
+            }
+        }
+
+        let rest: Vec<&git::Hunk> = (0..hunks.len())
+            .filter(|&i| !committed[i])
+            .map(|i| &hunks[i])
+            .collect();
+        if !rest.is_empty() {
+            if let Err(e) = git::git_with_input(&["apply", "--cached", "-"], &git::patch(&rest)) {
+                eprint!("Failed to stage the remaining changes again: {}\r\n", e);
+                return Err(CommandError::UpdateFailed);
+            }
+            print!("{} hunk(s) left staged.\r\n", rest.len());
+        }
+        Ok(())
+    }
+}
+
+/// Default socket for `/share`.
+const SHARE_SOCKET: &str = "share.sock";
+
+struct CommandShare;
+impl Command for CommandShare {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        if args.first() == Some(&"stop") {
+            match app.share.take() {
+                Some(share) => print!("Stopped sharing on {}.\r\n", share.address),
+                None => print!("This session isn't shared.\r\n"),
+            }
+            return Ok(());
+        }
+        if let Some(share) = &app.share {
+            if !args.is_empty() {
+                print!("Already sharing, run /share stop first.\r\n");
+                return Err(CommandError::InvalidArguments);
+            }
+            print!(
+                "Sharing on {} ({}), {} guest(s) attached.\r\n",
+                share.address,
+                match share.writable {
+                    true => "guests can send prompts",
+                    false => "read-only",
+                },
+                share.guests()
+            );
+            return Ok(());
+        }
+
+        let writable = args.contains(&"--write");
+        let address = match args.iter().find(|a| !a.starts_with("--")) {
+            Some(address) => address.to_string(),
+            None => get_data_path(SHARE_SOCKET).display().to_string(),
+        };
+        let context = Arc::clone(&app.context);
+        let share = match app
+            .tokio_rt
+            .block_on(share::Share::host(&address, context, writable))
+        {
+            Ok(share) => share,
+            Err(e) => {
+                eprint!("Failed to share on {}: {}\r\n", address, e);
+                return Err(CommandError::InvalidArguments);
+            }
+        };
+        print!(
+            "Sharing this session {}. Others can follow it with:\r\n  chad-gpt attach {}\r\n",
+            match writable {
+                true => "(guests can send prompts)",
+                false => "read-only",
+            },
+            address
+        );
+        if share::is_tcp(&address) {
+            print!(
+                "{}\r\n",
+                theme::format("[$yellow]TCP shares are unauthenticated, anyone who can reach the port can attach.[$/]")
+            );
+        }
+        app.share = Some(share);
+        Ok(())
+    }
+}
+
+struct CommandScaffold;
+impl Command for CommandScaffold {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        mut args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let force = match args.iter().position(|a| *a == "--force") {
+            Some(i) => {
+                args.remove(i);
+                true
+            }
+            None => false,
+        };
+        let [dir] = args.as_slice() else {
+            return Err(CommandError::InvalidArguments);
+        };
+        let app = app.borrow();
+        let Some(reply) = context_messages(&app)
+            .into_iter()
+            .rev()
+            .find(|m| m.role == "assistant")
+        else {
+            print!("There is no reply to scaffold.\r\n");
+            return Err(CommandError::Aborted);
+        };
+        let files = scaffold::files(&reply.content);
+        if files.is_empty() {
+            print!("No file names found in the last reply.\r\n");
+            return Err(CommandError::Aborted);
+        }
+
+        let root = std::path::Path::new(dir);
+        let mut written = Vec::new();
+        for file in &files {
+            let path = root.join(&file.path);
+            if path.exists() && !force {
+                print!("Skipped {}, it exists (use --force).\r\n", path.display());
+                continue;
+            }
+            let result = match path.parent() {
+                Some(parent) => std::fs::create_dir_all(parent),
+                None => Ok(()),
+            }
+            .and_then(|_| std::fs::write(&path, &file.content));
+            match result {
+                Ok(()) => written.push(file.path.as_str()),
+                Err(e) => eprint!("Failed to write {}: {}\r\n", path.display(), e),
+            }
+        }
+
+        print!("{}/\r\n", dir.trim_end_matches('/'));
+        for line in scaffold::tree(&written) {
+            print!("  {}\r\n", line);
+        }
+        print!("Wrote {} of {} file(s).\r\n", written.len(), files.len());
+        Ok(())
+    }
+}
+
+struct CommandUsage;
+impl Command for CommandUsage {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        _app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let stats = *openai::USAGE.lock().unwrap();
+        let hit_rate = if stats.prompt_tokens > 0 {
+            stats.cached_tokens as f64 * 100.0 / stats.prompt_tokens as f64
+        } else {
+            0.0
+        };
+
+        print!("Requests:          {}\r\n", stats.requests);
+        print!("Prompt tokens:     {}\r\n", stats.prompt_tokens);
+        print!(
+            "Cached tokens:     {} ({:.1}% cache hits)\r\n",
+            stats.cached_tokens, hit_rate
+        );
+        print!("Completion tokens: {}\r\n", stats.completion_tokens);
+        Ok(())
+    }
+}
+
+struct CommandFallback;
+impl Command for CommandFallback {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        match args.first() {
+            None => {}
+            Some(&"clear") => app.fallback_models.clear(),
+            Some(_) => app.fallback_models = args.iter().map(|m| m.to_string()).collect(),
+        }
+
+        if app.fallback_models.is_empty() {
+            print!("No fallback models configured.\r\n");
+        } else {
+            print!(
+                "Fallback chain: {} -> {}\r\n",
+                app.model,
+                app.fallback_models.join(" -> ")
+            );
+        }
+        Ok(())
+    }
+}
+
+struct CommandQueue;
+impl Command for CommandQueue {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        match args.first() {
+            None => {}
+            Some(&"on") => app.queue_offline = true,
+            Some(&"off") => app.queue_offline = false,
+            Some(&"clear") => app.offline_queue.clear(),
+            Some(_) => return Err(CommandError::InvalidArguments),
+        }
+
+        print!(
+            "Offline queueing is {}. {} message(s) pending{}.\r\n",
+            match app.queue_offline {
+                true => "enabled",
+                false => "disabled",
+            },
+            app.offline_queue.len(),
+            match app.offline {
+                true => ", currently offline",
+                false => "",
+            }
+        );
+        for (i, input) in app.offline_queue.iter().enumerate() {
+            print!("{}. {}\r\n", i + 1, input);
+        }
+        Ok(())
+    }
+}
+
+struct CommandContinue;
+impl Command for CommandContinue {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        if !app.truncated {
+            print!("The last reply was not cut off, continuing anyway.\r\n");
+        }
+        match app.continue_response() {
+            true => Ok(()),
+            false => Err(CommandError::Aborted),
+        }
+    }
+}
+
+struct CommandRetry;
+impl Command for CommandRetry {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        match app.retry_response() {
+            true => {
+                print!("\r\nUse /diff to see what changed.\r\n");
+                Ok(())
+            }
+            false => Err(CommandError::Aborted),
+        }
+    }
+}
+
+struct CommandDiff;
+impl Command for CommandDiff {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let app = app.borrow();
+        let current = app.tokio_rt.block_on(async {
+            let locked = app.context.lock().await;
+            locked
+                .last()
+                .filter(|m| m.role == "assistant")
+                .map(|m| m.content.clone())
+        });
+        let (Some(previous), Some(current)) = (&app.previous_reply, current) else {
+            print!("Nothing to compare, use /retry first.\r\n");
+            return Err(CommandError::Aborted);
+        };
+
+        let diff = similar::TextDiff::from_words(previous.as_str(), current.as_str());
+        let palette = theme::palette();
+        let mut out = String::new();
+        for change in diff.iter_all_changes() {
+            match change.tag() {
+                similar::ChangeTag::Equal => out.push_str(change.value()),
+                similar::ChangeTag::Delete => {
+                    out.push_str(&format!("{}{}\x1b[0m", palette.removed, change.value()))
+                }
+                similar::ChangeTag::Insert => {
+                    out.push_str(&format!("{}{}\x1b[0m", palette.added, change.value()))
+                }
+            }
+        }
+        print!("{}\r\n", out.replace('\n', "\r\n"));
+        let ratio = diff.ratio();
+        print!(
+            "{}\r\n",
+            theme::format(&format!("[$dim]{:.0}% similar[$/]", ratio * 100.0))
+        );
+        Ok(())
+    }
+}
+
+struct CommandRedact;
+impl Command for CommandRedact {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        app.redact = !app.redact;
+        print!(
+            "Secret redaction is now {}.\r\n",
+            match app.redact {
+                true => "enabled",
+                false => "disabled",
+            }
+        );
+        Ok(())
+    }
+}
+
+struct CommandConstrain;
+impl Command for CommandConstrain {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        let Some(&path) = args.first() else {
+            match &app.constraint {
+                Some(Constraint::Grammar(_)) => print!("Replies follow a GBNF grammar.\r\n"),
+                Some(Constraint::JsonSchema(_)) => print!("Replies follow a JSON schema.\r\n"),
+                None => print!("Replies are not constrained.\r\n"),
+            }
+            return Ok(());
+        };
+        if path == "off" {
+            app.constraint = None;
+            print!("Replies are no longer constrained.\r\n");
+            return Ok(());
+        }
+
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            eprint!("Failed to read {}: {}\r\n", path, e);
+            CommandError::ReadFailed
+        })?;
+        app.constraint = Some(match path.ends_with(".json") {
+            true => Constraint::JsonSchema(serde_json::from_str(&contents).map_err(|e| {
+                eprint!("Invalid JSON schema: {}\r\n", e);
+                CommandError::InvalidArguments
+            })?),
+            false => Constraint::Grammar(contents),
+        });
+        print!("Replies are now constrained by {}.\r\n", path);
+        Ok(())
+    }
+}
+
+struct CommandConfidence;
+impl Command for CommandConfidence {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        if args.first() == Some(&"last") {
+            if app.last_logprobs.is_empty() {
+                print!("No token probabilities for the last reply, enable /confidence first.\r\n");
+                return Ok(());
+            }
+            // The least certain tokens, in reply order.
+            let mut uncertain: Vec<&TokenLogprob> = app.last_logprobs.iter().collect();
+            uncertain.sort_by(|a, b| a.logprob.total_cmp(&b.logprob));
+            uncertain.truncate(20);
+            uncertain.retain(|t| t.probability() < 0.9);
+            if uncertain.is_empty() {
+                print!("The model was sure about every token.\r\n");
+                return Ok(());
+            }
+            for token in app.last_logprobs.iter() {
+                if !uncertain.iter().any(|u| std::ptr::eq(*u, token)) {
+                    continue;
+                }
+                let alternatives: Vec<String> = token
+                    .top
+                    .iter()
+                    .filter(|(t, _)| *t != token.token)
+                    .map(|(t, logprob)| format!("{:?} {:.0}%", t, logprob.exp() * 100.0))
+                    .collect();
+                print!(
+                    "{}{:?} {:.0}%\x1b[0m  {}\r\n",
+                    ConfidenceRenderer::color(token.probability()),
+                    token.token,
+                    token.probability() * 100.0,
+                    alternatives.join(", ")
+                );
+            }
+            return Ok(());
+        }
+
+        app.confidence = !app.confidence;
+        print!(
+            "Confidence coloring is now {}.\r\n",
+            match app.confidence {
+                true => "enabled",
+                false => "disabled",
+            }
+        );
+        Ok(())
+    }
+}
+
+struct CommandLineNumbers;
+impl Command for CommandLineNumbers {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        app.line_numbers = !app.line_numbers;
+        print!(
+            "Code block line numbers are now {}.\r\n",
+            match app.line_numbers {
+                true => "enabled",
+                false => "disabled",
+            }
+        );
+        Ok(())
+    }
+}
+
+struct CommandAutoCopy;
+impl Command for CommandAutoCopy {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        app.auto_copy = !app.auto_copy;
+        print!(
+            "Automatic copying of single code blocks is now {}.\r\n",
+            match app.auto_copy {
+                true => "enabled",
+                false => "disabled",
+            }
+        );
+        Ok(())
+    }
+}
+
+struct CommandMouse;
+impl Command for CommandMouse {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        _app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let enabled = !cli::MOUSE_ENABLED.fetch_xor(true, Ordering::Relaxed);
+        print!(
+            "Mouse support in selection menus is now {}.\r\n",
+            match enabled {
+                true => "enabled",
+                false => "disabled",
+            }
+        );
+        Ok(())
+    }
+}
+
+struct CommandTokenWarning;
+impl Command for CommandTokenWarning {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        if let Some(arg) = args.first() {
+            match arg.parse() {
+                Ok(x) => app.token_warning = x,
+                Err(_) => return Err(CommandError::InvalidArguments),
+            }
+        }
+
+        match app.token_warning {
+            0 => print!("Token warning is disabled.\r\n"),
+            n => print!("Messages over {} tokens ask for confirmation.\r\n", n),
+        }
+        Ok(())
+    }
+}
+
+struct CommandPreset(&'static str);
+impl Command for CommandPreset {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        let Some(&sampling) = app.presets.get(self.0) else {
+            return Err(CommandError::InvalidArguments);
+        };
+
+        // A preset without a seed of its own keeps the one in use.
+        app.sampling = Sampling {
+            seed: sampling.seed.or(app.sampling.seed),
+            ..sampling
+        };
+        app.active_preset = Some(self.0.to_owned());
+        print!(
+            "Using the {} preset (temperature {}{}).\r\n",
+            self.0,
+            sampling.temperature,
+            sampling
+                .top_p
+                .map(|p| format!(", top_p {}", p))
+                .unwrap_or_default()
+        );
+        Ok(())
+    }
+}
+
+const TRANSLATE_PROMPT: &str =
+    "You are a translator. Translate the text the user sends to {language}. \
+Keep the formatting, code blocks and tone. Reply with the translation only.";
+const PROOFREAD_PROMPT: &str =
+    "You are a proofreader. Fix the grammar, spelling and punctuation of \
+the text the user sends, changing as little as possible. Keep the formatting and code blocks \
+as they are. Reply with the corrected text only.";
+
+/// The text to rewrite: the clipboard with the `clipboard` argument,
+/// otherwise the last message of the conversation.
+fn rewrite_source(app: &Application, args: &[&str]) -> Result<String, CommandError> {
+    if args.contains(&"clipboard") {
+        return CLI::read_clipboard().map_err(|e| {
+            eprint!("Failed to read clipboard: {}\r\n", e);
+            CommandError::ReadFailed
+        });
+    }
+    let shared_context = &app.context;
+    let last = app.tokio_rt.block_on(async {
+        let locked = shared_context.lock().await;
+        locked
+            .iter()
+            .rev()
+            .find(|m| m.role != "system")
+            .map(|m| m.content.clone())
+    });
+    last.ok_or_else(|| {
+        print!("There is no message yet.\r\n");
+        CommandError::InvalidArguments
+    })
+}
 
+struct CommandTranslate;
+impl Command for CommandTranslate {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        let Some(language) = args.first().filter(|a| **a != "clipboard") else {
+            print!("Usage: /translate <language> [clipboard]\r\n");
+            return Err(CommandError::InvalidArguments);
+        };
+        let text = rewrite_source(&app, &args[1..])?;
+        let system = TRANSLATE_PROMPT.replace("{language}", language);
+        app.last_result = app.side_request(Some(&system), &text);
         Ok(())
     }
 }
 
-struct CommandSystemUse;
-impl Command for CommandSystemUse {
+struct CommandProofread;
+impl Command for CommandProofread {
     fn handle_command(
         &self,
         _registry: &CommandRegistry,
@@ -397,46 +3464,255 @@ impl Command for CommandSystemUse {
         app: Rc<RefCell<Application>>,
     ) -> Result<(), CommandError> {
         let mut app = app.borrow_mut();
+        let text = rewrite_source(&app, &args)?;
+        app.last_result = app.side_request(Some(PROOFREAD_PROMPT), &text);
+        Ok(())
+    }
+}
 
-        let available_prompts = app.system_prompts.get_available();
-        let name = match get_input_or_select(
-            &args,
-            &available_prompts
-                .iter()
-                .map(|s| s.as_str())
-                .collect::<Vec<_>>(),
-            "Select a system prompt:",
-            Some(&app.active_system_prompt),
-        ) {
-            Some(name) => name,
-            None => return Err(CommandError::Aborted),
-        };
+struct CommandReplay;
+impl Command for CommandReplay {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let app = app.borrow();
+        let shared_context = &app.context;
+        let messages = app
+            .tokio_rt
+            .block_on(async { shared_context.lock().await.clone() });
+        if !app.restored_history.is_empty() {
+            app.render_messages(&app.restored_history);
+            print!("--- current session ---\r\n");
+        }
+        if messages.iter().all(|m| m.role == "system") {
+            print!("The conversation is empty.\r\n");
+            return Ok(());
+        }
+        app.render_messages(&messages);
+        Ok(())
+    }
+}
 
-        let contents = match app.system_prompts.get(&name) {
-            Some(x) => Some(x.clone()),
-            None => None,
+struct CommandPersonas;
+impl Command for CommandPersonas {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        _args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let app = app.borrow();
+        if app.personas.is_empty() {
+            print!(
+                "No personas defined, add them to {}.\r\n",
+                get_data_path(crate::application::PERSONAS_FILE).display()
+            );
+            return Ok(());
+        }
+        let mut names: Vec<&String> = app.personas.keys().collect();
+        names.sort();
+        for name in names {
+            let persona = &app.personas[name];
+            print!(
+                "@{} ({}): {}\r\n",
+                name,
+                persona.model.as_deref().unwrap_or(&app.model),
+                persona.system_prompt.lines().next().unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+}
+
+struct CommandPreview;
+impl Command for CommandPreview {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let app = app.borrow();
+        let input = app.interpolate(&args.join(" "));
+        let input = match app.staging.is_empty() {
+            true => input,
+            false => app.staging.build(&input),
         };
-        let contents = match contents {
-            Some(x) => {
-                app.active_system_prompt = name;
-                x
+        let input = match app.redact {
+            true => {
+                let findings = redact::find_secrets(&input);
+                redact::redact(&input, &findings.iter().collect::<Vec<_>>())
             }
-            None => return Err(CommandError::InvalidSystemPrompt),
+            false => input,
         };
+        app.preview(&input);
+        Ok(())
+    }
+}
 
-        let shared_context = &app.context;
-        let _ = app.tokio_rt.block_on(async {
-            let mut locked = shared_context.lock().await;
-            openai::set_system_prompt(&mut locked, &contents);
-            locked.clone()
+struct CommandSweep;
+impl Command for CommandSweep {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let (Some(&parameter), Some(values)) = (args.first(), args.get(1)) else {
+            print!("Usage: /sweep temperature|top_p <v1,v2,...>\r\n");
+            return Err(CommandError::InvalidArguments);
+        };
+        if parameter != "temperature" && parameter != "top_p" {
+            return Err(CommandError::InvalidArguments);
+        }
+        let Ok(values) = values
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+        else {
+            return Err(CommandError::InvalidArguments);
+        };
+
+        let app = app.borrow();
+        let mut messages = context_messages(&app);
+        let Some(last) = messages.iter().rposition(|m| m.role == "user") else {
+            print!("There is no prompt to re-run.\r\n");
+            return Err(CommandError::Aborted);
+        };
+        let prompt = messages[last].content.clone();
+        messages.truncate(last);
+
+        print!("Running {} variants…\r\n", values.len());
+        let requests = values.iter().map(|&value| {
+            let mut sampling = app.sampling;
+            match parameter {
+                "temperature" => sampling.temperature = value,
+                _ => sampling.top_p = Some(value),
+            }
+            openai::complete_in(&app.model, messages.clone(), &prompt, sampling)
         });
+        let replies = app
+            .tokio_rt
+            .block_on(futures_util::future::join_all(requests));
 
+        for (value, reply) in values.iter().zip(replies) {
+            print!(
+                "{}\r\n",
+                theme::format(&format!("[$cyan]--- {} = {} ---[$/]", parameter, value))
+            );
+            match reply {
+                Ok(reply) => app.render_messages(&[Message::new("assistant", &reply)]),
+                Err(e) => print!("Request failed: {}\r\n\r\n", e),
+            }
+        }
         Ok(())
     }
 }
 
-struct CommandMarkdown;
-impl Command for CommandMarkdown {
+struct CommandReflect;
+impl Command for CommandReflect {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        let state = |on: bool| match on {
+            true => "enabled",
+            false => "disabled",
+        };
+        match args.first() {
+            None => match app.reflect_last() {
+                true => Ok(()),
+                false => Err(CommandError::Aborted),
+            },
+            Some(&"auto") => {
+                app.reflect = !app.reflect;
+                print!(
+                    "Reflecting on every reply is now {}.\r\n",
+                    state(app.reflect)
+                );
+                Ok(())
+            }
+            Some(&"critique") => {
+                app.show_critique = !app.show_critique;
+                print!(
+                    "Showing the critique is now {}.\r\n",
+                    state(app.show_critique)
+                );
+                Ok(())
+            }
+            Some(_) => Err(CommandError::InvalidArguments),
+        }
+    }
+}
+
+const DEFAULT_ROUNDTABLE_TURNS: usize = 2;
+const DEFAULT_MODERATOR: &str = "moderator";
+
+struct CommandRoundtable;
+impl Command for CommandRoundtable {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        mut args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut turns = std::env::var("CHAD_LLM_ROUNDTABLE_TURNS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_ROUNDTABLE_TURNS);
+        if args.first() == Some(&"--turns") {
+            turns = match args.get(1).and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => n,
+                _ => return Err(CommandError::InvalidArguments),
+            };
+            args.drain(..2);
+        }
+        if args.is_empty() {
+            return Err(CommandError::InvalidArguments);
+        }
+        let topic = args.join(" ");
+
+        let mut app = app.borrow_mut();
+        let moderator =
+            std::env::var("CHAD_LLM_MODERATOR").unwrap_or_else(|_| DEFAULT_MODERATOR.to_owned());
+        let participants: Vec<String> = match std::env::var("CHAD_LLM_ROUNDTABLE") {
+            Ok(names) => names.split(',').map(|n| n.trim().to_owned()).collect(),
+            Err(_) => {
+                let mut names: Vec<String> = app
+                    .personas
+                    .keys()
+                    .filter(|n| **n != moderator)
+                    .cloned()
+                    .collect();
+                names.sort();
+                names
+            }
+        };
+        if let Some(unknown) = participants.iter().find(|n| !app.personas.contains_key(*n)) {
+            print!("Unknown persona {}, see /personas.\r\n", unknown);
+            return Err(CommandError::InvalidArguments);
+        }
+        if participants.len() < 2 {
+            print!("A round table needs at least two personas, see /personas.\r\n");
+            return Err(CommandError::Aborted);
+        }
+
+        let moderator = app.personas.contains_key(&moderator).then_some(moderator);
+        match app.roundtable(&topic, &participants, moderator.as_deref(), turns) {
+            true => Ok(()),
+            false => Err(CommandError::Aborted),
+        }
+    }
+}
+
+struct CommandFollowups;
+impl Command for CommandFollowups {
     fn handle_command(
         &self,
         _registry: &CommandRegistry,
@@ -444,14 +3720,175 @@ impl Command for CommandMarkdown {
         app: Rc<RefCell<Application>>,
     ) -> Result<(), CommandError> {
         let mut app = app.borrow_mut();
-        app.markdown = !app.markdown;
-        println!(
-            "Markdown parsing is now {}.",
-            match app.markdown {
+        app.followups = !app.followups;
+        print!(
+            "Follow-up suggestions are now {}{}.\r\n",
+            match app.followups {
                 true => "enabled",
                 false => "disabled",
+            },
+            match app.followups {
+                true => format!(" (one extra request to {} per reply)", app.followup_model),
+                false => String::new(),
             }
         );
-        return Ok(());
+        Ok(())
+    }
+}
+
+struct CommandRecord;
+impl Command for CommandRecord {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        match args.first().copied() {
+            None => match &app.recording {
+                Some(recording) => print!("Recording replies to {}.\r\n", recording.path),
+                None => print!("Not recording. Usage: /record <file> | stop\r\n"),
+            },
+            Some("stop") => match app.recording.take() {
+                Some(recording) => print!(
+                    "Saved {}, play it back with `chad-gpt replay {}`.\r\n",
+                    recording.path, recording.path
+                ),
+                None => print!("Not recording.\r\n"),
+            },
+            Some(path) => match Recording::start(path) {
+                Ok(recording) => {
+                    print!("Recording replies to {} until /record stop.\r\n", path);
+                    app.recording = Some(recording);
+                }
+                Err(e) => {
+                    eprint!("Failed to create {}: {}\r\n", path, e);
+                    return Err(CommandError::UpdateFailed);
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+struct CommandDraft;
+impl Command for CommandDraft {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        app.draft_model = match args.first().copied() {
+            Some("off") => None,
+            Some(model) => Some(model.to_owned()),
+            None => match app.draft_model {
+                Some(_) => None,
+                None => Some(DEFAULT_DRAFT_MODEL.to_owned()),
+            },
+        };
+        match &app.draft_model {
+            Some(model) => print!(
+                "Drafts by {} are shown until {} answers (one extra request per message).\r\n",
+                model, app.model
+            ),
+            None => print!("Drafts are off.\r\n"),
+        }
+        Ok(())
+    }
+}
+
+struct CommandFollowup;
+impl Command for CommandFollowup {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let mut app = app.borrow_mut();
+        let question = args
+            .first()
+            .and_then(|a| a.parse::<usize>().ok())
+            .and_then(|n| n.checked_sub(1))
+            .and_then(|i| app.suggested_followups.get(i))
+            .cloned();
+        let Some(question) = question else {
+            print!("No such follow-up suggestion.\r\n");
+            return Err(CommandError::InvalidArguments);
+        };
+        print!("> {}\r\n", question);
+        app.send_message(&question);
+        print!("\r\n");
+        Ok(())
+    }
+}
+
+struct CommandAsk;
+impl Command for CommandAsk {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let question = args.join(" ");
+        let question = question.trim_matches(|c| c == '"' || c == '\'');
+        if question.is_empty() {
+            print!("Usage: /ask <question>\r\n");
+            return Err(CommandError::InvalidArguments);
+        }
+
+        // Only the system prompt goes along, none of the conversation.
+        let mut app = app.borrow_mut();
+        let system = app.system_instructions();
+        let system = (!system.is_empty()).then_some(system);
+        app.last_result = app.side_request(system.as_deref(), question);
+        Ok(())
+    }
+}
+
+struct CommandStats;
+impl Command for CommandStats {
+    fn handle_command(
+        &self,
+        _registry: &CommandRegistry,
+        args: Vec<&str>,
+        _app: Rc<RefCell<Application>>,
+    ) -> Result<(), CommandError> {
+        let days: Option<u64> = match args.first() {
+            Some(arg) => Some(arg.parse().map_err(|_| CommandError::InvalidArguments)?),
+            None => None,
+        };
+
+        let mut records = match stats::load() {
+            Ok(x) => x,
+            Err(_) => {
+                print!("No statistics recorded yet.\r\n");
+                return Ok(());
+            }
+        };
+        if let Some(days) = days {
+            let since = stats::now().saturating_sub(days * 24 * 60 * 60);
+            records.retain(|r| r.timestamp >= since);
+        }
+        if records.is_empty() {
+            print!("No statistics recorded for that period.\r\n");
+            return Ok(());
+        }
+
+        print!(
+            "{:<20} {:<24} {:>8} {:>10} {:>10} {:>8}\r\n",
+            "PROVIDER", "MODEL", "REQUESTS", "TTFT", "TOTAL", "TOK/S"
+        );
+        for ((provider, model), a) in stats::aggregate(&records) {
+            print!(
+                "{:<20} {:<24} {:>8} {:>8.0}ms {:>8.0}ms {:>8.1}\r\n",
+                provider, model, a.requests, a.avg_ttft_ms, a.avg_total_ms, a.avg_tokens_per_second
+            );
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,301 @@
+//! `chad-gpt cron`: prompts run on a schedule, e.g. a summary every weekday
+//! morning. Jobs are kept in `cron.json` and run by `chad-gpt cron daemon`,
+//! each reply saved as a new file in the job's output directory.
+use crate::application::get_data_path;
+use crate::openai::{self, AVAILABLE_MODELS};
+use crate::stats::{self, LocalTime};
+use crate::storage;
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const CRON_FILE: &str = "cron.json";
+
+const USAGE: &str = "cron add \"<min> <hour> <day> <month> <weekday>\" --prompt-file <file> --out <dir> [--model <model>]
+       cron list | remove <id> | run <id> | daemon";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: usize,
+    pub schedule: String,
+    pub prompt_file: PathBuf,
+    pub out: PathBuf,
+    pub model: String,
+}
+
+/// The values one field of a schedule matches, from `*`, `5`, `1-5`, `*/15`,
+/// `1-10/2` and lists of them.
+fn field(spec: &str, min: u32, max: u32) -> Result<Vec<u32>, String> {
+    let mut values = Vec::new();
+    for part in spec.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or(format!("invalid step in '{}'", part))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (
+                    a.parse()
+                        .map_err(|_| format!("invalid range '{}'", range))?,
+                    b.parse()
+                        .map_err(|_| format!("invalid range '{}'", range))?,
+                ),
+                None => {
+                    let value = range
+                        .parse()
+                        .map_err(|_| format!("invalid value '{}'", range))?;
+                    // `5/10` means from 5 on.
+                    match part.contains('/') {
+                        true => (value, max),
+                        false => (value, value),
+                    }
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("'{}' is outside {}-{}", part, min, max));
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+    Ok(values)
+}
+
+/// A parsed five-field cron schedule.
+struct Schedule {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+    /// Whether the day and weekday fields are `*`. When both are restricted
+    /// either one matching is enough.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Schedule {
+    fn parse(schedule: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = schedule.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err("a schedule has five fields: minute hour day month weekday".to_owned());
+        };
+        Ok(Self {
+            minutes: field(minute, 0, 59)?,
+            hours: field(hour, 0, 23)?,
+            days: field(day, 1, 31)?,
+            months: field(month, 1, 12)?,
+            // 7 is Sunday too.
+            weekdays: field(weekday, 0, 7)?.into_iter().map(|d| d % 7).collect(),
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    fn matches(&self, time: &LocalTime) -> bool {
+        let day = self.days.contains(&time.day);
+        let weekday = self.weekdays.contains(&time.weekday);
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        self.minutes.contains(&time.minute)
+            && self.hours.contains(&time.hour)
+            && self.months.contains(&time.month)
+            && day_matches
+    }
+}
+
+/// The scheduled jobs, none if there is no `cron.json` yet. One that can't
+/// be parsed is an error rather than no jobs, so it isn't saved over.
+fn load() -> Result<Vec<Job>, Box<dyn Error>> {
+    let path = get_data_path(CRON_FILE);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("can't parse {}: {}", path.display(), e).into())
+}
+
+/// Take the `CRON_FILE` lock before loading the jobs to change.
+fn save(jobs: &[Job]) -> Result<(), Box<dyn Error>> {
+    storage::replace(CRON_FILE, &serde_json::to_string_pretty(jobs)?)?;
+    Ok(())
+}
+
+/// `path` made absolute, so the daemon finds it from anywhere.
+fn absolute(path: &str) -> PathBuf {
+    let path = match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    };
+    std::path::absolute(&path).unwrap_or(path)
+}
+
+/// Sends the job's prompt and saves the reply, returning where.
+async fn run_job(job: &Job) -> Result<PathBuf, Box<dyn Error>> {
+    let prompt = std::fs::read_to_string(&job.prompt_file)?;
+    let reply = openai::complete(&job.model, None, &prompt).await?;
+
+    let time = stats::local_time(stats::now());
+    let stem = job
+        .prompt_file
+        .file_stem()
+        .map_or("reply".into(), |s| s.to_string_lossy());
+    let name = format!(
+        "{}-{:04}-{:02}-{:02}-{:02}{:02}.md",
+        stem, time.year, time.month, time.day, time.hour, time.minute
+    );
+    std::fs::create_dir_all(&job.out)?;
+    let path = job.out.join(name);
+    std::fs::write(&path, reply)?;
+    Ok(path)
+}
+
+fn describe(job: &Job) -> String {
+    format!(
+        "{:>3}  {:<16} {} -> {} ({})",
+        job.id,
+        job.schedule,
+        job.prompt_file.display(),
+        job.out.display(),
+        job.model
+    )
+}
+
+/// Runs due jobs until stopped, checking once a minute. Every minute since
+/// the last check is looked at, so none is missed while a job runs; a job
+/// due in several of them runs once. Jobs added or removed in the meantime
+/// are picked up.
+async fn daemon() -> Result<(), Box<dyn Error>> {
+    println!(
+        "Running scheduled prompts from {}.",
+        get_data_path(CRON_FILE).display()
+    );
+    // In minutes since the Unix epoch.
+    let mut checked = stats::now() / 60 - 1;
+    loop {
+        let current = stats::now() / 60;
+        let minutes: Vec<LocalTime> = (checked + 1..=current)
+            .map(|minute| stats::local_time(minute * 60))
+            .collect();
+        checked = current;
+        let jobs = load().unwrap_or_else(|e| {
+            eprintln!("Failed to load the jobs: {}", e);
+            vec![]
+        });
+        for job in jobs {
+            let Ok(schedule) = Schedule::parse(&job.schedule) else {
+                continue;
+            };
+            let Some(time) = minutes.iter().find(|time| schedule.matches(time)) else {
+                continue;
+            };
+            let clock = format!("{:02}:{:02}", time.hour, time.minute);
+            match run_job(&job).await {
+                Ok(path) => println!("{} job {}: saved {}", clock, job.id, path.display()),
+                Err(e) => eprintln!("{} job {} failed: {}", clock, job.id, e),
+            }
+        }
+        // Wake up just after the next minute starts.
+        let seconds = 60 - stats::now() % 60;
+        tokio::time::sleep(Duration::from_secs(seconds) + Duration::from_millis(200)).await;
+    }
+}
+
+/// Entry point of `chad-gpt cron`.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let usage = || format!("usage: {}", USAGE);
+    match args.first().map(String::as_str) {
+        Some("add") => {
+            let schedule = args.get(1).ok_or_else(usage)?;
+            Schedule::parse(schedule)?;
+            let mut prompt_file = None;
+            let mut out = None;
+            let mut model = AVAILABLE_MODELS[0].to_owned();
+            let mut rest = args[2..].iter();
+            while let Some(arg) = rest.next() {
+                let value = rest.next().ok_or(format!("{} needs a value", arg));
+                match arg.as_str() {
+                    "--prompt-file" => prompt_file = Some(absolute(value?)),
+                    "--out" => out = Some(absolute(value?)),
+                    "--model" => model = value?.clone(),
+                    other => return Err(format!("unknown argument '{}'", other).into()),
+                }
+            }
+            let (Some(prompt_file), Some(out)) = (prompt_file, out) else {
+                return Err(usage().into());
+            };
+            if !Path::new(&prompt_file).is_file() {
+                return Err(format!("{} doesn't exist", prompt_file.display()).into());
+            }
+
+            let _lock = storage::lock(CRON_FILE)?;
+            let mut jobs = load()?;
+            let job = Job {
+                id: jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1,
+                schedule: schedule.clone(),
+                prompt_file,
+                out,
+                model,
+            };
+            println!(
+                "Added job {}. Run `chad-gpt cron daemon` to have it run.",
+                job.id
+            );
+            jobs.push(job);
+            save(&jobs)
+        }
+        Some("list") => {
+            let jobs = load()?;
+            if jobs.is_empty() {
+                println!("No scheduled prompts.");
+            }
+            for job in &jobs {
+                println!("{}", describe(job));
+            }
+            Ok(())
+        }
+        Some("remove") => {
+            let id: usize = args
+                .get(1)
+                .and_then(|id| id.parse().ok())
+                .ok_or_else(usage)?;
+            let _lock = storage::lock(CRON_FILE)?;
+            let mut jobs = load()?;
+            let count = jobs.len();
+            jobs.retain(|j| j.id != id);
+            if jobs.len() == count {
+                return Err(format!("there is no job {}", id).into());
+            }
+            println!("Removed job {}.", id);
+            save(&jobs)
+        }
+        Some("run") => {
+            let id: usize = args
+                .get(1)
+                .and_then(|id| id.parse().ok())
+                .ok_or_else(usage)?;
+            let job = load()?
+                .into_iter()
+                .find(|j| j.id == id)
+                .ok_or(format!("there is no job {}", id))?;
+            let path = tokio::runtime::Runtime::new()?.block_on(run_job(&job))?;
+            println!("Saved {}", path.display());
+            Ok(())
+        }
+        Some("daemon") => tokio::runtime::Runtime::new()?.block_on(daemon()),
+        _ => Err(usage().into()),
+    }
+}
@@ -0,0 +1,146 @@
+use crate::application::get_data_path;
+
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use std::io;
+use std::sync::OnceLock;
+
+/// Salt and a known value to tell a wrong passphrase from corrupt data.
+pub const KEY_INFO_FILE: &str = "encryption.json";
+/// Marks encrypted text; anything without it is read as plain text.
+const PREFIX: &str = "enc:";
+const CHECK: &str = "chad-llm";
+
+#[derive(Serialize, Deserialize)]
+struct KeyInfo {
+    salt: String,
+    check: String,
+}
+
+static CIPHER: OnceLock<Option<ChaCha20Poly1305>> = OnceLock::new();
+
+fn secret() -> io::Result<Option<Vec<u8>>> {
+    if let Ok(path) = std::env::var("CHAD_LLM_KEY_FILE") {
+        return std::fs::read(&path).map(Some).map_err(|e| {
+            io::Error::new(e.kind(), format!("failed to read key file {}: {}", path, e))
+        });
+    }
+    Ok(std::env::var("CHAD_LLM_PASSPHRASE")
+        .ok()
+        .filter(|p| !p.is_empty())
+        .map(String::into_bytes))
+}
+
+fn derive(secret: &[u8], salt: &[u8]) -> io::Result<ChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(secret, salt, &mut key)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+/// Sets up encryption when `CHAD_LLM_KEY_FILE` or `CHAD_LLM_PASSPHRASE` is
+/// set. Fails on a passphrase that doesn't match the one used before, so
+/// nothing gets written with the wrong key.
+pub fn init() -> io::Result<()> {
+    let path = get_data_path(KEY_INFO_FILE);
+    let Some(secret) = secret()? else {
+        if path.exists() {
+            // Going on would overwrite encrypted stores with plain ones.
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "data is encrypted, set CHAD_LLM_PASSPHRASE or CHAD_LLM_KEY_FILE",
+            ));
+        }
+        let _ = CIPHER.set(None);
+        return Ok(());
+    };
+
+    let cipher = match std::fs::read_to_string(&path) {
+        Ok(contents) => {
+            let info: KeyInfo = serde_json::from_str(&contents)?;
+            let salt = BASE64
+                .decode(info.salt)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let cipher = derive(&secret, &salt)?;
+            if decrypt(&cipher, &info.check).ok().as_deref() != Some(CHECK) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "wrong passphrase or key file",
+                ));
+            }
+            cipher
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let mut salt = [0u8; 16];
+            rand::rng().fill(&mut salt);
+            let cipher = derive(&secret, &salt)?;
+            let info = KeyInfo {
+                salt: BASE64.encode(salt),
+                check: encrypt(&cipher, CHECK),
+            };
+            std::fs::write(&path, serde_json::to_string(&info)?)?;
+            cipher
+        }
+        Err(e) => return Err(e),
+    };
+    let _ = CIPHER.set(Some(cipher));
+    Ok(())
+}
+
+fn encrypt(cipher: &ChaCha20Poly1305, plain: &str) -> String {
+    let mut nonce = [0u8; 12];
+    rand::rng().fill(&mut nonce);
+    let mut sealed = nonce.to_vec();
+    sealed.extend(
+        cipher
+            .encrypt(Nonce::from_slice(&nonce), plain.as_bytes())
+            .expect("encryption failed"),
+    );
+    format!("{}{}", PREFIX, BASE64.encode(sealed))
+}
+
+fn decrypt(cipher: &ChaCha20Poly1305, text: &str) -> io::Result<String> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    let sealed = BASE64
+        .decode(text.strip_prefix(PREFIX).unwrap_or(text))
+        .map_err(|e| invalid(e.to_string()))?;
+    if sealed.len() < 12 {
+        return Err(invalid("encrypted data too short".to_owned()));
+    }
+    let (nonce, ciphertext) = sealed.split_at(12);
+    let plain = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| invalid("failed to decrypt".to_owned()))?;
+    String::from_utf8(plain).map_err(|e| invalid(e.to_string()))
+}
+
+/// Encrypts `plain` into a single line when encryption is enabled,
+/// otherwise returns it unchanged.
+pub fn seal(plain: &str) -> String {
+    match CIPHER.get() {
+        Some(Some(cipher)) => encrypt(cipher, plain),
+        _ => plain.to_owned(),
+    }
+}
+
+/// Reverses `seal`. Plain text passes through, so data written before
+/// encryption was turned on stays readable.
+pub fn open(text: &str) -> io::Result<String> {
+    if !text.starts_with(PREFIX) {
+        return Ok(text.to_owned());
+    }
+    match CIPHER.get() {
+        Some(Some(cipher)) => decrypt(cipher, text),
+        _ => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "data is encrypted, set CHAD_LLM_PASSPHRASE or CHAD_LLM_KEY_FILE",
+        )),
+    }
+}
@@ -0,0 +1,134 @@
+//! Digests of documents too large for the context. Chunks are summarized
+//! on their own, the summaries combined until they fit, and the result made
+//! denser over a few chain-of-density passes. The full text stays available
+//! through a keyword index over the chunks.
+use crate::openai;
+
+use std::collections::HashSet;
+use std::error::Error;
+
+pub const CHUNK_CHARS: usize = 12000;
+const DENSITY_PASSES: usize = 2;
+
+const MAP_PROMPT: &str = "Summarize this part of a longer document. Keep every fact, name, \
+number and decision that could matter later; drop repetition and filler.";
+
+const REDUCE_PROMPT: &str = "Combine these summaries of consecutive parts of one document \
+into a single summary. Keep the order of the document and every important detail.";
+
+const DENSITY_PROMPT: &str = "You are given a summary of a document. Find important entities \
+or facts it leaves out or only hints at, and rewrite it at the same length so that it \
+includes them, making room by compressing wording. Reply with the new summary only.";
+
+/// A document split into chunks of about `CHUNK_CHARS` characters.
+pub struct Document {
+    pub path: String,
+    pub chunks: Vec<String>,
+}
+
+impl Document {
+    pub fn new(path: &str, text: &str) -> Self {
+        Self {
+            path: path.to_owned(),
+            chunks: split(text, CHUNK_CHARS),
+        }
+    }
+
+    /// The `k` chunks sharing the most words with `query`, best first, with
+    /// their index.
+    pub fn search(&self, query: &str, k: usize) -> Vec<(usize, &str)> {
+        let terms = words(query);
+        let mut scored: Vec<(usize, usize)> = self
+            .chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let text = chunk.to_lowercase();
+                let score = terms.iter().map(|t| text.matches(t.as_str()).count()).sum();
+                (i, score)
+            })
+            .filter(|(_, score)| *score > 0)
+            .collect();
+        scored.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        scored
+            .into_iter()
+            .take(k)
+            .map(|(i, _)| (i, self.chunks[i].as_str()))
+            .collect()
+    }
+}
+
+/// Lowercase words of three letters or more, without duplicates.
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() >= 3)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Splits `text` at paragraph breaks, or line breaks for long paragraphs,
+/// into pieces of at most about `size` characters.
+pub fn split(text: &str, size: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for line in text.split_inclusive('\n') {
+        let at_break = line.trim().is_empty() || current.len() >= size;
+        if current.len() + line.len() > size && at_break && !current.trim().is_empty() {
+            chunks.push(std::mem::take(&mut current));
+        }
+        current.push_str(line);
+        while current.len() > size * 2 {
+            // A single huge line, cut it at a char boundary.
+            let mut cut = size;
+            while !current.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            let rest = current.split_off(cut);
+            chunks.push(std::mem::replace(&mut current, rest));
+        }
+    }
+    if !current.trim().is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Runs the summarization pipeline over `document` with `model`, reporting
+/// each step through `progress`.
+pub async fn summarize(
+    model: &str,
+    document: &Document,
+    progress: impl Fn(&str),
+) -> Result<String, Box<dyn Error>> {
+    let mut summaries = Vec::new();
+    for (i, chunk) in document.chunks.iter().enumerate() {
+        progress(&format!(
+            "Summarizing part {} of {}",
+            i + 1,
+            document.chunks.len()
+        ));
+        summaries.push(openai::complete(model, Some(MAP_PROMPT), chunk).await?);
+    }
+
+    let mut summary = summaries.join("\n\n");
+    while summaries.len() > 1 {
+        progress("Combining summaries");
+        let mut combined = Vec::new();
+        for group in split(&summary, CHUNK_CHARS) {
+            combined.push(openai::complete(model, Some(REDUCE_PROMPT), &group).await?);
+        }
+        if combined.len() >= summaries.len() {
+            // The summaries no longer shrink, keep what we have.
+            summary = combined.join("\n\n");
+            break;
+        }
+        summaries = combined;
+        summary = summaries.join("\n\n");
+    }
+
+    for pass in 1..=DENSITY_PASSES {
+        progress(&format!("Densifying, pass {} of {}", pass, DENSITY_PASSES));
+        summary = openai::complete(model, Some(DENSITY_PROMPT), &summary).await?;
+    }
+    Ok(summary)
+}
@@ -0,0 +1,100 @@
+//! Draft mode, see `/draft`: while the main model works on its answer, a
+//! fast model's answer is shown dimmed, and cleared once the main answer
+//! starts to arrive.
+use crate::models::StreamEvent;
+use crate::theme;
+
+use crossterm::{cursor, execute, terminal};
+use futures_util::stream::Peekable;
+use futures_util::{Stream, StreamExt};
+use std::io::{self, Write};
+use std::pin::Pin;
+
+/// The rows of the terminal taken by what was printed so far.
+struct Region {
+    width: usize,
+    rows: usize,
+    /// Width of the line being printed.
+    column: usize,
+}
+
+impl Region {
+    fn print(&mut self, text: &str) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.rows += self.column.div_ceil(self.width).max(1);
+                self.column = 0;
+                print!("\r\n");
+            }
+            self.column += console::measure_text_width(line);
+            print!("\x1b[2m{}\x1b[0m", line);
+        }
+        let _ = io::stdout().flush();
+    }
+
+    fn total_rows(&self) -> usize {
+        self.rows + self.column.div_ceil(self.width).max(1)
+    }
+}
+
+/// Shows `draft` until `main` has more than keep-alives to show, then clears
+/// it, or labels the answer when the draft no longer fits on screen.
+/// Returns `main` with nothing it sent missing.
+pub async fn show_until<S: Stream<Item = StreamEvent>>(
+    draft_model: &str,
+    draft: impl Stream<Item = StreamEvent>,
+    main: S,
+    model: &str,
+) -> Pin<Box<Peekable<S>>> {
+    let (width, height) = terminal::size().unwrap_or((80, 24));
+    let mut region = Region {
+        width: width.max(1) as usize,
+        rows: 0,
+        column: 0,
+    };
+    region.print(&format!(
+        "draft by {}, replaced once {} answers:\n",
+        draft_model, model
+    ));
+
+    let mut draft = Box::pin(draft);
+    let mut draft_done = false;
+    let mut main = Box::pin(main.peekable());
+    loop {
+        tokio::select! {
+            event = main.as_mut().peek() => match event {
+                Some(StreamEvent::KeepAlive) => {
+                    main.next().await;
+                }
+                _ => break,
+            },
+            event = draft.next(), if !draft_done => match event {
+                Some(StreamEvent::Token(token)) => region.print(&token),
+                Some(StreamEvent::Error(_)) | None => draft_done = true,
+                Some(_) => {}
+            },
+        }
+    }
+
+    let rows = region.total_rows();
+    if rows < height as usize {
+        let mut stdout = io::stdout();
+        if rows > 1 {
+            let _ = execute!(stdout, cursor::MoveUp(rows as u16 - 1));
+        }
+        let _ = execute!(
+            stdout,
+            cursor::MoveToColumn(0),
+            terminal::Clear(terminal::ClearType::FromCursorDown)
+        );
+    } else {
+        print!(
+            "\r\n{}\r\n",
+            theme::format(&format!(
+                "[$cyan]--- {} (replaces the draft above) ---[$/]",
+                model
+            ))
+        );
+    }
+    main
+}
@@ -0,0 +1,139 @@
+use crate::openai;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use std::error::Error;
+
+#[derive(Deserialize)]
+pub struct Suite {
+    pub models: Vec<String>,
+    #[serde(default)]
+    pub judge: Option<String>,
+    #[serde(default)]
+    pub system: Option<String>,
+    pub cases: Vec<Case>,
+}
+
+#[derive(Deserialize)]
+pub struct Case {
+    pub name: String,
+    pub prompt: String,
+    #[serde(default)]
+    pub system: Option<String>,
+    #[serde(default, rename = "assert")]
+    pub assertions: Vec<Assertion>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Assertion {
+    Contains(String),
+    Regex(String),
+    Json(bool),
+    Judge(String),
+}
+
+struct Outcome {
+    case: String,
+    model: String,
+    failures: Vec<String>,
+}
+
+async fn check(assertion: &Assertion, response: &str, judge: Option<&str>) -> Result<(), String> {
+    match assertion {
+        Assertion::Contains(needle) => {
+            if response.contains(needle.as_str()) {
+                Ok(())
+            } else {
+                Err(format!("does not contain {:?}", needle))
+            }
+        }
+        Assertion::Regex(pattern) => match Regex::new(pattern) {
+            Ok(re) if re.is_match(response) => Ok(()),
+            Ok(_) => Err(format!("does not match /{}/", pattern)),
+            Err(e) => Err(format!("invalid regex /{}/: {}", pattern, e)),
+        },
+        Assertion::Json(expected) => {
+            let valid = serde_json::from_str::<serde_json::Value>(response.trim()).is_ok();
+            if valid == *expected {
+                Ok(())
+            } else if *expected {
+                Err("is not valid JSON".to_owned())
+            } else {
+                Err("is valid JSON".to_owned())
+            }
+        }
+        Assertion::Judge(criterion) => {
+            let judge = judge.ok_or("judge assertion without a judge model")?;
+            let prompt = format!(
+                "Criterion: {}\n\nResponse:\n{}\n\nDoes the response satisfy the criterion? Answer with PASS or FAIL only.",
+                criterion, response
+            );
+            match openai::complete(judge, None, &prompt).await {
+                Ok(verdict) if verdict.to_uppercase().contains("PASS") => Ok(()),
+                Ok(_) => Err(format!("judge rejected: {}", criterion)),
+                Err(e) => Err(format!("judge request failed: {}", e)),
+            }
+        }
+    }
+}
+
+/// Runs every case of the suite against every model and prints a pass/fail
+/// table. Returns whether all cases passed.
+pub async fn run_suite(path: &str) -> Result<bool, Box<dyn Error>> {
+    let suite: Suite = serde_yaml::from_str(&std::fs::read_to_string(path)?)?;
+    let mut outcomes = Vec::new();
+
+    for case in &suite.cases {
+        let system = case.system.as_deref().or(suite.system.as_deref());
+        for model in &suite.models {
+            let failures = match openai::complete(model, system, &case.prompt).await {
+                Ok(response) => {
+                    let mut failures = Vec::new();
+                    for assertion in &case.assertions {
+                        if let Err(e) = check(assertion, &response, suite.judge.as_deref()).await {
+                            failures.push(e);
+                        }
+                    }
+                    failures
+                }
+                Err(e) => vec![format!("request failed: {}", e)],
+            };
+            outcomes.push(Outcome {
+                case: case.name.clone(),
+                model: model.clone(),
+                failures,
+            });
+        }
+    }
+
+    let case_width = outcomes
+        .iter()
+        .map(|o| o.case.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    let model_width = outcomes
+        .iter()
+        .map(|o| o.model.len())
+        .max()
+        .unwrap_or(0)
+        .max(5);
+    println!("{:case_width$}  {:model_width$}  RESULT", "CASE", "MODEL");
+    for outcome in &outcomes {
+        let result = if outcome.failures.is_empty() {
+            "PASS".to_owned()
+        } else {
+            format!("FAIL ({})", outcome.failures.join("; "))
+        };
+        println!(
+            "{:case_width$}  {:model_width$}  {}",
+            outcome.case, outcome.model, result
+        );
+    }
+
+    let passed = outcomes.iter().filter(|o| o.failures.is_empty()).count();
+    println!("\n{}/{} passed", passed, outcomes.len());
+    Ok(passed == outcomes.len())
+}
@@ -0,0 +1,26 @@
+use crate::models::Message;
+
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Conversation<'a> {
+    messages: Vec<&'a Message>,
+}
+
+/// Serializes conversations in the OpenAI fine-tuning format, one
+/// `{"messages": [...]}` object per line.
+pub fn to_jsonl(conversations: &[Vec<Message>], strip_system: bool) -> serde_json::Result<String> {
+    let mut out = String::new();
+    for conversation in conversations {
+        let messages: Vec<&Message> = conversation
+            .iter()
+            .filter(|m| !strip_system || m.role != "system")
+            .collect();
+        if messages.is_empty() {
+            continue;
+        }
+        out.push_str(&serde_json::to_string(&Conversation { messages })?);
+        out.push('\n');
+    }
+    Ok(out)
+}
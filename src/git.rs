@@ -0,0 +1,114 @@
+//! Thin wrappers around the git CLI for `/pr` and friends.
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// Runs git with `args` in the working directory and returns its stdout.
+/// Fails with git's own message when it exits with an error.
+pub fn git(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(stderr.trim().to_owned()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Like `git`, feeding `input` to its stdin.
+pub fn git_with_input(args: &[&str], input: &str) -> io::Result<String> {
+    let mut child = Command::new("git")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(input.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(stderr.trim().to_owned()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The branch pull requests go to: the remote's default branch, or else a
+/// local `main` or `master`.
+pub fn default_branch() -> Option<String> {
+    if let Ok(head) = git(&["symbolic-ref", "--short", "refs/remotes/origin/HEAD"]) {
+        return Some(head.trim().to_owned());
+    }
+    ["main", "master"]
+        .into_iter()
+        .find(|branch| git(&["rev-parse", "--verify", "--quiet", branch]).is_ok())
+        .map(str::to_owned)
+}
+
+/// One hunk of a diff, or a whole file section for changes without hunks
+/// (binary files, renames, mode changes).
+pub struct Hunk {
+    pub path: String,
+    /// The file's `diff --git` header up to its first hunk.
+    pub header: String,
+    pub body: String,
+}
+
+/// Splits the output of `git diff` into hunks.
+pub fn hunks(diff: &str) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let mut header = String::new();
+    let mut path = String::new();
+    let mut in_header = false;
+    for line in diff.split_inclusive('\n') {
+        if line.starts_with("diff --git ") {
+            if in_header {
+                // The previous file had no hunks.
+                hunks.push(Hunk {
+                    path: std::mem::take(&mut path),
+                    header: std::mem::take(&mut header),
+                    body: String::new(),
+                });
+            }
+            header = line.to_owned();
+            path = line
+                .trim_end()
+                .rsplit_once(" b/")
+                .map_or_else(String::new, |(_, p)| p.to_owned());
+            in_header = true;
+        } else if line.starts_with("@@") {
+            in_header = false;
+            hunks.push(Hunk {
+                path: path.clone(),
+                header: header.clone(),
+                body: line.to_owned(),
+            });
+        } else if in_header {
+            header.push_str(line);
+        } else if let Some(hunk) = hunks.last_mut() {
+            hunk.body.push_str(line);
+        }
+    }
+    if in_header {
+        hunks.push(Hunk {
+            path,
+            header,
+            body: String::new(),
+        });
+    }
+    hunks
+}
+
+/// A patch of `hunks`, in the order given, each file's header written once.
+pub fn patch(hunks: &[&Hunk]) -> String {
+    let mut patch = String::new();
+    let mut last_header: Option<&str> = None;
+    for hunk in hunks {
+        if last_header != Some(hunk.header.as_str()) {
+            patch.push_str(&hunk.header);
+            last_header = Some(&hunk.header);
+        }
+        patch.push_str(&hunk.body);
+    }
+    patch
+}
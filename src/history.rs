@@ -1,36 +1,502 @@
+use crate::application::get_data_path;
+use crate::crypto;
+use crate::import;
+use crate::models::Message;
+use crate::stats;
+use crate::storage;
+
+use serde::{Deserialize, Serialize};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::{self, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// One JSON file per session under the data directory.
+pub const TRANSCRIPTS_DIR: &str = "transcripts";
+/// Everything typed at the prompt, commands included, for Up-arrow recall.
+pub const INPUT_HISTORY_FILE: &str = "input_history.jsonl";
+/// Pruned sessions packed with `--archive`.
+pub const ARCHIVES_DIR: &str = "archives";
+/// Tags and other metadata per session.
+pub const SESSION_META_FILE: &str = "session_meta.json";
+/// The plain text history older versions wrote to the working directory.
+pub const LEGACY_HISTORY_FILE: &str = "session_history.txt";
+
+/// A message as written to the transcript.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TranscriptEntry {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub role: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Set on a reply that takes the place of the one before it, written by
+    /// `/retry` and `/reflect` so the question isn't saved twice.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub replaces: bool,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SessionMeta {
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Given to sessions rotated out at the end of the day.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+/// A conversation saved in the transcripts.
+pub struct Session {
+    pub id: String,
+    /// Seconds since the Unix epoch.
+    pub started: u64,
+    pub messages: Vec<Message>,
+    pub meta: SessionMeta,
+}
+
+/// A line of the input history.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InputEntry {
+    pub timestamp: u64,
+    pub input: String,
+}
+
+pub const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// What goes into the input history, configured like bash's `HISTCONTROL`:
+/// `CHAD_LLM_HISTCONTROL` takes a colon separated list of `ignoredups`,
+/// `ignorespace`, `ignoreboth` and `ignorecommands`, and
+/// `CHAD_LLM_HISTORY_SIZE` caps the number of entries kept.
+#[derive(Debug, Clone)]
+pub struct HistControl {
+    pub max_entries: usize,
+    pub ignore_dups: bool,
+    pub ignore_space: bool,
+    pub ignore_commands: bool,
+}
+
+impl HistControl {
+    pub fn from_env() -> Self {
+        let options = std::env::var("CHAD_LLM_HISTCONTROL").unwrap_or_default();
+        let has = |name: &str| options.split(':').any(|o| o.trim() == name);
+        HistControl {
+            max_entries: std::env::var("CHAD_LLM_HISTORY_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_HISTORY_SIZE),
+            ignore_dups: has("ignoredups") || has("ignoreboth"),
+            ignore_space: has("ignorespace") || has("ignoreboth"),
+            ignore_commands: has("ignorecommands"),
+        }
+    }
+
+    /// Whether `input` should be recorded after `previous`.
+    pub fn accepts(&self, previous: Option<&str>, input: &str) -> bool {
+        !(input.trim().is_empty()
+            || self.max_entries == 0
+            || (self.ignore_space && input.starts_with(' '))
+            || (self.ignore_commands && input.starts_with('/'))
+            || (self.ignore_dups && previous == Some(input)))
+    }
+}
 
 pub struct History {
-    file_path: String,
+    session: String,
+    control: HistControl,
+    last_input: Option<String>,
 }
 
 impl History {
-    pub fn new(file_path: &str) -> Self {
+    pub fn new() -> Self {
         History {
-            file_path: file_path.to_string(),
+            session: Self::new_session_id(),
+            control: HistControl::from_env(),
+            last_input: None,
         }
     }
 
-    pub fn save_entry(&self, entry: &str) -> io::Result<()> {
-        let path = Path::new(&self.file_path);
+    fn append(name: &str, line: &str) -> io::Result<()> {
+        let path = get_data_path(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
         let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", crypto::seal(line))
+    }
 
-        writeln!(file, "User: {}", entry)?;
+    pub fn save_input(&mut self, input: &str) -> io::Result<()> {
+        if !self.control.accepts(self.last_input.as_deref(), input) {
+            return Ok(());
+        }
+        self.last_input = Some(input.to_owned());
+        let entry = InputEntry {
+            timestamp: stats::now(),
+            input: input.to_owned(),
+        };
+        // Retention in another instance rewrites the file.
+        let _lock = storage::lock(INPUT_HISTORY_FILE)?;
+        Self::append(INPUT_HISTORY_FILE, &serde_json::to_string(&entry)?)
+    }
+
+    fn load_input_entries() -> io::Result<Vec<InputEntry>> {
+        let content = match std::fs::read_to_string(get_data_path(INPUT_HISTORY_FILE)) {
+            Ok(x) => x,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        Ok(content
+            .lines()
+            .filter_map(|line| crypto::open(line).ok())
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .collect())
+    }
+
+    pub fn load_inputs(&mut self) -> io::Result<Vec<String>> {
+        let inputs: Vec<String> = Self::load_input_entries()?
+            .into_iter()
+            .map(|e| e.input)
+            .collect();
+        self.last_input = inputs.last().cloned();
+        Ok(inputs)
+    }
+
+    pub fn save_message(&self, role: &str, content: &str, model: Option<&str>) -> io::Result<()> {
+        self.save_entry(TranscriptEntry {
+            timestamp: stats::now(),
+            role: role.to_owned(),
+            content: content.to_owned(),
+            model: model.map(String::from),
+            replaces: false,
+        })
+    }
+
+    /// Saves a reply replacing the last one, which stays in the file.
+    pub fn save_replacement(&self, content: &str, model: &str) -> io::Result<()> {
+        self.save_entry(TranscriptEntry {
+            timestamp: stats::now(),
+            role: "assistant".to_owned(),
+            content: content.to_owned(),
+            model: Some(model.to_owned()),
+            replaces: true,
+        })
+    }
+
+    fn save_entry(&self, entry: TranscriptEntry) -> io::Result<()> {
+        Self::append(
+            &format!("{}/{}.jsonl", TRANSCRIPTS_DIR, self.session),
+            &serde_json::to_string(&entry)?,
+        )
+    }
+
+    fn new_session_id() -> String {
+        format!("{}-{}", stats::now(), std::process::id())
+    }
+
+    /// Starts a new transcript; the current one is left as it is.
+    pub fn start_new(&mut self) {
+        self.session = Self::new_session_id();
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session
+    }
+
+    /// Continues an earlier session: new messages are added to its transcript.
+    pub fn resume(&mut self, id: &str) {
+        self.session = id.to_owned();
+    }
+
+    fn load_meta() -> io::Result<HashMap<String, SessionMeta>> {
+        match std::fs::read_to_string(get_data_path(SESSION_META_FILE)) {
+            Ok(contents) => Ok(serde_json::from_str(&crypto::open(&contents)?)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn meta(&self) -> io::Result<SessionMeta> {
+        Ok(Self::load_meta()?.remove(&self.session).unwrap_or_default())
+    }
+
+    pub fn set_meta(&self, meta: SessionMeta) -> io::Result<()> {
+        let _lock = storage::lock(SESSION_META_FILE)?;
+        let mut all = Self::load_meta()?;
+        all.insert(self.session.clone(), meta);
+        storage::replace(
+            SESSION_META_FILE,
+            &crypto::seal(&serde_json::to_string(&all)?),
+        )
+    }
+
+    fn load_transcript(path: &std::path::Path) -> io::Result<Vec<Message>> {
+        let mut messages: Vec<Message> = Vec::new();
+        for line in std::fs::read_to_string(path)?.lines() {
+            let Ok(line) = crypto::open(line) else {
+                continue;
+            };
+            let Ok(entry) = serde_json::from_str::<TranscriptEntry>(&line) else {
+                continue;
+            };
+            match messages.last_mut() {
+                Some(last) if last.role == "assistant" && entry.replaces => {
+                    last.content = entry.content;
+                    last.metadata.model = entry.model;
+                }
+                Some(last) if last.role == "assistant" && entry.role == "assistant" => {
+                    last.content.push_str(&entry.content)
+                }
+                _ => {
+                    let mut message = Message::new(&entry.role, &entry.content);
+                    message.metadata.model = entry.model;
+                    messages.push(message);
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Messages saved so far in the current session.
+    pub fn load_current(&self) -> io::Result<Vec<Message>> {
+        let path = get_data_path(&format!("{}/{}.jsonl", TRANSCRIPTS_DIR, self.session));
+        match Self::load_transcript(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            x => x,
+        }
+    }
+
+    /// Sessions saved in the transcripts, oldest first. A continuation is
+    /// stored as its own reply and merged into the one before it here.
+    pub fn list_sessions(&self) -> io::Result<Vec<Session>> {
+        let mut paths: Vec<_> = match std::fs::read_dir(get_data_path(TRANSCRIPTS_DIR)) {
+            Ok(dir) => dir.filter_map(|e| e.ok().map(|e| e.path())).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e),
+        };
+        paths.sort();
+
+        let mut meta = Self::load_meta()?;
+        let mut sessions = Vec::new();
+        for path in paths {
+            let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let messages = Self::load_transcript(&path)?;
+            if messages.is_empty() {
+                continue;
+            }
+            sessions.push(Session {
+                id: id.to_owned(),
+                started: id
+                    .split('-')
+                    .next()
+                    .and_then(|t| t.parse().ok())
+                    .unwrap_or_default(),
+                messages,
+                meta: meta.remove(id).unwrap_or_default(),
+            });
+        }
+        Ok(sessions)
+    }
+
+    /// Conversations of earlier sessions, oldest first, including the
+    /// legacy history file.
+    pub fn load_sessions(&self) -> io::Result<Vec<Vec<Message>>> {
+        self.load_sessions_but(None)
+    }
+
+    /// Like `load_sessions`, without the current session, whose messages
+    /// are in the context.
+    pub fn load_earlier_sessions(&self) -> io::Result<Vec<Vec<Message>>> {
+        self.load_sessions_but(Some(&self.session))
+    }
+
+    fn load_sessions_but(&self, skipped: Option<&str>) -> io::Result<Vec<Vec<Message>>> {
+        let mut sessions = Vec::new();
+        if let Ok(legacy) = std::fs::read_to_string(LEGACY_HISTORY_FILE) {
+            sessions.push(import::from_markdown(&legacy));
+        }
+        sessions.extend(
+            self.list_sessions()?
+                .into_iter()
+                .filter(|s| Some(s.id.as_str()) != skipped)
+                .map(|s| s.messages),
+        );
+        sessions.retain(|s| !s.is_empty());
+        Ok(sessions)
+    }
+
+    /// Prunes sessions idle for more than `CHAD_LLM_TRANSCRIPT_DAYS` days or
+    /// beyond `CHAD_LLM_SESSIONS_MAX_MB` (archived instead with
+    /// `CHAD_LLM_SESSIONS_ARCHIVE=1`), drops input history entries older than
+    /// `CHAD_LLM_INPUT_HISTORY_DAYS` days and trims the input history to its
+    /// maximum size. Nothing expires when the variables are unset.
+    pub fn apply_retention(&self) -> io::Result<()> {
+        let days = |var: &str| -> Option<u64> { std::env::var(var).ok()?.parse().ok() };
+        let cutoff = |days: u64| stats::now().saturating_sub(days * 24 * 60 * 60);
+
+        let max_bytes = std::env::var("CHAD_LLM_SESSIONS_MAX_MB")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(|mb| mb * 1024 * 1024);
+        let older_than = days("CHAD_LLM_TRANSCRIPT_DAYS").map(|d| d * 24 * 60 * 60);
+        if older_than.is_some() || max_bytes.is_some() {
+            let archive = std::env::var("CHAD_LLM_SESSIONS_ARCHIVE").is_ok_and(|v| v == "1");
+            self.prune(older_than, max_bytes, archive)?;
+        }
+
+        let _lock = storage::lock(INPUT_HISTORY_FILE)?;
+        let entries = Self::load_input_entries()?;
+        let mut kept: Vec<&InputEntry> = match days("CHAD_LLM_INPUT_HISTORY_DAYS") {
+            Some(days) => {
+                let cutoff = cutoff(days);
+                entries.iter().filter(|e| e.timestamp >= cutoff).collect()
+            }
+            None => entries.iter().collect(),
+        };
+        if kept.len() > self.control.max_entries {
+            kept.drain(..kept.len() - self.control.max_entries);
+        }
+        if kept.len() < entries.len() {
+            let mut contents = String::new();
+            for entry in kept {
+                contents.push_str(&crypto::seal(&serde_json::to_string(entry)?));
+                contents.push('\n');
+            }
+            storage::replace(INPUT_HISTORY_FILE, &contents)?;
+        }
         Ok(())
     }
 
-    pub fn save_response(&self, response: &str) -> io::Result<()> {
-        let path = Path::new(&self.file_path);
-        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    /// Removes sessions last written more than `older_than` seconds ago, then
+    /// the oldest ones until the transcripts fit in `max_bytes`. The current
+    /// session is kept. With `archive` the removed transcripts and their
+    /// metadata are first packed into a tarball under `archives/`. Returns
+    /// the removed session ids and the archive path.
+    pub fn prune(
+        &self,
+        older_than: Option<u64>,
+        max_bytes: Option<u64>,
+        archive: bool,
+    ) -> io::Result<(Vec<String>, Option<PathBuf>)> {
+        let mut files: Vec<(PathBuf, u64, u64)> = Vec::new();
+        if let Ok(dir) = std::fs::read_dir(get_data_path(TRANSCRIPTS_DIR)) {
+            for entry in dir.flatten() {
+                let metadata = entry.metadata()?;
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map_or(u64::MAX, |d| d.as_secs());
+                files.push((entry.path(), modified, metadata.len()));
+            }
+        }
+        let current = format!("{}.jsonl", self.session);
+        files.retain(|(path, _, _)| path.file_name().is_some_and(|n| *n != *current));
+        files.sort_by_key(|(_, modified, _)| *modified);
+
+        let cutoff = older_than.map(|secs| stats::now().saturating_sub(secs));
+        let mut total: u64 = files.iter().map(|(_, _, len)| len).sum();
+        let mut pruned = Vec::new();
+        for (path, modified, len) in files {
+            let expired = cutoff.is_some_and(|c| modified < c);
+            let too_big = max_bytes.is_some_and(|max| total > max);
+            if expired || too_big {
+                total -= len;
+                pruned.push(path);
+            }
+        }
+        if pruned.is_empty() {
+            return Ok((vec![], None));
+        }
 
-        writeln!(file, "GPT: {}", response)?;
+        let ids: Vec<String> = pruned
+            .iter()
+            .filter_map(|p| p.file_stem().and_then(|s| s.to_str()).map(String::from))
+            .collect();
+        let _lock = storage::lock(SESSION_META_FILE)?;
+        let mut meta = Self::load_meta()?;
+        let archive_path = match archive {
+            true => {
+                let removed_meta: HashMap<&String, &SessionMeta> = ids
+                    .iter()
+                    .filter_map(|id| Some((id, meta.get(id)?)))
+                    .collect();
+                Some(Self::archive(
+                    &pruned,
+                    &serde_json::to_string(&removed_meta)?,
+                )?)
+            }
+            false => None,
+        };
+
+        for path in &pruned {
+            std::fs::remove_file(path)?;
+        }
+        if ids.iter().any(|id| meta.remove(id).is_some()) {
+            storage::replace(
+                SESSION_META_FILE,
+                &crypto::seal(&serde_json::to_string(&meta)?),
+            )?;
+        }
+        Ok((ids, archive_path))
+    }
+
+    fn archive(files: &[PathBuf], meta: &str) -> io::Result<PathBuf> {
+        let dir = get_data_path(ARCHIVES_DIR);
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!(
+            "sessions-{}-{}.tar.gz",
+            stats::date(stats::now()),
+            stats::now()
+        ));
+
+        let encoder = GzEncoder::new(std::fs::File::create(&path)?, Compression::default());
+        let mut tar = tar::Builder::new(encoder);
+        for file in files {
+            if let Some(name) = file.file_name() {
+                tar.append_path_with_name(file, Path::new(TRANSCRIPTS_DIR).join(name))?;
+            }
+        }
+        // Sealed like the transcripts next to it.
+        let meta = crypto::seal(meta);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(meta.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(stats::now());
+        header.set_cksum();
+        tar.append_data(&mut header, SESSION_META_FILE, meta.as_bytes())?;
+        tar.into_inner()?.finish()?;
+        Ok(path)
+    }
+
+    /// Removes the conversation transcripts.
+    pub fn clear_transcripts(&self) -> io::Result<()> {
+        for result in [
+            std::fs::remove_dir_all(get_data_path(TRANSCRIPTS_DIR)),
+            std::fs::remove_file(get_data_path(SESSION_META_FILE)),
+            std::fs::remove_file(LEGACY_HISTORY_FILE),
+        ] {
+            match result {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
         Ok(())
     }
 
-    pub fn load_history(&self) -> io::Result<Vec<String>> {
-        let content = std::fs::read_to_string(&self.file_path)?;
-        Ok(content.lines().map(String::from).collect())
+    /// Removes the input history.
+    pub fn clear_inputs(&mut self) -> io::Result<()> {
+        self.last_input = None;
+        match std::fs::remove_file(get_data_path(INPUT_HISTORY_FILE)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
     }
 }
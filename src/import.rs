@@ -0,0 +1,168 @@
+use crate::models::Message;
+
+use serde_json::Value;
+
+use std::error::Error;
+
+#[derive(Debug)]
+enum ImportError {
+    UnrecognizedFormat,
+    EmptyConversation,
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for ImportError {}
+
+/// Reads a conversation exported by another tool and converts it into
+/// messages. Supports ChatGPT data exports, OpenAI playground / API style
+/// JSON and plain markdown transcripts.
+pub fn import_file(path: &str) -> Result<Vec<Message>, Box<dyn Error>> {
+    let contents = std::fs::read_to_string(path)?;
+    let messages = match serde_json::from_str::<Value>(&contents) {
+        Ok(json) => from_json(&json).ok_or(ImportError::UnrecognizedFormat)?,
+        Err(_) => from_markdown(&contents),
+    };
+
+    if messages.is_empty() {
+        return Err(Box::new(ImportError::EmptyConversation));
+    }
+    Ok(messages)
+}
+
+fn normalize_role(role: &str) -> Option<&'static str> {
+    match role.trim().to_lowercase().as_str() {
+        "system" | "developer" => Some("system"),
+        "user" | "human" | "you" => Some("user"),
+        "assistant" | "gpt" | "chatgpt" | "ai" | "model" => Some("assistant"),
+        _ => None,
+    }
+}
+
+fn content_to_string(content: &Value) -> Option<String> {
+    match content {
+        Value::String(s) => Some(s.clone()),
+        Value::Array(parts) => {
+            let text: Vec<String> = parts
+                .iter()
+                .filter_map(|p| match p {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Object(_) => p.get("text").and_then(|t| t.as_str()).map(String::from),
+                    _ => None,
+                })
+                .collect();
+            Some(text.join("\n"))
+        }
+        // ChatGPT exports nest the parts inside a content object.
+        Value::Object(_) => content.get("parts").and_then(content_to_string),
+        _ => None,
+    }
+}
+
+fn message_from_json(value: &Value) -> Option<Message> {
+    let role = value
+        .get("role")
+        .or_else(|| value.get("author").and_then(|a| a.get("role")))?
+        .as_str()?;
+    let content = content_to_string(value.get("content")?)?;
+    if content.trim().is_empty() {
+        return None;
+    }
+    Some(Message::new(normalize_role(role)?, &content))
+}
+
+fn from_json(json: &Value) -> Option<Vec<Message>> {
+    match json {
+        // A ChatGPT `conversations.json` holds many conversations, take the
+        // most recent one.
+        Value::Array(items) if items.first().is_some_and(|i| i.get("mapping").is_some()) => {
+            from_json(items.iter().max_by(|a, b| {
+                let a = a.get("update_time").and_then(|t| t.as_f64()).unwrap_or(0.0);
+                let b = b.get("update_time").and_then(|t| t.as_f64()).unwrap_or(0.0);
+                a.total_cmp(&b)
+            })?)
+        }
+        Value::Array(items) => Some(items.iter().filter_map(message_from_json).collect()),
+        Value::Object(_) if json.get("mapping").is_some() => from_chatgpt_mapping(json),
+        Value::Object(_) => from_json(json.get("messages")?),
+        _ => None,
+    }
+}
+
+fn from_chatgpt_mapping(json: &Value) -> Option<Vec<Message>> {
+    let mapping = json.get("mapping")?.as_object()?;
+    let mut node_id = json
+        .get("current_node")
+        .and_then(|n| n.as_str())
+        .map(String::from)
+        .or_else(|| {
+            // Without a current node, follow the last child from the root.
+            let mut id = mapping
+                .iter()
+                .find(|(_, n)| n.get("parent").is_none_or(|p| p.is_null()))?
+                .0
+                .clone();
+            while let Some(child) = mapping
+                .get(&id)?
+                .get("children")
+                .and_then(|c| c.as_array())
+                .and_then(|c| c.last())
+                .and_then(|c| c.as_str())
+            {
+                id = child.to_owned();
+            }
+            Some(id)
+        })?;
+
+    let mut messages = Vec::new();
+    while let Some(node) = mapping.get(&node_id) {
+        if let Some(msg) = node.get("message").and_then(message_from_json) {
+            messages.push(msg);
+        }
+        match node.get("parent").and_then(|p| p.as_str()) {
+            Some(parent) => node_id = parent.to_owned(),
+            None => break,
+        }
+    }
+    messages.reverse();
+    Some(messages)
+}
+
+/// Parses transcripts where each message starts with a role marker such as
+/// `User:`, `**Assistant:**` or `## User`, including chad-llm's own history
+/// file.
+pub fn from_markdown(contents: &str) -> Vec<Message> {
+    let mut messages: Vec<Message> = Vec::new();
+
+    for line in contents.lines() {
+        let stripped = line.trim_start_matches('#').trim().replace("**", "");
+        let marker = match stripped.split_once(':') {
+            Some((role, rest)) => normalize_role(role).map(|r| (r, rest.trim_start())),
+            None if line.starts_with('#') => normalize_role(&stripped).map(|r| (r, "")),
+            None => None,
+        };
+
+        match marker {
+            Some((role, rest)) => messages.push(Message::new(role, rest)),
+            None => match messages.last_mut() {
+                Some(last) => {
+                    if !last.content.is_empty() {
+                        last.content.push('\n');
+                    }
+                    last.content.push_str(line);
+                }
+                None => continue,
+            },
+        }
+    }
+
+    for msg in messages.iter_mut() {
+        msg.content = msg.content.trim().to_owned();
+    }
+    messages.retain(|m| !m.content.is_empty());
+    messages
+}
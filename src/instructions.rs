@@ -0,0 +1,110 @@
+//! Layered system instructions. The final system message is composed of
+//! the selected system prompt, the global "about me" block, the active
+//! profile's instructions, the project's notes, additions made for the current
+//! session and the preferred reply language.
+use crate::application::get_data_path;
+use crate::crypto;
+use crate::notes;
+use crate::request_template;
+
+pub const ABOUT_ME_FILE: &str = "about_me.md";
+pub const INSTRUCTIONS_DIR: &str = "instructions";
+
+pub struct Layer {
+    pub name: String,
+    pub content: String,
+}
+
+fn read(path: &str) -> Option<String> {
+    let contents = std::fs::read_to_string(get_data_path(path)).ok()?;
+    let contents = crypto::open(&contents).ok()?;
+    let contents = contents.trim();
+    (!contents.is_empty()).then(|| contents.to_owned())
+}
+
+/// The non-empty layers in the order they are composed.
+pub fn layers(
+    system_prompt: Option<(&str, &str)>,
+    session: &[String],
+    reply_language: Option<&str>,
+) -> Vec<Layer> {
+    let profile = request_template::profile();
+    let mut layers = Vec::new();
+    if let Some((name, content)) = system_prompt.filter(|(_, c)| !c.trim().is_empty()) {
+        layers.push(Layer {
+            name: format!("system prompt \"{}\"", name),
+            content: content.trim().to_owned(),
+        });
+    }
+    if let Some(content) = read(ABOUT_ME_FILE) {
+        layers.push(Layer {
+            name: ABOUT_ME_FILE.to_owned(),
+            content,
+        });
+    }
+    let profile_file = format!("{}/{}.md", INSTRUCTIONS_DIR, profile);
+    if let Some(content) = read(&profile_file) {
+        layers.push(Layer {
+            name: format!("profile {} ({})", profile, profile_file),
+            content,
+        });
+    }
+    let (project_notes, _) = notes::budgeted();
+    if !project_notes.is_empty() {
+        layers.push(Layer {
+            name: format!("project notes ({})", notes::NOTES_FILE),
+            content: format!(
+                "Notes about the project being worked on:\n{}",
+                project_notes
+                    .iter()
+                    .map(|n| format!("- {}", n))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ),
+        });
+    }
+    if !session.is_empty() {
+        layers.push(Layer {
+            name: "this session".to_owned(),
+            content: session.join("\n"),
+        });
+    }
+    if let Some(language) = reply_language {
+        layers.push(Layer {
+            name: "reply language".to_owned(),
+            content: format!(
+                "Always reply in {}, whatever language the user writes in, unless asked \
+                 otherwise. Keep code, identifiers and quoted text as they are.",
+                language_name(language)
+            ),
+        });
+    }
+    layers
+}
+
+/// The English name for common language codes, anything else as given.
+pub fn language_name(code: &str) -> &str {
+    match code.to_lowercase().as_str() {
+        "ro" => "Romanian",
+        "en" => "English",
+        "de" => "German",
+        "fr" => "French",
+        "es" => "Spanish",
+        "it" => "Italian",
+        "hu" => "Hungarian",
+        "pt" => "Portuguese",
+        "nl" => "Dutch",
+        "pl" => "Polish",
+        "ru" => "Russian",
+        "uk" => "Ukrainian",
+        _ => code,
+    }
+}
+
+pub fn compose(layers: &[Layer]) -> String {
+    layers
+        .iter()
+        .map(|l| l.content.as_str())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
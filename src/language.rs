@@ -0,0 +1,199 @@
+use crate::application::get_data_path;
+
+use regex::Regex;
+
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+pub const ALIASES_FILE: &str = "language_aliases.json";
+
+/// Fence tags models use that bat knows under another name.
+const DEFAULT_ALIASES: &[(&str, &str)] = &[
+    ("csharp", "c#"),
+    ("cs", "c#"),
+    ("fsharp", "f#"),
+    ("js", "javascript"),
+    ("jsx", "javascript"),
+    ("mjs", "javascript"),
+    ("ts", "typescript"),
+    ("sh", "bash"),
+    ("shell", "bash"),
+    ("zsh", "bash"),
+    ("console", "bash"),
+    ("shellscript", "bash"),
+    ("dockerfile", "Dockerfile"),
+    ("docker", "Dockerfile"),
+    ("jsonc", "json"),
+    ("json5", "json"),
+    ("py", "python"),
+    ("python3", "python"),
+    ("rs", "rust"),
+    ("golang", "go"),
+    ("cpp", "c++"),
+    ("hpp", "c++"),
+    ("cxx", "c++"),
+    ("objc", "objective-c"),
+    ("yml", "yaml"),
+    ("md", "markdown"),
+    ("rb", "ruby"),
+    ("kt", "kotlin"),
+    ("ps1", "powershell"),
+    ("pwsh", "powershell"),
+    ("plaintext", "txt"),
+    ("text", "txt"),
+];
+
+// Defaults overridden by the aliases file, a JSON object like `{"vue": "html"}`.
+static ALIASES: LazyLock<HashMap<String, String>> = LazyLock::new(|| {
+    let mut aliases: HashMap<String, String> = DEFAULT_ALIASES
+        .iter()
+        .map(|(alias, name)| (alias.to_string(), name.to_string()))
+        .collect();
+    if let Ok(contents) = std::fs::read_to_string(get_data_path(ALIASES_FILE)) {
+        match serde_json::from_str::<HashMap<String, String>>(&contents) {
+            Ok(custom) => aliases.extend(
+                custom
+                    .into_iter()
+                    .map(|(alias, name)| (alias.to_lowercase(), name)),
+            ),
+            Err(e) => eprint!("Failed to parse {}: {}\r\n", ALIASES_FILE, e),
+        }
+    }
+    aliases
+});
+
+/// Maps a fence tag to the name bat uses for the language.
+pub fn resolve_alias(language: &str) -> String {
+    ALIASES
+        .get(&language.to_lowercase())
+        .cloned()
+        .unwrap_or_else(|| language.to_owned())
+}
+
+// (language, pattern, weight). The names are ones bat knows.
+static RULES: LazyLock<Vec<(&'static str, Regex, u32)>> =
+    LazyLock::new(|| {
+        [
+        ("rust", r"(?m)^\s*(?:pub(?:\(crate\))? )?fn \w+", 3),
+        ("rust", r"\blet mut\b", 3),
+        ("rust", r"(?m)^\s*(?:impl|trait|mod)\b", 3),
+        ("rust", r"(?m)^\s*use \w+(?:::\w+)+", 3),
+        ("rust", r"\w+!\(", 2),
+        ("rust", r"&(?:mut |'\w+ )?self\b", 3),
+        ("python", r"(?m)^\s*def \w+\(.*\)\s*(?:->.*)?:\s*$", 4),
+        ("python", r"(?m)^\s*(?:from \w+(?:\.\w+)* )?import \w+\s*$", 2),
+        ("python", r"(?m)^\s*(?:elif|except)\b.*:\s*$", 4),
+        ("python", r"\bself\.\w+", 1),
+        ("python", r"\bprint\(", 1),
+        ("javascript", r"\b(?:const|let) \w+ = ", 2),
+        ("javascript", r"\bfunction\s*\w*\(", 2),
+        ("javascript", r"=>", 1),
+        ("javascript", r"\bconsole\.log\(", 4),
+        ("javascript", r"\brequire\(['\x22]", 4),
+        ("typescript", r"(?m)^\s*(?:export )?(?:interface|type) \w+", 3),
+        ("typescript", r"\w+\??: (?:string|number|boolean)\b", 4),
+        ("go", r"(?m)^package \w+", 5),
+        ("go", r"(?m)^func \w+", 4),
+        ("go", r":= ", 1),
+        ("go", r"\bfmt\.\w+\(", 4),
+        ("c", r"(?m)^#include\s*[<\x22]", 4),
+        ("c", r"\bint main\(", 2),
+        ("c", r"\bprintf\(", 2),
+        ("c++", r"\bstd::\w+", 4),
+        ("c++", r"(?m)^\s*template\s*<", 4),
+        ("java", r"\bpublic (?:static )?(?:class|void|final)\b", 4),
+        ("java", r"\bSystem\.out\.print", 5),
+        ("c#", r"(?m)^using System", 5),
+        ("c#", r"\bConsole\.Write", 5),
+        ("php", r"<\?php", 10),
+        ("html", r"(?i)<(?:!DOCTYPE html|html|div|body|head)\b", 5),
+        ("xml", r"<\?xml", 10),
+        ("sql", r"(?i)\bSELECT\b[\s\S]+\bFROM\b", 4),
+        ("sql", r"(?i)\b(?:CREATE TABLE|INSERT INTO|UPDATE \w+ SET)\b", 5),
+        ("bash", r"(?m)^#!.*\b(?:ba|z)?sh\b", 10),
+        (
+            "bash",
+            r"(?m)^\s*\$? ?(?:sudo|apt|apt-get|brew|cargo|npm|pip|git|cd|echo|export|mkdir|curl) ",
+            3,
+        ),
+        ("bash", r"(?m)^\s*(?:fi|done|esac)\s*$", 4),
+        ("dockerfile", r"(?m)^FROM \S+", 4),
+        ("dockerfile", r"(?m)^(?:RUN|COPY|WORKDIR|ENTRYPOINT|CMD) ", 3),
+        ("toml", r"(?m)^\[[\w.-]+\]\s*$", 3),
+        ("toml", r#"(?m)^[\w-]+ = (?:"|\d|\[|\{|true|false)"#, 2),
+        ("yaml", r"(?m)^[\w-]+:(?: .*)?$", 1),
+        ("yaml", r"(?m)^\s*- \w+", 1),
+        ("python", r"(?m)^#!.*python", 10),
+        ("javascript", r"(?m)^#!.*node", 10),
+    ]
+    .into_iter()
+    .map(|(language, pattern, weight)| (language, Regex::new(pattern).unwrap(), weight))
+    .collect()
+    });
+
+/// File extension for code in `language`, `txt` when unknown.
+pub fn extension(language: &str) -> &'static str {
+    match resolve_alias(language).to_lowercase().as_str() {
+        "rust" => "rs",
+        "python" => "py",
+        "javascript" => "js",
+        "typescript" => "ts",
+        "bash" => "sh",
+        "c" => "c",
+        "c++" => "cpp",
+        "c#" => "cs",
+        "f#" => "fs",
+        "go" => "go",
+        "java" => "java",
+        "kotlin" => "kt",
+        "ruby" => "rb",
+        "php" => "php",
+        "swift" => "swift",
+        "objective-c" => "m",
+        "lua" => "lua",
+        "sql" => "sql",
+        "html" => "html",
+        "css" => "css",
+        "json" => "json",
+        "yaml" => "yaml",
+        "toml" => "toml",
+        "xml" => "xml",
+        "markdown" => "md",
+        "powershell" => "ps1",
+        "haskell" => "hs",
+        "elixir" => "ex",
+        "scala" => "scala",
+        "zig" => "zig",
+        _ => "txt",
+    }
+}
+
+/// Guesses the language of an untagged code block from its content. Returns
+/// `None` when nothing stands out, in which case it is shown as plain text.
+pub fn detect(content: &str) -> Option<&'static str> {
+    let trimmed = content.trim();
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(trimmed).is_ok()
+    {
+        return Some("json");
+    }
+
+    let mut scores: Vec<(&'static str, u32)> = Vec::new();
+    for (language, re, weight) in RULES.iter() {
+        if re.is_match(content) {
+            match scores.iter_mut().find(|(l, _)| l == language) {
+                Some((_, score)) => *score += weight,
+                None => scores.push((language, *weight)),
+            }
+        }
+    }
+
+    // Ties go to the language listed first.
+    let mut best: Option<(&'static str, u32)> = None;
+    for (language, score) in scores {
+        if best.is_none_or(|(_, b)| score > b) {
+            best = Some((language, score));
+        }
+    }
+    best.filter(|(_, score)| *score >= 3).map(|(l, _)| l)
+}
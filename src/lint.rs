@@ -0,0 +1,172 @@
+//! Heuristics for messages that likely hurt reply quality, see
+//! `/lint_context`.
+use crate::models::Message;
+use crate::rate_limit::RateLimiter;
+
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+const LARGE_MESSAGE_TOKENS: usize = 3000;
+/// Exchanges at the end of the conversation that count as the current topic.
+const RECENT_EXCHANGES: usize = 3;
+const TANGENT_MIN_TOKENS: usize = 200;
+const TANGENT_MAX_OVERLAP: f64 = 0.05;
+
+pub struct Finding {
+    pub index: usize,
+    pub reason: String,
+}
+
+pub fn lint(messages: &[Message]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    large_logs(messages, &mut findings);
+    duplicate_blocks(messages, &mut findings);
+    contradictions(messages, &mut findings);
+    tangents(messages, &mut findings);
+    findings.sort_by_key(|f| f.index);
+    findings
+}
+
+fn large_logs(messages: &[Message], findings: &mut Vec<Finding>) {
+    static LOG_LINE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?i)(^\s*\[?\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}|\b(ERROR|WARN(ING)?|INFO|DEBUG|TRACE)\b|^\s+at \S+\(|^\s*File .*, line \d+)").unwrap()
+    });
+    for (index, message) in messages.iter().enumerate() {
+        let tokens = RateLimiter::estimate_tokens(&message.content);
+        if message.role != "user" || tokens < LARGE_MESSAGE_TOKENS {
+            continue;
+        }
+        let lines = message.content.lines().count().max(1);
+        let log_lines = message
+            .content
+            .lines()
+            .filter(|l| LOG_LINE.is_match(l))
+            .count();
+        let reason = match log_lines * 4 >= lines {
+            true => format!("pasted log of {} lines (~{} tokens)", lines, tokens),
+            false => format!("very large message (~{} tokens)", tokens),
+        };
+        findings.push(Finding { index, reason });
+    }
+}
+
+/// Fenced blocks, attached files included, longer than a few lines that
+/// appear again in a later message. The earlier copy is flagged.
+fn duplicate_blocks(messages: &[Message], findings: &mut Vec<Finding>) {
+    static BLOCK: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?s)```[^\n]*\n(.*?)```").unwrap());
+    let blocks: Vec<HashSet<&str>> = messages
+        .iter()
+        .map(|m| {
+            BLOCK
+                .captures_iter(&m.content)
+                .map(|c| c.get(1).unwrap().as_str().trim())
+                .filter(|b| b.lines().count() >= 5)
+                .collect()
+        })
+        .collect();
+    for (index, own) in blocks.iter().enumerate() {
+        let repeated = own
+            .iter()
+            .filter(|b| blocks[index + 1..].iter().any(|later| later.contains(*b)))
+            .count();
+        if repeated > 0 {
+            findings.push(Finding {
+                index,
+                reason: format!("{} block(s) attached again later", repeated),
+            });
+        }
+    }
+}
+
+/// Instructions like "use tabs" followed later by "don't use tabs". The
+/// overridden instruction is flagged.
+fn contradictions(messages: &[Message], findings: &mut Vec<Finding>) {
+    static INSTRUCTION: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?i)\b(always|never|don't|do not|avoid|stop|use|prefer)\s+(?:using\s+|use\s+)?(?:the\s+|a\s+|an\s+)?([a-z][\w\-]*)").unwrap()
+    });
+    let instructions: Vec<Vec<(bool, String)>> = messages
+        .iter()
+        .map(|m| match m.role.as_str() {
+            "user" | "system" => INSTRUCTION
+                .captures_iter(&m.content)
+                .map(|c| {
+                    let negative = matches!(
+                        c[1].to_lowercase().as_str(),
+                        "never" | "don't" | "do not" | "avoid" | "stop"
+                    );
+                    (negative, c[2].to_lowercase())
+                })
+                .collect(),
+            _ => vec![],
+        })
+        .collect();
+    for (index, own) in instructions.iter().enumerate() {
+        let overridden = own.iter().find(|(negative, object)| {
+            instructions[index + 1..]
+                .iter()
+                .flatten()
+                .any(|(n, o)| n != negative && o == object)
+        });
+        if let Some((negative, object)) = overridden {
+            findings.push(Finding {
+                index,
+                reason: format!(
+                    "\"{}{}\" is contradicted later",
+                    match negative {
+                        true => "don't ",
+                        false => "",
+                    },
+                    object
+                ),
+            });
+        }
+    }
+}
+
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() >= 4)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Older exchanges sharing almost no words with the recent ones.
+fn tangents(messages: &[Message], findings: &mut Vec<Finding>) {
+    let user_turns: Vec<usize> = messages
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| m.role == "user")
+        .map(|(i, _)| i)
+        .collect();
+    if user_turns.len() <= RECENT_EXCHANGES {
+        return;
+    }
+    let recent_start = user_turns[user_turns.len() - RECENT_EXCHANGES];
+    let recent: HashSet<String> = messages[recent_start..]
+        .iter()
+        .flat_map(|m| words(&m.content))
+        .collect();
+
+    for turn in user_turns.windows(2).map(|w| (w[0], w[1])) {
+        if turn.1 > recent_start {
+            break;
+        }
+        let exchange = &messages[turn.0..turn.1];
+        let text: String = exchange.iter().map(|m| m.content.as_str()).collect();
+        if RateLimiter::estimate_tokens(&text) < TANGENT_MIN_TOKENS {
+            continue;
+        }
+        let own = words(&text);
+        let overlap = own.intersection(&recent).count() as f64 / own.len().max(1) as f64;
+        if overlap < TANGENT_MAX_OVERLAP {
+            for index in turn.0..turn.1 {
+                findings.push(Finding {
+                    index,
+                    reason: "unrelated to the recent conversation".to_owned(),
+                });
+            }
+        }
+    }
+}
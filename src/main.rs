@@ -1,74 +1,442 @@
 #![allow(dead_code)]
 
 mod application;
+mod bookmarks;
 mod cli;
 mod commands;
+mod cron;
+mod crypto;
+mod digest;
+mod draft;
+mod eval;
+mod export;
+mod git;
 mod history;
+mod import;
+mod instructions;
+mod language;
+mod lint;
 mod models;
+mod notes;
 mod openai;
+mod pane;
+mod proxy;
+mod purge;
+mod rate_limit;
+mod recording;
+mod redact;
+mod render;
+mod report;
+mod request_template;
 mod response;
+mod runner;
+mod scaffold;
+mod serve;
+mod share;
+mod shell_hook;
+mod shutdown;
+mod staging;
+mod stats;
+mod storage;
 mod system_prompt;
+mod tail;
+mod theme;
 
 use cli::{ReadLine, CLI};
-use clipboard::{ClipboardContext, ClipboardProvider};
-use openai::send_request;
-use std::cell::RefCell;
-use std::io::{self, BufRead, IsTerminal, Write};
+use std::cell::{Cell, RefCell};
+use std::io::{self, IsTerminal, Write};
 use std::rc::Rc;
 use std::sync::Arc;
 
+const EDITOR_HELP: &str = "Write your message below. Lines starting with '#:' are ignored.
+Save and quit to send. Leave it empty or exit with an error (:cq) to abort;
+the text is kept and /editor will resume it. Use /editor last to start from
+your previous message.";
+
+/// Removes `flag` from `args`, returning whether it was there.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|a| a == flag) {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Adds a line to the input history, which is separate from the transcript.
+/// With redaction on, secrets are masked first, whatever was sent.
+fn save_input(app: &mut application::Application, input: &str) {
+    let input = match app.redact {
+        true => redact::redact_all(input),
+        false => input.to_owned(),
+    };
+    if let Err(e) = app.session_history.save_input(&input) {
+        eprint!("Failed to save entry: {}\r\n", e);
+    }
+}
+
+/// What quitting now would lose, if anything: guest prompts still waiting
+/// for their turn, or messages queued while offline.
+fn unfinished(app: &application::Application, guests_waiting: bool) -> Option<String> {
+    if guests_waiting {
+        return Some("Guest prompts are waiting to be answered.".to_owned());
+    }
+    match app.offline_queue.len() {
+        0 => None,
+        n => Some(format!("{} queued message(s) haven't been sent.", n)),
+    }
+}
+
+/// Makes the terminal stdin again after piped input has been read.
+#[cfg(unix)]
+fn reopen_tty() -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let tty = std::fs::File::open("/dev/tty")?;
+    if unsafe { libc::dup2(tty.as_raw_fd(), libc::STDIN_FILENO) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn reopen_tty() -> io::Result<()> {
+    Err(io::Error::other("not supported on this platform"))
+}
+
 fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    if take_flag(&mut args, "--local-only") {
+        openai::LOCAL_ONLY.store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+    let echo = take_flag(&mut args, "--echo");
+    let dry_run = take_flag(&mut args, "--dry-run");
+    let force_compact = take_flag(&mut args, "--compact");
+    // `chad-gpt serve`: the usual session, also shown as a web page.
+    let serve_port = match args.get(1).map(|s| s.as_str()) == Some("serve") {
+        true => {
+            args.remove(1);
+            match args.iter().position(|a| a == "--port") {
+                Some(i) => {
+                    let Some(port) = args.get(i + 1).and_then(|p| p.parse().ok()) else {
+                        eprintln!("Usage: {} serve [--port <port>]", args[0]);
+                        std::process::exit(2);
+                    };
+                    args.drain(i..=i + 1);
+                    Some(port)
+                }
+                None => Some(serve::DEFAULT_PORT),
+            }
+        }
+        false => None,
+    };
+    let hybrid_prompt = match args.iter().position(|a| a == "-i") {
+        Some(i) if i + 1 < args.len() => {
+            let prompt = args.remove(i + 1);
+            args.remove(i);
+            Some(prompt)
+        }
+        _ => None,
+    };
+    // `cat log | chad-gpt -i "question"`: answer about the piped input, then
+    // carry on interactively.
+    let piped = match &hybrid_prompt {
+        Some(_) if !io::stdin().is_terminal() => {
+            let piped = cli::read_piped(io::stdin().lock()).unwrap();
+            if let Err(e) = reopen_tty() {
+                eprintln!("Failed to open the terminal for interactive use: {}", e);
+                std::process::exit(2);
+            }
+            Some(piped)
+        }
+        _ => None,
+    };
+    if args.get(1).map(|s| s.as_str()) == Some("eval") {
+        let Some(path) = args.get(2) else {
+            eprintln!("Usage: {} eval <suite.yaml>", args[0]);
+            std::process::exit(2);
+        };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        match rt.block_on(eval::run_suite(path)) {
+            Ok(true) => std::process::exit(0),
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Failed to run suite {}: {}", path, e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    if args.get(1).map(|s| s.as_str()) == Some("report") {
+        if let Err(e) = report::run(&args[2..]) {
+            eprintln!("Failed to build report: {}", e);
+            eprintln!("Usage: {} report [--month YYYY-MM] [--csv]", args[0]);
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    if args.iter().any(|a| a == "--tail") {
+        if let Err(e) = tail::run(&args[1..]) {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    if args.get(1).map(|s| s.as_str()) == Some("cron") {
+        if let Err(e) = cron::run(&args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    if args.get(1).map(|s| s.as_str()) == Some("proxy") {
+        if let Err(e) = proxy::run(&args[2..]) {
+            eprintln!("Failed to run the proxy: {}", e);
+            eprintln!("Usage: {} proxy [--port <port>] [--no-redact]", args[0]);
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    if args.get(1).map(|s| s.as_str()) == Some("render") {
+        if let Err(e) = render::run(&args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.get(1).map(|s| s.as_str()) == Some("replay") {
+        if let Err(e) = recording::replay(&args[2..]) {
+            eprintln!("Failed to replay: {}", e);
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    if args.get(1).map(|s| s.as_str()) == Some("purge") {
+        if let Err(e) = purge::run(&args[2..]) {
+            eprintln!("Failed to purge: {}", e);
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    if args.get(1).map(|s| s.as_str()) == Some("shell-hook") {
+        match args.get(2).and_then(|shell| shell_hook::hook(shell)) {
+            Some(hook) => println!("{}", hook),
+            None => {
+                eprintln!(
+                    "Usage: {} shell-hook <{}>",
+                    args[0],
+                    shell_hook::SHELLS.join("|")
+                );
+                std::process::exit(2);
+            }
+        }
+        return;
+    }
+
+    if args.get(1).map(|s| s.as_str()) == Some("attach") {
+        let Some(address) = args.get(2) else {
+            eprintln!("Usage: {} attach <socket path|host:port>", args[0]);
+            std::process::exit(2);
+        };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        if let Err(e) = rt.block_on(share::attach(address)) {
+            eprintln!("Failed to attach to {}: {}", address, e);
+            std::process::exit(2);
+        }
+        // stdin is still being read on a runtime thread.
+        std::process::exit(0);
+    }
+
+    let _ = std::fs::create_dir_all(application::get_data_dir());
+    if let Err(e) = crypto::init() {
+        eprintln!("Failed to set up encryption: {}", e);
+        std::process::exit(2);
+    }
+
     let gapp = Rc::new(RefCell::new(application::Application::new()));
+    gapp.borrow_mut().apply_model_pins(&[]);
+    gapp.borrow_mut().echo = echo;
+    gapp.borrow_mut().dry_run = dry_run;
+    if let Some(port) = serve_port {
+        let mut app = gapp.borrow_mut();
+        let context = Arc::clone(&app.context);
+        match app.tokio_rt.block_on(serve::Server::start(port, context)) {
+            Ok(server) => {
+                print!("Serving the conversation on http://127.0.0.1:{}\r\n", port);
+                app.server = Some(server);
+            }
+            Err(e) => {
+                eprintln!("Failed to serve on port {}: {}", port, e);
+                std::process::exit(2);
+            }
+        }
+    }
     let mut command_registry = commands::CommandRegistry::new();
     command_registry.register_default_commands();
 
+    // Shows the session and model in the tab title, restored on exit.
+    let set_title = io::stdin().is_terminal()
+        && std::env::var("CHAD_LLM_TERMINAL_TITLE").map_or(true, |v| v != "0");
+    let mut title = String::new();
+    if set_title {
+        cli::save_title();
+    }
+    shutdown::install(&gapp.borrow().tokio_rt, set_title);
+
     if io::stdin().is_terminal() {
         // Load previous history entries
-        match gapp.borrow_mut().session_history.load_history() {
-            Ok(entries) => {
-                for entry in entries {
-                    print!(" {}\r\n", entry);
+        let mut app = gapp.borrow_mut();
+        // Warm the model list cache so /set_model opens instantly.
+        if openai::cached_models().is_none_or(|(_, stale)| stale) {
+            app.tokio_rt.spawn(openai::refresh_models());
+        }
+        if let Err(e) = app.session_history.apply_retention() {
+            eprint!("Failed to apply history retention: {}\r\n", e);
+        }
+        // Seed the input history so suggestions and Up work across sessions.
+        match app.session_history.load_inputs() {
+            Ok(inputs) => {
+                for input in inputs {
+                    cli::History::<String>::write(&mut app.cli_history, &input);
                 }
             }
+            Err(e) => eprint!("Failed to load input history: {}\r\n", e),
+        }
+        match app.session_history.load_sessions() {
+            Ok(sessions) => {
+                app.restored_history = sessions.concat();
+                app.show_restored_history();
+            }
             Err(e) => eprint!("Failed to load history: {}\r\n", e),
         }
     }
 
+    if let Some(prompt) = hybrid_prompt {
+        let mut app = gapp.borrow_mut();
+        if let Some(piped) = piped.filter(|p| !p.trim().is_empty()) {
+            app.staging.add(staging::StagedPart::Stdin(piped));
+        }
+        print!("> {}\r\n", prompt);
+        app.send_message(&prompt);
+        print!("\r\n");
+    }
+
     loop {
-        let mut input = String::new();
-        if !io::stdin().is_terminal() {
-            for line in io::stdin().lock().lines() {
-                input.push_str(&line.unwrap());
+        {
+            let mut app = gapp.borrow_mut();
+            if app.offline && app.tokio_rt.block_on(openai::is_online(&app.model)) {
+                app.offline = false;
+                let queued = std::mem::take(&mut app.offline_queue);
+                print!(
+                    "Back online. Sending {} queued message(s).\r\n",
+                    queued.len()
+                );
+                for input in queued {
+                    print!("> {}\r\n", input);
+                    app.send_message(&input);
+                    print!("\r\n");
+                }
+            }
+        }
+
+        {
+            // Guests of a shared session take their turn between the host's.
+            let mut app = gapp.borrow_mut();
+            while let Some(input) = app
+                .share
+                .as_ref()
+                .and_then(|share| share.prompts.lock().unwrap().pop_front())
+            {
+                print!("> [guest] {}\r\n", input);
+                app.send_guest_message(&input);
+                print!("\r\n");
+            }
+        }
+
+        if set_title {
+            let app = gapp.borrow();
+            let current = format!("chad-llm: {} ({})", app.session_title(), app.model);
+            if current != title {
+                cli::set_title(&current);
+                title = current;
             }
+        }
+
+        {
+            // Checked every time so resizing a tmux pane switches profiles.
+            let mut app = gapp.borrow_mut();
+            app.compact = force_compact || cli::narrow_terminal();
+            response::COMPACT.store(app.compact, std::sync::atomic::Ordering::Relaxed);
+        }
+
+        let mut input: String;
+        let eof = Cell::new(false);
+        if !io::stdin().is_terminal() {
+            // Windows pipes hand us CRLF line endings.
+            input = cli::read_piped(io::stdin().lock()).unwrap();
         } else {
             {
                 let mut app = gapp.borrow_mut();
+                let app = &mut *app;
+                let prompt = app.prompt();
+                let rprompt = app.rprompt();
+                let guest_prompts = app.share.as_ref().map(|share| Arc::clone(&share.prompts));
+                let guest_waiting = || {
+                    guest_prompts
+                        .as_ref()
+                        .is_some_and(|p| !p.lock().unwrap().is_empty())
+                };
                 input = match ReadLine::<String>::new()
-                    .prompt(&format!("[$green]{} [$/]> ", whoami::realname()))
+                    .prompt(&prompt)
+                    .rprompt(&rprompt)
+                    .interrupt(&guest_waiting)
                     .completion(&command_registry)
                     .history(&mut app.cli_history)
+                    .abbreviations(&app.abbreviations)
+                    .eof(&eof)
                     .run()
                 {
                     Some(x) => x,
+                    None if eof.take() => match unfinished(app, guest_waiting()) {
+                        Some(what)
+                            if CLI::read_key(&format!("{} Quit anyway? [y/n]", what), "yn")
+                                != Some('y') =>
+                        {
+                            continue
+                        }
+                        _ => break,
+                    },
                     None => continue,
                 };
-            }
-
-            // Save the input to history
-            {
-                let app = gapp.borrow_mut();
-                if let Err(e) = app.session_history.save_entry(&input) {
-                    eprint!("Failed to save entry: {}\r\n", e);
-                }
+                app.rotate_daily_session();
             }
 
             // Check if a command, and if so, then parse it.
             if input.starts_with('/') && input.len() > 1 {
+                // Command lines are kept for recall only, never in the transcript.
+                save_input(&mut gapp.borrow_mut(), &input);
                 let mut args = Vec::<&str>::new();
                 let mut name: &str = "<unknown command>";
                 let mut first = true;
 
                 input = input.strip_prefix('/').unwrap().to_owned();
+                // `/command args > $name` binds the command's result.
+                let mut bind_to = None;
+                if let Some((command, name)) = input.rsplit_once(" > $") {
+                    if !name.is_empty()
+                        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    {
+                        bind_to = Some(name.to_owned());
+                        input = command.to_owned();
+                    }
+                }
                 let input_cmd = input.clone();
                 for arg in input_cmd.split(' ') {
                     if arg == "" {
@@ -82,39 +450,50 @@ fn main() {
                     first = false;
                 }
 
-                if name == "paste" {
-                    // FIXME: Register this as a command.
-                    let mut clipboard: ClipboardContext = ClipboardProvider::new().unwrap();
-                    match clipboard.get_contents() {
-                        Ok(paste_content) => {
-                            print!("\n{}", paste_content); // Print the clipboard content
-                            std::io::stdout().flush().unwrap();
-
-                            let additional_input = ReadLine::<String>::new()
-                                .prompt("Add additional details")
-                                .run()
-                                .unwrap();
-
-                            // Aggregate the clipboard content and additional input
-                            input.push_str(&paste_content);
-                            input.push_str(&additional_input);
-                        }
-                        Err(err) => eprint!("Failed to read clipboard: {}\r\n", err),
-                    }
-                } else if name == "editor" {
-                    if let Some(inp) = CLI::editor("") {
-                        input = inp
+                if name == "editor" {
+                    let mut app = gapp.borrow_mut();
+                    let original = if args.first() == Some(&"last") {
+                        app.last_user_message().unwrap_or_default()
+                    } else if let Some(draft) = app.editor_draft.take() {
+                        draft
                     } else {
-                        print!("Aborted!\r\n");
-                        continue;
+                        app.editor_template()
+                    };
+
+                    match CLI::editor_with_header(EDITOR_HELP, &original) {
+                        Ok(inp) => input = inp,
+                        Err(draft) => {
+                            if !draft.trim().is_empty() {
+                                app.editor_draft = Some(draft);
+                                print!("Aborted! Run /editor again to resume.\r\n");
+                            } else {
+                                print!("Aborted!\r\n");
+                            }
+                            continue;
+                        }
                     }
                 } else if name == "quit" || name == "exit" {
                     break;
                 } else {
+                    gapp.borrow_mut().last_result = None;
                     let res = command_registry.execute_command(name, args, gapp.clone());
-                    match res {
-                        Ok(()) => print!("Command executed successfuly!\r\n"),
-                        Err(e) => print!("Failed to execute command. Reason: {:?}\r\n", e),
+                    let status = match res {
+                        Ok(()) => "Command executed successfuly!".to_owned(),
+                        Err(e) => format!("Failed to execute command. Reason: {:?}", e),
+                    };
+                    match gapp.borrow().compact {
+                        true => print!("{}\r\n", cli::fit_to_terminal(&status)),
+                        false => print!("{}\r\n", status),
+                    }
+                    if let Some(var) = bind_to {
+                        let mut app = gapp.borrow_mut();
+                        match app.last_result.take() {
+                            Some(value) => {
+                                print!("Bound ${}.\r\n", var);
+                                app.variables.insert(var, value);
+                            }
+                            None => print!("/{} has no result to bind.\r\n", name),
+                        }
                     }
 
                     continue;
@@ -123,32 +502,11 @@ fn main() {
         }
 
         let mut app = gapp.borrow_mut();
-        let response_stream =
-            app.tokio_rt
-                .block_on(send_request(&input, Arc::clone(&app.context), &app.model));
-        match response_stream {
-            Ok(stream) => {
-                let mut code_blocks = std::mem::take(&mut app.code_blocks);
-
-                let response = app.tokio_rt.block_on(response::process_response(
-                    Box::pin(stream),
-                    &mut code_blocks,
-                    !app.markdown,
-                ));
-
-                app.code_blocks = code_blocks;
-
-                match response {
-                    Ok(resp) => {
-                        // Save the GPT response to history
-                        if let Err(e) = app.session_history.save_response(&resp) {
-                            eprint!("Failed to save response: {}\r\n", e);
-                        }
-                    }
-                    Err(err) => eprint!("Failed to process response: {}\r\n", err),
-                }
-            }
-            Err(err) => eprint!("Request failed: {}\r\n", err),
+        let typed = input;
+        let input = app.interpolate(&typed);
+        // Messages are kept for recall once actually sent.
+        if app.send_message(&input) && io::stdin().is_terminal() {
+            save_input(&mut app, &typed);
         }
 
         print!("\r\n");
@@ -158,4 +516,7 @@ fn main() {
             break;
         }
     }
+    if set_title {
+        cli::restore_title();
+    }
 }
@@ -1,9 +1,39 @@
 use serde::{Deserialize, Serialize};
 
+use std::time::Instant;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Message {
     pub role: String,
     pub content: String,
+    /// Local bookkeeping, never sent to the API.
+    #[serde(skip)]
+    pub metadata: MessageMetadata,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MessageMetadata {
+    /// The model that actually produced an assistant message.
+    pub model: Option<String>,
+    /// The persona an assistant message was addressed to.
+    pub persona: Option<String>,
+    /// What the provider needs to look into or reproduce a reply: its request
+    /// ID, the model snapshot that answered, the seed sent and the system
+    /// fingerprint.
+    pub request_id: Option<String>,
+    pub snapshot: Option<String>,
+    pub seed: Option<u64>,
+    pub fingerprint: Option<String>,
+}
+
+impl Message {
+    pub fn new(role: &str, content: &str) -> Self {
+        Self {
+            role: role.to_owned(),
+            content: content.to_owned(),
+            metadata: MessageMetadata::default(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,3 +50,119 @@ pub struct Choice {
 pub struct Delta {
     pub content: Option<String>,
 }
+
+#[derive(Debug, Clone, Default)]
+pub struct CodeBlock {
+    pub language: String,
+    pub content: String,
+    /// The reply ended before the block's closing fence.
+    pub truncated: bool,
+}
+
+/// Something put on the clipboard by one of the copy commands.
+#[derive(Debug, Clone)]
+pub struct CopiedItem {
+    pub copied_at: Instant,
+    pub source: String,
+    pub content: String,
+}
+
+/// A model the session is required to use, see `model_pins.json`.
+#[derive(Debug, Clone)]
+pub struct ModelPin {
+    pub model: String,
+    /// What requires it, e.g. `tag prod-incident`.
+    pub reason: String,
+}
+
+/// An assistant addressed as `@name` in a message.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Persona {
+    #[serde(default)]
+    pub color: Option<String>,
+    pub system_prompt: String,
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Sampling parameters sent with every request.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Sampling {
+    pub temperature: f64,
+    #[serde(default)]
+    pub top_p: Option<f64>,
+    /// Asks the provider for repeatable sampling, see `CHAD_LLM_SEED`.
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self {
+            temperature: 0.5,
+            top_p: None,
+            seed: None,
+        }
+    }
+}
+
+/// Token counts reported by the API for a single request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cached_tokens: u64,
+}
+
+/// A generated token with its log probability and the most likely
+/// alternatives at its position.
+#[derive(Debug, Clone)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub top: Vec<(String, f64)>,
+}
+
+impl TokenLogprob {
+    pub fn probability(&self) -> f64 {
+        self.logprob.exp()
+    }
+}
+
+/// Restricts replies to a shape scripts can parse, see `/constrain`.
+#[derive(Debug, Clone)]
+pub enum Constraint {
+    /// A GBNF grammar, understood by llama.cpp.
+    Grammar(String),
+    /// A JSON schema, sent as an OpenAI style `response_format`.
+    JsonSchema(serde_json::Value),
+}
+
+/// What a main conversation request asks for beyond sampling, set with
+/// `/confidence` and `/constrain`. Internal requests use the default.
+#[derive(Debug, Clone, Default)]
+pub struct ReplyFormat {
+    pub logprobs: bool,
+    pub constraint: Option<Constraint>,
+}
+
+/// What `send_request` streams back while a reply is being generated.
+#[derive(Debug)]
+pub enum StreamEvent {
+    Token(String),
+    ToolCallDelta {
+        index: usize,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: String,
+    },
+    Usage(TokenUsage),
+    /// Sent alongside the tokens when logprobs were requested.
+    Logprobs(Vec<TokenLogprob>),
+    Done {
+        finish_reason: Option<String>,
+    },
+    /// Data without content, such as SSE comments sent during long pauses.
+    KeepAlive,
+    Error(std::io::Error),
+}
@@ -0,0 +1,58 @@
+//! Notes about the project in the working directory, kept by `/note` in
+//! `.chad-llm-notes.md` next to the project settings and added to the system
+//! message of every session started there.
+use crate::rate_limit::RateLimiter;
+
+use std::io;
+
+pub const NOTES_FILE: &str = ".chad-llm-notes.md";
+/// Notes past this many tokens are left out of the system message, see
+/// `CHAD_LLM_NOTES_TOKENS`.
+const DEFAULT_TOKEN_BUDGET: usize = 500;
+
+/// The notes in the order they were added, one `- ` list item each.
+pub fn load() -> Vec<String> {
+    std::fs::read_to_string(NOTES_FILE)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.strip_prefix("- "))
+        .map(String::from)
+        .collect()
+}
+
+pub fn save(notes: &[String]) -> io::Result<()> {
+    if notes.is_empty() {
+        return match std::fs::remove_file(NOTES_FILE) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    let content: String = notes.iter().map(|n| format!("- {}\n", n)).collect();
+    std::fs::write(NOTES_FILE, content)
+}
+
+fn token_budget() -> usize {
+    std::env::var("CHAD_LLM_NOTES_TOKENS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_TOKEN_BUDGET)
+}
+
+/// The newest notes that fit the token budget, oldest first, and how many
+/// were left out.
+pub fn budgeted() -> (Vec<String>, usize) {
+    let notes = load();
+    let mut budget = token_budget();
+    let mut kept = Vec::new();
+    for note in notes.iter().rev() {
+        let tokens = RateLimiter::estimate_tokens(note);
+        if tokens > budget {
+            break;
+        }
+        budget -= tokens;
+        kept.push(note.clone());
+    }
+    kept.reverse();
+    let left_out = notes.len() - kept.len();
+    (kept, left_out)
+}
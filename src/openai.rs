@@ -1,30 +1,51 @@
-use crate::models::Message;
+use crate::application::get_data_path;
+use crate::models::{
+    Constraint, Message, MessageMetadata, ReplyFormat, Sampling, StreamEvent, TokenLogprob,
+    TokenUsage,
+};
+use crate::rate_limit::{self, RateLimiter};
+use crate::request_template;
+use crate::stats;
 
 use futures_util::Stream;
 use futures_util::StreamExt;
-use reqwest::Client;
+use reqwest::{Client, StatusCode, Url};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
 use tokio_stream::wrappers::ReceiverStream;
 
+use std::collections::HashMap;
 use std::env;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::sync::Mutex as SyncMutex;
 
 pub type SharedContext = Arc<Mutex<Vec<Message>>>;
 
+/// Token usage accumulated over all requests made in this process.
+pub static USAGE: SyncMutex<UsageStats> = SyncMutex::new(UsageStats {
+    requests: 0,
+    prompt_tokens: 0,
+    completion_tokens: 0,
+    cached_tokens: 0,
+});
+
+#[derive(Debug, Clone, Copy)]
+pub struct UsageStats {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cached_tokens: u64,
+}
+
 pub fn set_system_prompt(context: &mut Vec<Message>, content: &str) {
     if context.first().map_or(false, |m| m.role == "system") {
         context.remove(0);
     }
     if !content.is_empty() {
-        context.insert(
-            0,
-            Message {
-                role: "system".to_owned(),
-                content: content.to_owned(),
-            },
-        );
+        context.insert(0, Message::new("system", content));
     }
 }
 
@@ -34,22 +55,119 @@ pub struct ChatRequest {
     pub messages: Vec<Message>,
     pub max_tokens: i64,
     pub temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
     pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grammar: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamOptions {
+    pub include_usage: bool,
 }
 
 #[derive(Deserialize)]
 struct Chunk {
     choices: Vec<Choice>,
+    usage: Option<Usage>,
+    model: Option<String>,
+    system_fingerprint: Option<String>,
+}
+
+/// A reply sent at once, by servers that don't stream.
+#[derive(Deserialize)]
+struct Completion {
+    choices: Vec<CompletionChoice>,
+    usage: Option<Usage>,
+    model: Option<String>,
+    system_fingerprint: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CompletionChoice {
+    message: CompletionMessage,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CompletionMessage {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<CompletionToolCall>,
+}
+
+#[derive(Deserialize)]
+struct CompletionToolCall {
+    id: Option<String>,
+    function: FunctionChunk,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    prompt_tokens_details: Option<PromptTokensDetails>,
+}
+
+#[derive(Deserialize)]
+struct PromptTokensDetails {
+    cached_tokens: u64,
 }
 
 #[derive(Deserialize)]
 struct Choice {
     delta: Delta,
+    finish_reason: Option<String>,
+    logprobs: Option<ChoiceLogprobs>,
+}
+
+#[derive(Deserialize)]
+struct ChoiceLogprobs {
+    content: Option<Vec<LogprobEntry>>,
+}
+
+#[derive(Deserialize)]
+struct LogprobEntry {
+    token: String,
+    logprob: f64,
+    #[serde(default)]
+    top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Deserialize)]
+struct TopLogprob {
+    token: String,
+    logprob: f64,
 }
 
 #[derive(Deserialize)]
 struct Delta {
     content: Option<String>,
+    tool_calls: Option<Vec<ToolCallChunk>>,
+}
+
+#[derive(Deserialize)]
+struct ToolCallChunk {
+    index: usize,
+    id: Option<String>,
+    function: Option<FunctionChunk>,
+}
+
+#[derive(Deserialize)]
+struct FunctionChunk {
+    name: Option<String>,
+    arguments: Option<String>,
 }
 
 pub static AVAILABLE_MODELS: &'static [&'static str] = &[
@@ -62,7 +180,148 @@ pub static AVAILABLE_MODELS: &'static [&'static str] = &[
     "o1-preview",
 ];
 
+/// Set once the server rejected `stream_options`.
+static NO_STREAM_OPTIONS: AtomicBool = AtomicBool::new(false);
+/// Set once the server rejected or ignored `stream: true`; replies are
+/// then requested whole and rendered at once.
+static NO_STREAMING: AtomicBool = AtomicBool::new(false);
+
+/// Alternatives reported for each token when log probabilities are asked.
+const TOP_LOGPROBS: u8 = 5;
+
+/// Set by `--local-only`: refuse to talk to anything but localhost.
+pub static LOCAL_ONLY: AtomicBool = AtomicBool::new(false);
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// The API base URL, overridable with `OPENAI_BASE_URL` to point at any
+/// OpenAI compatible server.
+pub fn base_url() -> String {
+    env::var("OPENAI_BASE_URL")
+        .map(|u| u.trim_end_matches('/').to_owned())
+        .unwrap_or_else(|_| DEFAULT_BASE_URL.to_owned())
+}
+
+/// Short provider name used in statistics, the host of the base URL.
+pub fn provider_name() -> String {
+    Url::parse(&base_url())
+        .ok()
+        .and_then(|u| u.host_str().map(String::from))
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+/// The short provider name for requests to `model`.
+pub fn provider_of(model: &str) -> String {
+    match model.starts_with(OLLAMA_PREFIX) {
+        true => "ollama".to_owned(),
+        false => provider_name(),
+    }
+}
+
+fn try_api_key() -> Option<String> {
+    match env::var("OPENAI_API_KEY") {
+        Ok(key) => Some(key),
+        // Local servers usually don't need a key.
+        Err(_) if env::var("OPENAI_BASE_URL").is_ok() => Some(String::new()),
+        Err(_) => None,
+    }
+}
+
+fn api_key() -> String {
+    try_api_key().unwrap_or_else(|| panic!("OPENAI_API_KEY not set"))
+}
+
+/// Prefix of the models served by a local Ollama server, as in
+/// `ollama:llama3`.
+pub const OLLAMA_PREFIX: &str = "ollama:";
+const DEFAULT_OLLAMA_HOST: &str = "http://localhost:11434";
+
+/// The OpenAI compatible API of the Ollama server, found through
+/// `OLLAMA_HOST` like the `ollama` command does.
+fn ollama_url() -> String {
+    let host = env::var("OLLAMA_HOST").unwrap_or_else(|_| DEFAULT_OLLAMA_HOST.to_owned());
+    let host = host.trim_end_matches('/');
+    match host.contains("://") {
+        true => format!("{}/v1", host),
+        false => format!("http://{}/v1", host),
+    }
+}
+
+/// Where requests for `model` go: the base URL, the name the server knows
+/// the model by and the API key.
+fn backend(model: &str) -> (String, &str, String) {
+    match model.strip_prefix(OLLAMA_PREFIX) {
+        Some(name) => (ollama_url(), name, String::new()),
+        None => (base_url(), model, api_key()),
+    }
+}
+
+/// Builds an endpoint URL, enforcing local-only mode.
+fn endpoint(path: &str) -> Result<Url, std::io::Error> {
+    endpoint_at(&base_url(), path)
+}
+
+fn endpoint_at(base: &str, path: &str) -> Result<Url, std::io::Error> {
+    let url = Url::parse(&format!("{}/{}", base, path))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+
+    if LOCAL_ONLY.load(Ordering::Relaxed) {
+        let local = match url.host_str() {
+            Some("localhost") => true,
+            Some(host) => host
+                .trim_matches(['[', ']'])
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback()),
+            None => false,
+        };
+        if !local {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("local-only mode refuses to send data to {}", url),
+            ));
+        }
+    }
+    Ok(url)
+}
+
+/// Cheap connectivity probe used to leave the offline state, for the
+/// server of `model`.
+pub async fn is_online(model: &str) -> bool {
+    let base = match model.starts_with(OLLAMA_PREFIX) {
+        true => ollama_url(),
+        false => base_url(),
+    };
+    let Ok(url) = endpoint_at(&base, "models") else {
+        return false;
+    };
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return false;
+    };
+    let connect = tokio::net::TcpStream::connect((host.to_owned(), port));
+    matches!(
+        tokio::time::timeout(std::time::Duration::from_secs(3), connect).await,
+        Ok(Ok(_))
+    )
+}
+
 pub async fn get_models() -> Option<Vec<String>> {
+    fetch_models(&base_url(), &try_api_key()?).await
+}
+
+/// The models of the Ollama server, with `OLLAMA_PREFIX`. Empty when it
+/// isn't running.
+pub async fn ollama_models() -> Vec<String> {
+    let url = ollama_url();
+    match tokio::time::timeout(std::time::Duration::from_secs(2), fetch_models(&url, "")).await {
+        Ok(Some(models)) => models
+            .into_iter()
+            .map(|model| format!("{}{}", OLLAMA_PREFIX, model))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+async fn fetch_models(base: &str, api_key: &str) -> Option<Vec<String>> {
     #[derive(Deserialize)]
     struct Model {
         id: String,
@@ -74,8 +333,7 @@ pub async fn get_models() -> Option<Vec<String>> {
     }
 
     let client = Client::new();
-    let api_key = env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set");
-    let url = "https://api.openai.com/v1/models";
+    let url = endpoint_at(base, "models").ok()?;
 
     let response = client
         .get(url)
@@ -88,64 +346,655 @@ pub async fn get_models() -> Option<Vec<String>> {
     Some(body.data.into_iter().map(|model| model.id).collect())
 }
 
+pub const MODELS_CACHE_FILE: &str = "models_cache.json";
+const DEFAULT_MODELS_TTL_HOURS: u64 = 24;
+
+#[derive(Serialize, Deserialize)]
+struct CachedModels {
+    fetched: u64,
+    models: Vec<String>,
+}
+
+fn load_models_cache() -> HashMap<String, CachedModels> {
+    std::fs::read_to_string(get_data_path(MODELS_CACHE_FILE))
+        .ok()
+        .and_then(|c| serde_json::from_str(&c).ok())
+        .unwrap_or_default()
+}
+
+/// The model list last fetched from the current provider, and whether it
+/// is older than `CHAD_LLM_MODELS_TTL_HOURS` (24 by default).
+pub fn cached_models() -> Option<(Vec<String>, bool)> {
+    let ttl = env::var("CHAD_LLM_MODELS_TTL_HOURS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MODELS_TTL_HOURS);
+    let cached = load_models_cache().remove(&base_url())?;
+    let stale = stats::now().saturating_sub(cached.fetched) > ttl * 3600;
+    Some((cached.models, stale))
+}
+
+/// Fetches the model list and stores it in the cache.
+pub async fn refresh_models() -> Option<Vec<String>> {
+    let models = get_models().await?;
+    let mut cache = load_models_cache();
+    cache.insert(
+        base_url(),
+        CachedModels {
+            fetched: stats::now(),
+            models: models.clone(),
+        },
+    );
+    if let Ok(contents) = serde_json::to_string(&cache) {
+        let _ = std::fs::write(get_data_path(MODELS_CACHE_FILE), contents);
+    }
+    Some(models)
+}
+
+/// The request parameter an error body says the server doesn't support:
+/// OpenAI style servers name it in `error.param`, others quote it in the
+/// message.
+fn rejected_parameter(body: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(body.trim()).ok()?;
+    let error = &value["error"];
+    if let Some(param) = error["param"].as_str() {
+        return Some(param.to_owned());
+    }
+    let message = error["message"].as_str().or(error.as_str())?;
+    ["stream_options", "stream", "tools", "tool_choice"]
+        .into_iter()
+        .find(|name| {
+            ['\'', '"', '`']
+                .iter()
+                .any(|quote| message.contains(&format!("{}{}{}", quote, name, quote)))
+        })
+        .map(String::from)
+}
+
+/// Recognizes servers refusing a streaming feature, so the request can be
+/// retried without it. Returns whether anything was turned off.
+fn degrade(status: StatusCode, body: &str) -> bool {
+    if status != StatusCode::BAD_REQUEST
+        && status != StatusCode::UNPROCESSABLE_ENTITY
+        && status != StatusCode::NOT_IMPLEMENTED
+    {
+        return false;
+    }
+    match rejected_parameter(body).as_deref() {
+        Some("stream_options") if !NO_STREAM_OPTIONS.swap(true, Ordering::Relaxed) => {
+            print!(
+                "The server doesn't support usage reporting while streaming, retrying without.\r\n"
+            );
+            true
+        }
+        Some("stream") if !NO_STREAMING.swap(true, Ordering::Relaxed) => {
+            print!(
+                "The server doesn't support streaming, replies will be shown when complete.\r\n"
+            );
+            true
+        }
+        // Some servers only return tool calls in whole replies.
+        Some("tools" | "tool_choice") if !NO_STREAMING.swap(true, Ordering::Relaxed) => {
+            print!(
+                "The server doesn't stream tool calls, replies will be shown when complete.\r\n"
+            );
+            true
+        }
+        _ => false,
+    }
+}
+
+fn record_usage(usage: Usage) -> TokenUsage {
+    let usage = TokenUsage {
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+        cached_tokens: usage.prompt_tokens_details.map_or(0, |d| d.cached_tokens),
+    };
+    let mut stats = USAGE.lock().unwrap();
+    stats.requests += 1;
+    stats.prompt_tokens += usage.prompt_tokens;
+    stats.completion_tokens += usage.completion_tokens;
+    stats.cached_tokens += usage.cached_tokens;
+    usage
+}
+
+/// The provider's own account of an error from a body such as
+/// `{"error": {"message": ..., "code": ...}}`, or `None` for other bodies.
+fn api_error(body: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(body.trim()).ok()?;
+    let error = value.get("error")?;
+    if let Some(message) = error.as_str() {
+        return Some(message.to_owned());
+    }
+    let mut text = error["message"].as_str()?.to_owned();
+    let code = match &error["code"] {
+        serde_json::Value::String(code) => Some(code.clone()),
+        serde_json::Value::Number(code) => Some(code.to_string()),
+        _ => error["type"].as_str().map(String::from),
+    };
+    if let Some(code) = code {
+        text.push_str(&format!(" ({})", code));
+    }
+    Some(text)
+}
+
+/// The ID to quote when asking the provider about a failed request.
+fn request_id(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    ["x-request-id", "request-id"]
+        .iter()
+        .find_map(|name| headers.get(*name)?.to_str().ok())
+        .map(String::from)
+}
+
+/// A failed response, with the provider's message in place of the raw body
+/// where there is one.
+fn describe_failure(model: &str, status: StatusCode, body: &str, id: Option<String>) -> String {
+    let mut text = match api_error(body) {
+        Some(error) => format!("{} returned {}: {}", model, status, error),
+        None if body.trim().is_empty() => format!("{} returned {}", model, status),
+        None => format!("{} returned {}: {}", model, status, body.trim()),
+    };
+    if let Some(id) = id {
+        text.push_str(&format!(" [request {}]", id));
+    }
+    text
+}
+
+fn is_fallback_status(status: StatusCode) -> bool {
+    status == StatusCode::UNAUTHORIZED
+        || status == StatusCode::FORBIDDEN
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+}
+
+/// Sends a single prompt outside any conversation and returns the whole
+/// reply.
+pub async fn complete(
+    model: &str,
+    system: Option<&str>,
+    prompt: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut messages: Vec<Message> = Vec::new();
+    if let Some(system) = system {
+        set_system_prompt(&mut messages, system);
+    }
+    complete_in(model, messages, prompt, Sampling::default()).await
+}
+
+/// Like `complete`, but continues the conversation in `messages`.
+pub async fn complete_in(
+    model: &str,
+    messages: Vec<Message>,
+    prompt: &str,
+    sampling: Sampling,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let context = Arc::new(Mutex::new(messages));
+    let (_, stream) = send_request(
+        prompt,
+        context,
+        model,
+        &[],
+        sampling,
+        &ReplyFormat::default(),
+    )
+    .await?;
+    let mut stream = Box::pin(stream);
+    let mut response = String::new();
+    while let Some(event) = stream.next().await {
+        match event {
+            StreamEvent::Token(token) => response.push_str(&token),
+            StreamEvent::Error(e) => return Err(Box::new(e)),
+            _ => {}
+        }
+    }
+    Ok(response)
+}
+
+/// The request for `messages` with the current streaming settings.
+fn chat_request(
+    model: &str,
+    messages: Vec<Message>,
+    sampling: Sampling,
+    format: &ReplyFormat,
+) -> ChatRequest {
+    let logprobs = format.logprobs;
+    let streaming = !NO_STREAMING.load(Ordering::Relaxed);
+    ChatRequest {
+        model: model.to_owned(),
+        messages,
+        max_tokens: 2048,
+        temperature: sampling.temperature,
+        top_p: sampling.top_p,
+        seed: sampling.seed,
+        stream: streaming,
+        // OpenAI caches stable prompt prefixes (the system prompt comes first)
+        // on its own; the final usage chunk reports the cached token count.
+        stream_options: (streaming && !NO_STREAM_OPTIONS.load(Ordering::Relaxed)).then_some(
+            StreamOptions {
+                include_usage: true,
+            },
+        ),
+        logprobs: logprobs.then_some(true),
+        top_logprobs: logprobs.then_some(TOP_LOGPROBS),
+        response_format: match &format.constraint {
+            Some(Constraint::JsonSchema(schema)) => Some(serde_json::json!({
+                "type": "json_schema",
+                "json_schema": { "name": "constraint", "schema": schema, "strict": true },
+            })),
+            _ => None,
+        },
+        grammar: match &format.constraint {
+            Some(Constraint::Grammar(grammar)) => Some(grammar.clone()),
+            _ => None,
+        },
+    }
+}
+
+/// Providers that only cache prompt prefixes marked with `cache_control`;
+/// OpenAI caches them on its own.
+const EXPLICIT_CACHING_HOSTS: &[&str] = &["api.anthropic.com", "openrouter.ai"];
+
+/// Whether requests to `model` mark their stable prefix for caching:
+/// automatically for the providers that need it, or with
+/// `CHAD_LLM_CACHE_CONTROL=1` (or `0` to never mark it).
+fn explicit_caching(model: &str) -> bool {
+    match env::var("CHAD_LLM_CACHE_CONTROL").as_deref() {
+        Ok("1") => true,
+        Ok("0") => false,
+        _ => {
+            !model.starts_with(OLLAMA_PREFIX)
+                && EXPLICIT_CACHING_HOSTS.contains(&provider_name().as_str())
+        }
+    }
+}
+
+/// Marks the end of the stable prefix of a request for the provider's
+/// prompt cache: the system message, and the conversation before the new
+/// prompt. Marked messages are sent as text parts.
+fn mark_cache_breakpoints(body: &mut serde_json::Value) {
+    let Some(messages) = body["messages"].as_array_mut() else {
+        return;
+    };
+    let mut marked = Vec::new();
+    if messages.first().is_some_and(|m| m["role"] == "system") {
+        marked.push(0);
+    }
+    if messages.len() >= 3 {
+        marked.push(messages.len() - 2);
+    }
+    for i in marked {
+        let text = messages[i]["content"].take();
+        messages[i]["content"] = serde_json::json!([{
+            "type": "text",
+            "text": text,
+            "cache_control": { "type": "ephemeral" },
+        }]);
+    }
+}
+
+/// The JSON body actually sent for `request` to `model`, after the request
+/// template.
+fn request_body_value(request: &ChatRequest, model: &str) -> serde_json::Result<serde_json::Value> {
+    let mut body = serde_json::to_value(request)?;
+    if explicit_caching(model) {
+        mark_cache_breakpoints(&mut body);
+    }
+    if let Some(template) = request_template::ACTIVE.as_ref() {
+        template.apply(&mut body);
+    }
+    Ok(body)
+}
+
+/// The body `send_request` would send for `input` in `context`, without
+/// sending it or changing the context.
+pub async fn preview_request(
+    input: &str,
+    context: SharedContext,
+    model: &str,
+    sampling: Sampling,
+    format: &ReplyFormat,
+) -> serde_json::Result<serde_json::Value> {
+    let mut messages = context.lock().await.clone();
+    messages.push(Message::new("user", input));
+    let name = model.strip_prefix(OLLAMA_PREFIX).unwrap_or(model);
+    request_body_value(&chat_request(name, messages, sampling, format), model)
+}
+
+/// Sends a chat completion `body` built by another client as it is, to the
+/// server of its model with that server's key and within its rate limits,
+/// for `chad-gpt proxy`.
+pub async fn forward(body: &serde_json::Value) -> Result<reqwest::Response, std::io::Error> {
+    let model = body["model"].as_str().unwrap_or_default();
+    if !model.starts_with(OLLAMA_PREFIX) && try_api_key().is_none() {
+        return Err(missing_key());
+    }
+    let (base, name, api_key) = backend(model);
+    let url = endpoint_at(&base, "chat/completions")?;
+    let mut body = body.clone();
+    if name != model {
+        // `ollama:llama3` is `llama3` to the Ollama server.
+        if let Some(object) = body.as_object_mut() {
+            object.insert("model".to_owned(), name.into());
+        }
+    }
+    let prompt_tokens: usize = body["messages"].as_array().map_or(0, |messages| {
+        messages
+            .iter()
+            .map(|m| RateLimiter::estimate_tokens(&m["content"].to_string()))
+            .sum()
+    });
+    let max_tokens = ["max_tokens", "max_completion_tokens"]
+        .iter()
+        .find_map(|key| body[key].as_u64())
+        .unwrap_or(0);
+    rate_limit::limiter(&provider_of(model))
+        .acquire(prompt_tokens + max_tokens as usize)
+        .await;
+
+    Client::new()
+        .post(url)
+        .header("Authorization", format!("Bearer {}", api_key))
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| match e.is_connect() || e.is_timeout() {
+            true => std::io::Error::new(std::io::ErrorKind::NotConnected, e.to_string()),
+            false => std::io::Error::other(e.to_string()),
+        })
+}
+
+/// The error for a request to the provider without `OPENAI_API_KEY`.
+fn missing_key() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::PermissionDenied,
+        "OPENAI_API_KEY is not set",
+    )
+}
+
+/// The provider's own `GET /models` response with the models of the local
+/// Ollama server added, as status and body, for `chad-gpt proxy`.
+pub async fn forward_models() -> Result<(StatusCode, String), std::io::Error> {
+    let api_key = try_api_key().ok_or_else(missing_key)?;
+    let response = Client::new()
+        .get(endpoint("models")?)
+        .header("Authorization", format!("Bearer {}", api_key))
+        .send()
+        .await
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    if !status.is_success() {
+        return Ok((status, body));
+    }
+    let Ok(mut list) = serde_json::from_str::<serde_json::Value>(&body) else {
+        return Ok((status, body));
+    };
+    if let Some(data) = list["data"].as_array_mut() {
+        data.extend(
+            ollama_models()
+                .await
+                .into_iter()
+                .map(|id| serde_json::json!({ "id": id, "object": "model", "owned_by": "ollama" })),
+        );
+    }
+    Ok((status, list.to_string()))
+}
+
+/// Sends `input` with the conversation in `context` to `model`. If the
+/// request fails with an auth, rate limit or outage error, each model in
+/// `fallbacks` is tried in turn. Returns the model that served the reply
+/// along with the reply stream.
 pub async fn send_request(
     input: &str,
     context: SharedContext,
     model: &str,
-) -> Result<impl Stream<Item = Result<String, std::io::Error>>, std::io::Error> {
+    fallbacks: &[String],
+    sampling: Sampling,
+    format: &ReplyFormat,
+) -> Result<(String, impl Stream<Item = StreamEvent>), std::io::Error> {
     let client = Client::new();
-    let api_key = env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set");
-    let url = "https://api.openai.com/v1/chat/completions";
 
     // Lock the context to access the stored messages and prepare the new message
     let messages = {
         let mut ctx = context.lock().await;
-        ctx.push(Message {
-            role: "user".to_string(),
-            content: input.to_string(),
-        });
+        ctx.push(Message::new("user", input));
         ctx.clone()
     };
 
-    let request_body = ChatRequest {
-        model: model.to_owned(),
-        messages: messages.clone(),
-        max_tokens: 2048,
-        temperature: 0.5,
-        stream: true,
-    };
+    let mut models = vec![model.to_owned()];
+    models.extend(fallbacks.iter().filter(|m| *m != model).cloned());
 
-    let response = client
-        .post(url)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .json(&request_body)
-        .send()
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+    let mut last_error = None;
+    let mut offline = false;
+    let mut served = None;
+    'models: for (i, model) in models.iter().enumerate() {
+        let (base, name, api_key) = backend(model);
+        let url = endpoint_at(&base, "chat/completions")?;
+        let error = loop {
+            let request_body = chat_request(name, messages.clone(), sampling, format);
+            let body = request_body_value(&request_body, model)?;
+
+            let prompt_tokens: usize = messages
+                .iter()
+                .map(|m| RateLimiter::estimate_tokens(&m.content))
+                .sum();
+            rate_limit::limiter(&provider_of(model))
+                .acquire(prompt_tokens + request_body.max_tokens as usize)
+                .await;
+
+            break match client
+                .post(url.clone())
+                .header("Authorization", format!("Bearer {}", api_key))
+                .json(&body)
+                .send()
+                .await
+            {
+                Ok(response) if response.status().is_success() => {
+                    served = Some((model.clone(), response));
+                    break 'models;
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let id = request_id(response.headers());
+                    let body = response.text().await.unwrap_or_default();
+                    if degrade(status, &body) {
+                        continue;
+                    }
+                    let error = describe_failure(model, status, &body, id);
+                    if !is_fallback_status(status) {
+                        last_error = Some(error);
+                        break 'models;
+                    }
+                    error
+                }
+                Err(e) => {
+                    offline = e.is_connect() || e.is_timeout();
+                    format!("{}: {}", model, e)
+                }
+            };
+        };
+
+        if let Some(next) = models.get(i + 1) {
+            print!("{}, falling back to {}.\r\n", error, next);
+        }
+        last_error = Some(error);
+    }
+
+    let (model, response) = match served {
+        Some(x) => x,
+        None => {
+            let kind = match offline {
+                true => std::io::ErrorKind::NotConnected,
+                false => std::io::ErrorKind::Other,
+            };
+            return Err(std::io::Error::new(kind, last_error.unwrap_or_default()));
+        }
+    };
 
     let (tx, rx) = mpsc::channel(100);
-    let mut stream = response.bytes_stream();
     let context_clone = Arc::clone(&context);
+    // Filled in from the reply as it arrives.
+    let mut metadata = MessageMetadata {
+        model: Some(model.clone()),
+        request_id: request_id(response.headers()),
+        seed: sampling.seed,
+        ..MessageMetadata::default()
+    };
+    // Errors reported inside a successful response, as a JSON body or an SSE
+    // event, name the request too.
+    let (served_model, id) = (model.clone(), metadata.request_id.clone());
+    let reported = move |error: String| {
+        let mut text = format!("{} reported an error: {}", served_model, error);
+        if let Some(id) = &id {
+            text.push_str(&format!(" [request {}]", id));
+        }
+        StreamEvent::Error(std::io::Error::other(text))
+    };
+
+    let event_stream = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_none_or(|v| v.contains("event-stream"));
+    if !event_stream {
+        // Asked to stream but got the whole reply, don't ask again.
+        NO_STREAMING.store(true, Ordering::Relaxed);
+        tokio::spawn(async move {
+            let body = response.text().await.unwrap_or_default();
+            let completion = match serde_json::from_str::<Completion>(&body) {
+                Ok(x) => x,
+                Err(e) => {
+                    let error = match api_error(&body) {
+                        Some(error) => reported(error),
+                        None => StreamEvent::Error(std::io::Error::other(e)),
+                    };
+                    let _ = tx.send(error).await;
+                    return;
+                }
+            };
+            metadata.snapshot = completion.model;
+            metadata.fingerprint = completion.system_fingerprint;
+            let mut events = Vec::new();
+            let mut assistant_reply = String::new();
+            if let Some(choice) = completion.choices.into_iter().next() {
+                assistant_reply = choice.message.content.unwrap_or_default();
+                events.push(StreamEvent::Token(assistant_reply.clone()));
+                // Passed on as if streamed in one piece each.
+                for (index, call) in choice.message.tool_calls.into_iter().enumerate() {
+                    events.push(StreamEvent::ToolCallDelta {
+                        index,
+                        id: call.id,
+                        name: call.function.name,
+                        arguments: call.function.arguments.unwrap_or_default(),
+                    });
+                }
+                events.push(StreamEvent::Done {
+                    finish_reason: choice.finish_reason,
+                });
+            }
+            if let Some(usage) = completion.usage {
+                events.push(StreamEvent::Usage(record_usage(usage)));
+            }
+            for event in events {
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+            if !assistant_reply.is_empty() {
+                let mut message = Message::new("assistant", &assistant_reply);
+                message.metadata = metadata;
+                context_clone.lock().await.push(message);
+            }
+        });
+        return Ok((model, ReceiverStream::new(rx)));
+    }
+
+    let mut stream = response.bytes_stream();
 
     tokio::spawn(async move {
         let mut assistant_reply = String::new();
+        let mut events = Vec::new();
+        // Network chunks don't line up with SSE lines, or even characters.
+        let mut buffer: Vec<u8> = Vec::new();
 
-        while let Some(item) = stream.next().await {
+        'read: loop {
+            let item = tokio::select! {
+                item = stream.next() => match item {
+                    Some(item) => item,
+                    None => break,
+                },
+                // Given up on as stalled: a reply arriving now would land in
+                // the context behind whatever was said since.
+                _ = tx.closed() => return,
+            };
             match item {
                 Ok(chunk) => {
-                    let chunk_str = String::from_utf8_lossy(&chunk);
-                    let lines: Vec<&str> = chunk_str.split("\n").collect();
+                    // Any data, SSE comments and empty chunks included, shows
+                    // the server is still there while the model thinks.
+                    events.push(StreamEvent::KeepAlive);
+                    buffer.extend_from_slice(&chunk);
+                    let mut lines = Vec::new();
+                    while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                        let line: Vec<u8> = buffer.drain(..=end).collect();
+                        lines.push(String::from_utf8_lossy(&line).trim_end().to_owned());
+                    }
                     for line in lines {
-                        if line.starts_with("data: ") {
-                            let json_str = &line[6..];
-                            if json_str != "[DONE]" {
+                        if let Some(json_str) = line.strip_prefix("data:").map(str::trim_start) {
+                            if let Some(error) = api_error(json_str) {
+                                events.push(reported(error));
+                            } else if json_str != "[DONE]" {
                                 if let Ok(chunk) = serde_json::from_str::<Chunk>(json_str) {
+                                    if chunk.model.is_some() {
+                                        metadata.snapshot = chunk.model;
+                                    }
+                                    if chunk.system_fingerprint.is_some() {
+                                        metadata.fingerprint = chunk.system_fingerprint;
+                                    }
+                                    if let Some(usage) = chunk.usage {
+                                        events.push(StreamEvent::Usage(record_usage(usage)));
+                                    }
                                     for choice in chunk.choices {
+                                        let logprobs = choice
+                                            .logprobs
+                                            .and_then(|l| l.content)
+                                            .unwrap_or_default();
+                                        if !logprobs.is_empty() {
+                                            events.push(StreamEvent::Logprobs(
+                                                logprobs
+                                                    .into_iter()
+                                                    .map(|l| TokenLogprob {
+                                                        token: l.token,
+                                                        logprob: l.logprob,
+                                                        top: l
+                                                            .top_logprobs
+                                                            .into_iter()
+                                                            .map(|t| (t.token, t.logprob))
+                                                            .collect(),
+                                                    })
+                                                    .collect(),
+                                            ));
+                                        }
                                         if let Some(content) = choice.delta.content {
                                             assistant_reply.push_str(&content);
-                                            if tx.send(Ok(content.clone())).await.is_err() {
-                                                return;
-                                            }
+                                            events.push(StreamEvent::Token(content));
+                                        }
+                                        for call in choice.delta.tool_calls.unwrap_or_default() {
+                                            let function = call.function;
+                                            events.push(StreamEvent::ToolCallDelta {
+                                                index: call.index,
+                                                id: call.id,
+                                                name: function
+                                                    .as_ref()
+                                                    .and_then(|f| f.name.clone()),
+                                                arguments: function
+                                                    .and_then(|f| f.arguments)
+                                                    .unwrap_or_default(),
+                                            });
+                                        }
+                                        if choice.finish_reason.is_some() {
+                                            events.push(StreamEvent::Done {
+                                                finish_reason: choice.finish_reason,
+                                            });
                                         }
                                     }
                                 }
@@ -154,13 +1003,28 @@ pub async fn send_request(
                     }
                 }
                 Err(e) => {
-                    let _ = tx
-                        .send(Err(std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            e.to_string(),
-                        )))
-                        .await;
-                    break;
+                    // Keep what arrived before the connection dropped, before
+                    // reporting it, so the reply can be resumed from there.
+                    if !assistant_reply.is_empty() {
+                        let mut message =
+                            Message::new("assistant", &std::mem::take(&mut assistant_reply));
+                        message.metadata = metadata.clone();
+                        context_clone.lock().await.push(message);
+                    }
+                    events.push(StreamEvent::Error(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionAborted,
+                        e.to_string(),
+                    )));
+                }
+            }
+
+            for event in events.drain(..) {
+                let is_error = matches!(event, StreamEvent::Error(_));
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+                if is_error {
+                    break 'read;
                 }
             }
         }
@@ -168,12 +1032,11 @@ pub async fn send_request(
         // Update the shared context with the assistant's full reply
         if !assistant_reply.is_empty() {
             let mut ctx = context_clone.lock().await;
-            ctx.push(Message {
-                role: "assistant".to_string(),
-                content: assistant_reply,
-            });
+            let mut message = Message::new("assistant", &assistant_reply);
+            message.metadata = metadata;
+            ctx.push(message);
         }
     });
 
-    Ok(ReceiverStream::new(rx))
+    Ok((model, ReceiverStream::new(rx)))
 }
@@ -0,0 +1,57 @@
+//! Hands text to a shell in another tmux pane or kitty window, see
+//! `/send_pane`. Text goes in as a bracketed paste, so nothing runs until
+//! Enter is pressed there.
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+const TMUX_BUFFER: &str = "chad-llm";
+
+/// Runs `program args` with `input` on stdin.
+fn pipe(program: &str, args: &[&str], input: &str) -> io::Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(input.as_bytes())?;
+    let output = child.wait_with_output()?;
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
+
+/// Pastes `text` into `target`: `kitty:<match>` for a kitty window (e.g.
+/// `kitty:title:shell`), anything else is a tmux target pane (e.g. `1`,
+/// `:2.1`, `%5`).
+pub fn send(target: &str, text: &str) -> io::Result<()> {
+    let text = text.trim_end_matches('\n');
+    match target.strip_prefix("kitty:") {
+        Some(window) => pipe(
+            "kitty",
+            &[
+                "@",
+                "send-text",
+                "--match",
+                window,
+                "--bracketed-paste",
+                "enable",
+                "--stdin",
+            ],
+            text,
+        ),
+        None => {
+            pipe("tmux", &["load-buffer", "-b", TMUX_BUFFER, "-"], text)?;
+            pipe(
+                "tmux",
+                &["paste-buffer", "-d", "-p", "-b", TMUX_BUFFER, "-t", target],
+                "",
+            )
+        }
+    }
+}
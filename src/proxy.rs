@@ -0,0 +1,380 @@
+//! `chad-gpt proxy`: an OpenAI compatible `/v1/chat/completions` endpoint on
+//! localhost, so other tools can use chad-llm's provider and key. Requests
+//! pass through the redaction filter, the rate limits and the monthly budget,
+//! and their usage is recorded like chad-llm's own.
+use crate::application::get_data_dir;
+use crate::openai;
+use crate::rate_limit::RateLimiter;
+use crate::redact;
+use crate::report;
+use crate::serve;
+use crate::stats::{self, RequestStats};
+
+use futures_util::StreamExt;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const DEFAULT_PORT: u16 = 8081;
+/// Requests with longer headers are dropped.
+const MAX_HEADERS: usize = 16 * 1024;
+const MAX_BODY: usize = 32 * 1024 * 1024;
+
+/// Entry point of `chad-gpt proxy [--port <port>] [--no-redact]`.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut port = DEFAULT_PORT;
+    let mut redact = true;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                port = args
+                    .next()
+                    .and_then(|p| p.parse().ok())
+                    .ok_or("--port needs a port number")?
+            }
+            "--no-redact" => redact = false,
+            other => return Err(format!("unknown argument '{}'", other).into()),
+        }
+    }
+    let budget: Option<f64> = match std::env::var("CHAD_LLM_MONTHLY_BUDGET") {
+        Ok(budget) => Some(
+            budget
+                .parse()
+                .map_err(|_| "CHAD_LLM_MONTHLY_BUDGET must be an amount in USD")?,
+        ),
+        Err(_) => None,
+    };
+    let _ = std::fs::create_dir_all(get_data_dir());
+
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(async {
+        let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+        println!(
+            "Forwarding http://127.0.0.1:{}/v1 to {}{}{}",
+            port,
+            openai::base_url(),
+            match redact {
+                true => ", redacting secrets",
+                false => "",
+            },
+            budget.map_or(String::new(), |b| format!(", budget ${:.2} a month", b))
+        );
+        let proxy = Proxy { redact, budget };
+        loop {
+            let (stream, _) = listener.accept().await?;
+            tokio::spawn(proxy.respond(stream));
+        }
+    })
+}
+
+#[derive(Clone, Copy)]
+struct Proxy {
+    redact: bool,
+    budget: Option<f64>,
+}
+
+/// The response for a request that couldn't be forwarded.
+fn forward_error(e: &std::io::Error) -> String {
+    match e.kind() {
+        std::io::ErrorKind::PermissionDenied => {
+            error("401 Unauthorized", "authentication_error", &e.to_string())
+        }
+        _ => error("502 Bad Gateway", "api_error", &e.to_string()),
+    }
+}
+
+/// An OpenAI style error response.
+fn error(status: &str, kind: &str, message: &str) -> String {
+    let body = json!({ "error": { "message": message, "type": kind } }).to_string();
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn header<'a>(headers: &'a str, name: &str) -> Option<&'a str> {
+    headers.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+impl Proxy {
+    async fn respond(self, mut stream: TcpStream) {
+        let mut request = Vec::new();
+        let mut buf = [0; 8192];
+        let headers_end = loop {
+            if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                break end + 4;
+            }
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => request.extend_from_slice(&buf[..n]),
+            }
+            if request.len() > MAX_HEADERS {
+                return;
+            }
+        };
+        let headers = String::from_utf8_lossy(&request[..headers_end]).into_owned();
+        let body = request.split_off(headers_end);
+
+        let request_line = headers.lines().next().unwrap_or_default();
+        let (method, path) = request_line
+            .split_once(' ')
+            .map(|(method, rest)| (method, rest.split(' ').next().unwrap_or_default()))
+            .unwrap_or_default();
+        let response = match (method, path) {
+            // Other sites could otherwise use it through DNS rebinding.
+            _ if !serve::local_host(&headers) => error(
+                "403 Forbidden",
+                "forbidden",
+                "Only local clients are served",
+            ),
+            ("GET", "/v1/models" | "/models") => self.models().await,
+            ("POST", "/v1/chat/completions" | "/chat/completions") => {
+                match read_json(&headers, body, &mut stream).await {
+                    Some(Ok(request)) => return self.chat(request, stream).await,
+                    Some(Err(response)) => response,
+                    None => return,
+                }
+            }
+            _ => error("404 Not Found", "invalid_request_error", "Unknown endpoint"),
+        };
+        let _ = stream.write_all(response.as_bytes()).await;
+    }
+
+    async fn models(&self) -> String {
+        let (status, body) = match openai::forward_models().await {
+            Ok(x) => x,
+            Err(e) => return forward_error(&e),
+        };
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    }
+
+    async fn chat(self, mut request: Value, mut stream: TcpStream) {
+        if let Some(budget) = self.budget {
+            let spent = report::month_cost(&stats::date(stats::now())[..7]);
+            if spent >= budget {
+                println!(
+                    "Refused a request: ${:.2} of the ${:.2} budget spent",
+                    spent, budget
+                );
+                let message = format!(
+                    "Monthly budget of ${:.2} reached (${:.2} spent)",
+                    budget, spent
+                );
+                let response = error("429 Too Many Requests", "budget_exceeded", &message);
+                let _ = stream.write_all(response.as_bytes()).await;
+                return;
+            }
+        }
+        if self.redact {
+            let redacted = redact_messages(&mut request);
+            if !redacted.is_empty() {
+                println!("Redacted {}", redacted.join(", "));
+            }
+        }
+
+        let requested_model = request["model"].as_str().unwrap_or_default().to_owned();
+        let prompt_tokens = RateLimiter::estimate_tokens(&request["messages"].to_string());
+        let started = Instant::now();
+        let response = match openai::forward(&request).await {
+            Ok(response) => response,
+            Err(e) => {
+                let response = forward_error(&e);
+                let _ = stream.write_all(response.as_bytes()).await;
+                return;
+            }
+        };
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("application/json")
+            .to_owned();
+        let head = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+            status, content_type
+        );
+        if stream.write_all(head.as_bytes()).await.is_err() {
+            return;
+        }
+
+        // Passed on chunk by chunk, read along for the usage.
+        let event_stream = content_type.contains("event-stream");
+        let mut usage = Usage::default();
+        let mut first_chunk = None;
+        let mut body = response.bytes_stream();
+        let mut buffer: Vec<u8> = Vec::new();
+        while let Some(Ok(chunk)) = body.next().await {
+            first_chunk.get_or_insert_with(|| started.elapsed());
+            if stream.write_all(&chunk).await.is_err() {
+                break;
+            }
+            buffer.extend_from_slice(&chunk);
+            while let Some(end) = buffer
+                .iter()
+                .position(|&b| b == b'\n')
+                .filter(|_| event_stream)
+            {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                if let Some(data) = line.trim().strip_prefix("data:") {
+                    usage.read(data.trim());
+                }
+            }
+        }
+        if !status.is_success() {
+            println!("{} returned {}", requested_model, status);
+            return;
+        }
+        if !event_stream {
+            usage.read(&String::from_utf8_lossy(&buffer));
+        }
+
+        // Ollama reports the bare name, the prefix is what keeps it apart.
+        let provider = openai::provider_of(&requested_model);
+        let model = match requested_model.starts_with(openai::OLLAMA_PREFIX) {
+            true => requested_model,
+            false => usage.model.unwrap_or(requested_model),
+        };
+        let record = RequestStats {
+            timestamp: stats::now(),
+            provider,
+            model: model.clone(),
+            ttft_ms: first_chunk.unwrap_or_default().as_millis() as u64,
+            total_ms: started.elapsed().as_millis() as u64,
+            prompt_tokens: usage.prompt_tokens.unwrap_or(prompt_tokens as u64),
+            cached_tokens: usage.cached_tokens,
+            completion_tokens: usage
+                .completion_tokens
+                .unwrap_or(RateLimiter::estimate_tokens(&usage.content) as u64),
+        };
+        println!(
+            "{} {}: {} prompt + {} completion tokens in {}ms",
+            stats::clock(record.timestamp),
+            model,
+            record.prompt_tokens,
+            record.completion_tokens,
+            record.total_ms
+        );
+        if let Err(e) = stats::record(&record) {
+            eprintln!("Failed to record usage: {}", e);
+        }
+    }
+}
+
+/// The JSON body of a request, or the error response to send instead.
+/// `None` when the client went away.
+async fn read_json(
+    headers: &str,
+    mut body: Vec<u8>,
+    stream: &mut TcpStream,
+) -> Option<Result<Value, String>> {
+    // Browsers can't send JSON cross-site without asking first.
+    if !header(headers, "content-type").is_some_and(|t| t.starts_with("application/json")) {
+        return Some(Err(error(
+            "415 Unsupported Media Type",
+            "invalid_request_error",
+            "Expected a JSON body",
+        )));
+    }
+    let length: usize = header(headers, "content-length")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(0);
+    if length > MAX_BODY {
+        return Some(Err(error(
+            "413 Payload Too Large",
+            "invalid_request_error",
+            "Request too large",
+        )));
+    }
+    let mut buf = [0; 8192];
+    while body.len() < length {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => body.extend_from_slice(&buf[..n]),
+        }
+    }
+    Some(
+        serde_json::from_slice(&body[..length.min(body.len())]).map_err(|e| {
+            error(
+                "400 Bad Request",
+                "invalid_request_error",
+                &format!("Invalid JSON: {}", e),
+            )
+        }),
+    )
+}
+
+/// Masks secrets in the text of each message, returning what was masked.
+fn redact_messages(request: &mut Value) -> Vec<String> {
+    let mut redacted = Vec::new();
+    let mut mask = |text: &mut Value| {
+        let Some(original) = text.as_str() else {
+            return;
+        };
+        let findings = redact::find_secrets(original);
+        if findings.is_empty() {
+            return;
+        }
+        redacted.extend(findings.iter().map(|f| f.preview()));
+        let masked = redact::redact(original, &findings.iter().collect::<Vec<_>>());
+        *text = Value::String(masked);
+    };
+    for message in request["messages"].as_array_mut().into_iter().flatten() {
+        match &mut message["content"] {
+            Value::Array(parts) => parts.iter_mut().for_each(|part| mask(&mut part["text"])),
+            content => mask(content),
+        }
+    }
+    redacted
+}
+
+/// What a reply reports about its usage; streamed replies only include it
+/// when the client asks with `stream_options`.
+#[derive(Default)]
+struct Usage {
+    model: Option<String>,
+    prompt_tokens: Option<u64>,
+    completion_tokens: Option<u64>,
+    cached_tokens: u64,
+    /// The reply, to estimate its tokens when the usage is missing.
+    content: String,
+}
+
+impl Usage {
+    /// Reads a whole reply or a streamed chunk.
+    fn read(&mut self, json: &str) {
+        let Ok(value) = serde_json::from_str::<Value>(json) else {
+            return;
+        };
+        if let Some(model) = value["model"].as_str() {
+            self.model = Some(model.to_owned());
+        }
+        for choice in value["choices"].as_array().into_iter().flatten() {
+            for content in [&choice["delta"]["content"], &choice["message"]["content"]] {
+                self.content.push_str(content.as_str().unwrap_or_default());
+            }
+        }
+        let usage = &value["usage"];
+        if let Some(tokens) = usage["prompt_tokens"].as_u64() {
+            self.prompt_tokens = Some(tokens);
+            self.completion_tokens = usage["completion_tokens"].as_u64();
+            self.cached_tokens = usage["prompt_tokens_details"]["cached_tokens"]
+                .as_u64()
+                .unwrap_or(0);
+        }
+    }
+}
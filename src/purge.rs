@@ -0,0 +1,233 @@
+use crate::application::{
+    get_data_dir, get_data_path, ABBREVIATIONS_FILE, EDITOR_TEMPLATE_FILE, MODEL_PINS_FILE,
+    PERSONAS_FILE, PRESETS_FILE, SESSION_TEMPLATES_DIR,
+};
+use crate::cli::EDITOR_TEMP_PREFIX;
+use crate::crypto::KEY_INFO_FILE;
+use crate::history::LEGACY_HISTORY_FILE;
+use crate::instructions::{ABOUT_ME_FILE, INSTRUCTIONS_DIR};
+use crate::language::ALIASES_FILE;
+use crate::notes::NOTES_FILE;
+use crate::rate_limit::RATE_LIMITS_FILE;
+use crate::recording;
+use crate::report::PRICES_FILE;
+use crate::request_template::REQUEST_TEMPLATES_FILE;
+use crate::system_prompt::SYSTEM_PROMPTS_FILE;
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// What the default purge keeps: everything in the data directory not
+/// listed here is conversation data.
+const CONFIGURATION: &[&str] = &[
+    ABBREVIATIONS_FILE,
+    ABOUT_ME_FILE,
+    ALIASES_FILE,
+    EDITOR_TEMPLATE_FILE,
+    INSTRUCTIONS_DIR,
+    KEY_INFO_FILE,
+    MODEL_PINS_FILE,
+    PERSONAS_FILE,
+    PRESETS_FILE,
+    PRICES_FILE,
+    RATE_LIMITS_FILE,
+    REQUEST_TEMPLATES_FILE,
+    SESSION_TEMPLATES_DIR,
+    SYSTEM_PROMPTS_FILE,
+];
+
+fn is_configuration(path: &Path) -> bool {
+    CONFIGURATION
+        .iter()
+        .any(|name| path.starts_with(get_data_path(name)))
+}
+
+fn files_in(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        if let Ok(dir) = std::fs::read_dir(path) {
+            for entry in dir.flatten() {
+                files_in(&entry.path(), files);
+            }
+        }
+    } else if path.exists() {
+        files.push(path.to_owned());
+    }
+}
+
+/// Files that would be purged: the conversation data, that is everything in
+/// the data directory but the configuration, the recordings, the project
+/// notes in the current directory and files left behind by `$EDITOR`. With
+/// `all` the configuration and system prompts go too.
+pub fn targets(all: bool) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    files_in(&get_data_dir(), &mut files);
+    if !all {
+        files.retain(|file| !is_configuration(file));
+    }
+    for path in recording::recorded() {
+        files_in(&path, &mut files);
+    }
+    if let Ok(dir) = std::fs::read_dir(std::env::temp_dir()) {
+        for entry in dir.flatten() {
+            if entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(EDITOR_TEMP_PREFIX)
+            {
+                files_in(&entry.path(), &mut files);
+            }
+        }
+    }
+    files_in(Path::new(NOTES_FILE), &mut files);
+    files_in(Path::new(LEGACY_HISTORY_FILE), &mut files);
+    files
+}
+
+/// Removes the directories under `path` left empty by the purge.
+fn remove_empty_dirs(path: &Path) {
+    if let Ok(dir) = std::fs::read_dir(path) {
+        for entry in dir.flatten() {
+            if entry.path().is_dir() && !is_configuration(&entry.path()) {
+                remove_empty_dirs(&entry.path());
+                // Fails, as intended, unless the directory is empty.
+                let _ = std::fs::remove_dir(entry.path());
+            }
+        }
+    }
+}
+
+/// Overwrites the file with zeros before removing it. On SSDs and
+/// copy-on-write filesystems the old blocks may survive regardless.
+fn shred(path: &Path) -> io::Result<()> {
+    let len = std::fs::metadata(path)?.len();
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    let zeros = [0u8; 4096];
+    let mut left = len;
+    while left > 0 {
+        let n = left.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..n])?;
+        left -= n as u64;
+    }
+    file.sync_all()?;
+    drop(file);
+    std::fs::remove_file(path)
+}
+
+/// Shreds `files`, returning the ones removed and the failures.
+pub fn purge(files: &[PathBuf]) -> (Vec<PathBuf>, Vec<(PathBuf, io::Error)>) {
+    let mut removed = Vec::new();
+    let mut failed = Vec::new();
+    for file in files {
+        match shred(file) {
+            Ok(()) => removed.push(file.clone()),
+            Err(e) => failed.push((file.clone(), e)),
+        }
+    }
+    remove_empty_dirs(&get_data_dir());
+    (removed, failed)
+}
+
+/// Entry point of `chad-gpt purge [--all] [--yes]`.
+pub fn run(args: &[String]) -> io::Result<()> {
+    let all = args.iter().any(|a| a == "--all");
+    let yes = args.iter().any(|a| a == "--yes");
+    let files = targets(all);
+    if files.is_empty() {
+        println!("Nothing to purge.");
+        return Ok(());
+    }
+
+    for file in &files {
+        println!("  {}", file.display());
+    }
+    if !yes {
+        print!("Securely delete these {} files? [y/N] ", files.len());
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            println!("Nothing deleted.");
+            return Ok(());
+        }
+    }
+
+    let (removed, failed) = purge(&files);
+    for (file, e) in &failed {
+        eprintln!("Failed to delete {}: {}", file.display(), e);
+    }
+    println!("Removed {} files.", removed.len());
+    Ok(())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::bookmarks::{self, Bookmark, BOOKMARKS_FILE};
+    use crate::cron::CRON_FILE;
+    use crate::history::{History, SessionMeta, ARCHIVES_DIR};
+    use crate::openai::MODELS_CACHE_FILE;
+    use crate::shell_hook::LAST_COMMAND_FILE;
+    use crate::stats::STATS_FILE;
+
+    #[test]
+    fn default_purge_keeps_only_configuration() {
+        let root = tempfile::tempdir().unwrap();
+        std::env::set_var("XDG_DATA_HOME", root.path().join("data"));
+        std::env::set_var("TMPDIR", root.path());
+        std::env::set_current_dir(root.path()).unwrap();
+
+        let mut history = History::new();
+        history.save_input("hello").unwrap();
+        history.save_message("user", "hello", None).unwrap();
+        history.set_meta(SessionMeta::default()).unwrap();
+        bookmarks::add(&Bookmark {
+            timestamp: 0,
+            session: history.session_id().to_owned(),
+            model: None,
+            question: Some("hello".to_owned()),
+            answer: "hi".to_owned(),
+        })
+        .unwrap();
+        for name in [
+            STATS_FILE,
+            MODELS_CACHE_FILE,
+            LAST_COMMAND_FILE,
+            CRON_FILE,
+            &format!("{}/old.tar.gz", ARCHIVES_DIR),
+        ] {
+            let path = get_data_path(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "data").unwrap();
+        }
+        drop(recording::Recording::start("demo.jsonl").unwrap());
+        std::fs::write(NOTES_FILE, "notes").unwrap();
+        let editor_file = std::env::temp_dir().join(format!("{}abcdefg", EDITOR_TEMP_PREFIX));
+        std::fs::write(&editor_file, "draft").unwrap();
+
+        let mut configuration = Vec::new();
+        for name in CONFIGURATION {
+            let path = match name.contains('.') {
+                true => get_data_path(name),
+                false => get_data_path(&format!("{}/kept.md", name)),
+            };
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, "configuration").unwrap();
+            configuration.push(path);
+        }
+        assert!(!bookmarks::load().unwrap().is_empty());
+
+        let (_, failed) = purge(&targets(false));
+        assert!(failed.is_empty());
+
+        let mut left = Vec::new();
+        files_in(&get_data_dir(), &mut left);
+        left.sort();
+        configuration.sort();
+        assert_eq!(left, configuration);
+        assert!(!get_data_path(BOOKMARKS_FILE).exists());
+        assert!(!Path::new("demo.jsonl").exists());
+        assert!(!Path::new(NOTES_FILE).exists());
+        assert!(!editor_file.exists());
+    }
+}
@@ -0,0 +1,180 @@
+use crate::application::get_data_path;
+
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::env;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex as SyncMutex};
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+pub const RATE_LIMITS_FILE: &str = "rate_limits.json";
+
+/// The limits of one provider in `rate_limits.json`.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+struct Limits {
+    rpm: Option<usize>,
+    tpm: Option<usize>,
+}
+
+/// One limiter per provider, as each has its own limits.
+static LIMITERS: LazyLock<SyncMutex<HashMap<String, Arc<RateLimiter>>>> =
+    LazyLock::new(Default::default);
+
+/// The limiter for requests to `provider`, see `RateLimiter::for_provider`.
+pub fn limiter(provider: &str) -> Arc<RateLimiter> {
+    let mut limiters = LIMITERS.lock().unwrap();
+    let limiter = limiters
+        .entry(provider.to_owned())
+        .or_insert_with(|| Arc::new(RateLimiter::for_provider(provider)));
+    Arc::clone(limiter)
+}
+
+/// Client-side limiter for requests-per-minute and tokens-per-minute, so
+/// bursts of requests wait locally instead of being rejected with a 429.
+pub struct RateLimiter {
+    rpm: Option<usize>,
+    tpm: Option<usize>,
+    sent: SyncMutex<VecDeque<(Instant, usize)>>,
+    /// Tickets of the requests waiting their turn, served lowest first.
+    queue: SyncMutex<BTreeSet<u64>>,
+    next_ticket: AtomicU64,
+}
+
+/// A request's place in the queue, given up however `acquire` ends, its
+/// future being dropped included.
+struct Ticket<'a> {
+    queue: &'a SyncMutex<BTreeSet<u64>>,
+    id: u64,
+}
+
+impl Ticket<'_> {
+    /// 1 for the next request to go.
+    fn position(&self) -> usize {
+        self.queue.lock().unwrap().range(..self.id).count() + 1
+    }
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        self.queue.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl RateLimiter {
+    pub fn new(rpm: Option<usize>, tpm: Option<usize>) -> Self {
+        Self {
+            rpm,
+            tpm,
+            sent: SyncMutex::new(VecDeque::new()),
+            queue: SyncMutex::new(BTreeSet::new()),
+            next_ticket: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads the limits of `provider` from `rate_limits.json` in the data
+    /// directory, which maps provider names to `{"rpm": .., "tpm": ..}`.
+    /// Providers not listed there get `CHAD_LLM_RPM` and `CHAD_LLM_TPM`.
+    /// Unset or invalid values disable the corresponding limit.
+    pub fn for_provider(provider: &str) -> Self {
+        let configured = std::fs::read_to_string(get_data_path(RATE_LIMITS_FILE))
+            .ok()
+            .and_then(|c| serde_json::from_str::<HashMap<String, Limits>>(&c).ok())
+            .and_then(|mut all| all.remove(provider));
+        let limits = configured.unwrap_or_else(|| {
+            let read = |name| env::var(name).ok().and_then(|v| v.parse().ok());
+            Limits {
+                rpm: read("CHAD_LLM_RPM"),
+                tpm: read("CHAD_LLM_TPM"),
+            }
+        });
+        Self::new(limits.rpm, limits.tpm)
+    }
+
+    /// Rough token estimate used for the TPM budget.
+    pub fn estimate_tokens(text: &str) -> usize {
+        text.len().div_ceil(4)
+    }
+
+    fn wait_time(&self, sent: &VecDeque<(Instant, usize)>, tokens: usize) -> Option<Duration> {
+        let now = Instant::now();
+        let mut wait = None;
+
+        if let Some(rpm) = self.rpm {
+            if sent.len() >= rpm.max(1) {
+                wait = Some(WINDOW.saturating_sub(now - sent[sent.len() - rpm.max(1)].0));
+            }
+        }
+
+        if let Some(tpm) = self.tpm {
+            let mut used: usize = sent.iter().map(|(_, t)| t).sum();
+            // Oversized requests only wait for an empty window.
+            let tokens = tokens.min(tpm);
+            for (at, t) in sent.iter() {
+                if used + tokens <= tpm {
+                    break;
+                }
+                used -= t;
+                let w = WINDOW.saturating_sub(now - *at);
+                wait = Some(wait.map_or(w, |x: Duration| x.max(w)));
+            }
+        }
+
+        wait.filter(|w| !w.is_zero())
+    }
+
+    /// Waits until a request of `tokens` estimated tokens fits within the
+    /// limits and the requests queued before it went, then records it. The
+    /// wait and queue position are shown on the status line while waiting.
+    pub async fn acquire(&self, tokens: usize) {
+        if self.rpm.is_none() && self.tpm.is_none() {
+            return;
+        }
+
+        let ticket = Ticket {
+            queue: &self.queue,
+            id: self.next_ticket.fetch_add(1, Ordering::SeqCst),
+        };
+        self.queue.lock().unwrap().insert(ticket.id);
+        let terminal = std::io::stdout().is_terminal();
+        let mut shown = false;
+        loop {
+            let position = ticket.position();
+            let wait = {
+                let mut sent = self.sent.lock().unwrap();
+                while sent.front().is_some_and(|(at, _)| at.elapsed() >= WINDOW) {
+                    sent.pop_front();
+                }
+                match self.wait_time(&sent, tokens) {
+                    None if position == 1 => {
+                        sent.push_back((Instant::now(), tokens));
+                        break;
+                    }
+                    // Behind a request that may go any moment.
+                    None => Duration::from_secs(1),
+                    Some(wait) => wait,
+                }
+            };
+            let status = format!(
+                "rate limited: waiting {}s, queue position {}",
+                wait.as_secs().max(1),
+                position
+            );
+            match terminal {
+                true => print!("\r\x1b[2K\x1b[2m{}\x1b[0m", status),
+                false if !shown => eprintln!("{}", status),
+                false => {}
+            }
+            let _ = std::io::stdout().flush();
+            shown = true;
+            // Counted down on the status line.
+            tokio::time::sleep(wait.min(Duration::from_secs(1))).await;
+        }
+        if shown && terminal {
+            print!("\r\x1b[2K");
+            let _ = std::io::stdout().flush();
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Recordings of replies as they streamed, token by token with their timing,
+//! made with `/record` and played back by `chad-gpt replay` without calling
+//! the API: for demos and for reporting rendering bugs.
+use crate::application::get_data_path;
+use crate::response::{Sink, TerminalRenderer};
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "replay <file> [--speed <factor>] [--raw]";
+/// The paths recordings were made at, one per line, so `purge` finds them.
+pub const RECORDINGS_FILE: &str = "recordings.txt";
+
+/// A line of a recording.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Entry {
+    Prompt {
+        content: String,
+    },
+    /// `ms` into the reply.
+    Token {
+        ms: u64,
+        text: String,
+    },
+    Done,
+}
+
+/// The recordings made so far that still exist.
+pub fn recorded() -> Vec<PathBuf> {
+    std::fs::read_to_string(get_data_path(RECORDINGS_FILE))
+        .unwrap_or_default()
+        .lines()
+        .map(PathBuf::from)
+        .filter(|path| path.exists())
+        .collect()
+}
+
+fn append(file: &Mutex<File>, entry: &Entry) {
+    // A recording missing a line beats a reply failing over it.
+    let _ = writeln!(
+        file.lock().unwrap(),
+        "{}",
+        serde_json::to_string(entry).unwrap()
+    );
+}
+
+/// A recording being made. Dropping it closes the file.
+pub struct Recording {
+    pub path: String,
+    file: Arc<Mutex<File>>,
+}
+
+impl Recording {
+    pub fn start(path: &str) -> io::Result<Self> {
+        let file = File::create(path)?;
+        if let Ok(path) = std::fs::canonicalize(path) {
+            let _ = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(get_data_path(RECORDINGS_FILE))
+                .and_then(|mut index| writeln!(index, "{}", path.display()));
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    pub fn write(&self, entry: &Entry) {
+        append(&self.file, entry);
+    }
+
+    /// A sink recording a reply from now on.
+    pub fn recorder(&self) -> Recorder {
+        Recorder {
+            file: Some(Arc::clone(&self.file)),
+            started: Instant::now(),
+        }
+    }
+}
+
+/// Records each token of a reply, or nothing when not recording.
+pub struct Recorder {
+    file: Option<Arc<Mutex<File>>>,
+    started: Instant,
+}
+
+impl Recorder {
+    pub fn none() -> Self {
+        Self {
+            file: None,
+            started: Instant::now(),
+        }
+    }
+
+    fn write(&self, entry: &Entry) {
+        if let Some(file) = &self.file {
+            append(file, entry);
+        }
+    }
+}
+
+impl Sink for Recorder {
+    fn token(&mut self, token: &str) {
+        self.write(&Entry::Token {
+            ms: self.started.elapsed().as_millis() as u64,
+            text: token.to_owned(),
+        });
+    }
+
+    fn finish(&mut self) {
+        self.write(&Entry::Done);
+    }
+}
+
+/// Entry point of `chad-gpt replay`.
+pub fn replay(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut path = None;
+    let mut speed = 1.0;
+    let mut raw = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--speed" => {
+                speed = args
+                    .next()
+                    .and_then(|s| s.parse::<f64>().ok())
+                    .filter(|s| *s > 0.0)
+                    .ok_or("--speed needs a factor, such as 2 or 0.5")?
+            }
+            "--raw" => raw = true,
+            _ if path.is_none() => path = Some(arg.clone()),
+            other => return Err(format!("unknown argument '{}', usage: {}", other, USAGE).into()),
+        }
+    }
+    let path = path.ok_or(format!("usage: {}", USAGE))?;
+    let file = File::open(&path).map_err(|e| format!("{}: {}", path, e))?;
+
+    let mut renderer = TerminalRenderer::new(raw);
+    let mut started = Instant::now();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry = serde_json::from_str(&line).map_err(|e| format!("{}: {}", path, e))?;
+        match entry {
+            Entry::Prompt { content } => {
+                print!("> {}\r\n\r\n", content.replace('\n', "\r\n"));
+                started = Instant::now();
+            }
+            Entry::Token { ms, text } => {
+                let due = Duration::from_millis((ms as f64 / speed) as u64);
+                std::thread::sleep(due.saturating_sub(started.elapsed()));
+                renderer.token(&text);
+            }
+            Entry::Done => {
+                renderer.finish();
+                print!("\r\n\r\n");
+                renderer = TerminalRenderer::new(raw);
+                started = Instant::now();
+            }
+        }
+        io::stdout().flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use regex::Regex;
+
+use std::sync::LazyLock;
+
+/// A secret found in an outgoing message.
+pub struct Finding {
+    pub kind: &'static str,
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+impl Finding {
+    /// The secret with most of it hidden, safe to show on screen.
+    pub fn preview(&self) -> String {
+        let shown: String = self.text.chars().take(4).collect();
+        format!("{}: {}…", self.kind, shown)
+    }
+}
+
+// (kind, pattern, capture group holding the secret)
+static PATTERNS: LazyLock<Vec<(&'static str, Regex, usize)>> = LazyLock::new(|| {
+    [
+        (
+            "private key",
+            r"-----BEGIN [A-Z ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z ]*PRIVATE KEY-----",
+            0,
+        ),
+        ("OpenAI key", r"sk-[A-Za-z0-9_-]{20,}", 0),
+        ("AWS key", r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b", 0),
+        ("GitHub token", r"\bgh[pousr]_[A-Za-z0-9]{36,}\b", 0),
+        ("Slack token", r"\bxox[abprs]-[A-Za-z0-9-]{10,}", 0),
+        (
+            "credential",
+            r#"(?mi)^\s*(?:export\s+)?[a-z0-9_]*(?:secret|password|passwd|token|api_?key|access_?key|private_?key)[a-z0-9_]*\s*[=:]\s*["']?([^\s"']+)"#,
+            1,
+        ),
+        ("email", r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}\b", 0),
+    ]
+    .into_iter()
+    .map(|(kind, pattern, group)| (kind, Regex::new(pattern).unwrap(), group))
+    .collect()
+});
+
+/// Finds secrets in `text`, ordered by position and without overlaps.
+pub fn find_secrets(text: &str) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = Vec::new();
+    for (kind, re, group) in PATTERNS.iter() {
+        for caps in re.captures_iter(text) {
+            if let Some(m) = caps.get(*group) {
+                findings.push(Finding {
+                    kind,
+                    start: m.start(),
+                    end: m.end(),
+                    text: m.as_str().to_owned(),
+                });
+            }
+        }
+    }
+
+    findings.sort_by_key(|f| f.start);
+    let mut end = 0;
+    findings.retain(|f| {
+        let keep = f.start >= end;
+        if keep {
+            end = f.end;
+        }
+        keep
+    });
+    findings
+}
+
+/// Replaces every finding with a `[REDACTED <kind>]` marker.
+pub fn redact(text: &str, findings: &[&Finding]) -> String {
+    let mut out = String::new();
+    let mut last = 0;
+    for finding in findings {
+        out.push_str(&text[last..finding.start]);
+        out.push_str(&format!("[REDACTED {}]", finding.kind));
+        last = finding.end;
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// `text` with every secret found in it masked, for text sent or stored
+/// without asking.
+pub fn redact_all(text: &str) -> String {
+    let findings = find_secrets(text);
+    redact(text, &findings.iter().collect::<Vec<_>>())
+}
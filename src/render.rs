@@ -0,0 +1,178 @@
+//! `chad-gpt render`: a regression check for the streaming markdown renderer.
+//! Each file is fed to the renderer in randomly sized chunks, as replies
+//! arrive, and the output must match the file's golden output whatever the
+//! chunking.
+use crate::response::{Sink, TerminalRenderer};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const DEFAULT_RUNS: u64 = 20;
+const MAX_CHUNK: usize = 16;
+
+const USAGE: &str = "render <file.md>... [--runs <n>] [--seed <n>] [--update]";
+
+/// Entry point of `chad-gpt render`.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut files = Vec::new();
+    let mut runs = DEFAULT_RUNS;
+    let mut seed = 0;
+    let mut update = false;
+    let mut chunks = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut number = |name: &str| -> Result<u64, String> {
+            args.next()
+                .and_then(|n| n.parse().ok())
+                .ok_or(format!("{} needs a number", name))
+        };
+        match arg.as_str() {
+            "--runs" => runs = number("--runs")?.max(1),
+            "--seed" => seed = number("--seed")?,
+            "--update" => update = true,
+            // Used for the runs themselves, see `render_with`.
+            "--chunks" => chunks = Some(number("--chunks")?),
+            _ if arg.starts_with("--") => {
+                return Err(format!("unknown argument '{}', usage: {}", arg, USAGE).into())
+            }
+            _ => files.push(PathBuf::from(arg)),
+        }
+    }
+    if files.is_empty() {
+        return Err(format!("usage: {}", USAGE).into());
+    }
+    if let Some(chunk_seed) = chunks {
+        return emit(&files[0], chunk_seed);
+    }
+
+    let mut failed = 0;
+    for file in &files {
+        if let Err(e) = check(file, runs, seed, update) {
+            failed += 1;
+            println!("FAIL {}: {}", file.display(), e);
+        }
+    }
+    println!(
+        "{} of {} file(s) passed.",
+        files.len() - failed,
+        files.len()
+    );
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} file(s) failed", failed).into()),
+    }
+}
+
+/// Renders `file` split into chunks picked by `chunk_seed`, to stdout, as it
+/// would look in a terminal. Seed 0 sends the file in one piece.
+fn emit(file: &Path, chunk_seed: u64) -> Result<(), Box<dyn Error>> {
+    let text = std::fs::read_to_string(file)?;
+    let mut renderer = TerminalRenderer::new(false);
+    // Otherwise the piped output is little more than the code blocks.
+    renderer.force_terminal();
+    let mut rng = StdRng::seed_from_u64(chunk_seed);
+    let mut rest = text.as_str();
+    while !rest.is_empty() {
+        let size = match chunk_seed {
+            0 => rest.len(),
+            _ => rng.random_range(1..=MAX_CHUNK),
+        };
+        let end = rest.char_indices().nth(size).map_or(rest.len(), |(i, _)| i);
+        renderer.token(&rest[..end]);
+        rest = &rest[end..];
+    }
+    renderer.finish();
+    io::stdout().flush()?;
+    Ok(())
+}
+
+/// The output of one run, rendered by a child process so the renderer's
+/// printing can be captured.
+fn render_with(file: &Path, chunk_seed: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    let output = Command::new(std::env::current_exe()?)
+        .arg("render")
+        .arg(file)
+        .args(["--chunks", &chunk_seed.to_string()])
+        // Output must not depend on who runs the check.
+        .env_remove("NO_COLOR")
+        .env_remove("CHAD_LLM_THEME")
+        .output()?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr)
+            .trim()
+            .to_owned()
+            .into());
+    }
+    Ok(output.stdout)
+}
+
+fn check(file: &Path, runs: u64, seed: u64, update: bool) -> Result<(), Box<dyn Error>> {
+    let mut golden_path = file.as_os_str().to_owned();
+    golden_path.push(".golden");
+    let golden_path = PathBuf::from(golden_path);
+
+    let whole = render_with(file, 0)?;
+    let golden = if update {
+        std::fs::write(&golden_path, &whole)?;
+        println!("wrote {}", golden_path.display());
+        whole.clone()
+    } else {
+        match std::fs::read(&golden_path) {
+            Ok(golden) => golden,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(format!(
+                    "no golden output in {}, --update writes it",
+                    golden_path.display()
+                )
+                .into())
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    if whole != golden {
+        return Err(format!(
+            "differs from {}{}",
+            golden_path.display(),
+            diverges(&golden, &whole)
+        )
+        .into());
+    }
+    for run in 1..=runs {
+        let chunk_seed = seed * runs + run;
+        let output = render_with(file, chunk_seed)?;
+        if output != golden {
+            return Err(format!(
+                "chunk seed {} differs from {}{}",
+                chunk_seed,
+                golden_path.display(),
+                diverges(&golden, &output)
+            )
+            .into());
+        }
+    }
+    println!("ok   {} ({} runs)", file.display(), runs + 1);
+    Ok(())
+}
+
+/// Where `output` first departs from `golden`, with a little of each.
+fn diverges(golden: &[u8], output: &[u8]) -> String {
+    let at = golden
+        .iter()
+        .zip(output)
+        .position(|(a, b)| a != b)
+        .unwrap_or(golden.len().min(output.len()));
+    let around = |bytes: &[u8]| {
+        let end = (at + 40).min(bytes.len());
+        format!("{:?}", String::from_utf8_lossy(&bytes[at..end]))
+    };
+    format!(
+        " at byte {}: expected {}, got {}",
+        at,
+        around(golden),
+        around(output)
+    )
+}
@@ -0,0 +1,154 @@
+use crate::application::get_data_path;
+use crate::stats::{self, RequestStats};
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+pub const PRICES_FILE: &str = "prices.json";
+
+/// USD per million (input, cached input, output) tokens.
+type Price = (f64, f64, f64);
+
+fn default_prices() -> HashMap<String, Price> {
+    [
+        ("gpt-4o", (2.50, 1.25, 10.00)),
+        ("gpt-4o-mini", (0.15, 0.075, 0.60)),
+        ("gpt-4.1", (2.00, 0.50, 8.00)),
+        ("gpt-4.1-mini", (0.40, 0.10, 1.60)),
+        ("gpt-4.1-nano", (0.10, 0.025, 0.40)),
+        ("o1", (15.00, 7.50, 60.00)),
+        ("o3-mini", (1.10, 0.55, 4.40)),
+        ("o4-mini", (1.10, 0.275, 4.40)),
+    ]
+    .into_iter()
+    .map(|(model, price)| (model.to_owned(), price))
+    .collect()
+}
+
+/// Built-in prices, overridden by `prices.json` in the data directory,
+/// e.g. `{ "my-model": [0.5, 0.25, 1.5] }`.
+fn load_prices() -> HashMap<String, Price> {
+    let mut prices = default_prices();
+    if let Ok(contents) = std::fs::read_to_string(get_data_path(PRICES_FILE)) {
+        match serde_json::from_str::<HashMap<String, Price>>(&contents) {
+            Ok(custom) => prices.extend(custom),
+            Err(e) => eprintln!("Ignoring invalid {}: {}", PRICES_FILE, e),
+        }
+    }
+    prices
+}
+
+/// Prices are looked up by exact name, then by the longest matching prefix so
+/// dated snapshots such as `gpt-4o-2024-08-06` are covered.
+fn price_for<'a>(prices: &'a HashMap<String, Price>, model: &str) -> Option<&'a Price> {
+    prices.get(model).or_else(|| {
+        prices
+            .iter()
+            .filter(|(name, _)| model.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, price)| price)
+    })
+}
+
+fn cost(price: &Price, record: &RequestStats) -> f64 {
+    let cached = record.cached_tokens.min(record.prompt_tokens);
+    let uncached = record.prompt_tokens - cached;
+    (uncached as f64 * price.0
+        + cached as f64 * price.1
+        + record.completion_tokens as f64 * price.2)
+        / 1_000_000.0
+}
+
+/// The cost of the usage recorded in `month` (`YYYY-MM`), leaving out models
+/// without a known price.
+pub fn month_cost(month: &str) -> f64 {
+    let prices = load_prices();
+    stats::load()
+        .unwrap_or_default()
+        .iter()
+        .filter(|record| stats::date(record.timestamp).starts_with(month))
+        .filter_map(|record| price_for(&prices, &record.model).map(|price| cost(price, record)))
+        .sum()
+}
+
+#[derive(Default)]
+struct Row {
+    requests: usize,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    cost: Option<f64>,
+}
+
+/// Entry point of `chad-gpt report [--month YYYY-MM] [--csv]`.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut month = None;
+    let mut csv = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--csv" => csv = true,
+            "--month" => month = Some(args.next().ok_or("--month needs a value")?.clone()),
+            other => return Err(format!("unknown argument '{}'", other).into()),
+        }
+    }
+    let month = month.unwrap_or_else(|| stats::date(stats::now())[..7].to_owned());
+
+    let prices = load_prices();
+    let mut rows: BTreeMap<(String, String), Row> = BTreeMap::new();
+    for record in stats::load()? {
+        let day = stats::date(record.timestamp);
+        if !day.starts_with(&month) {
+            continue;
+        }
+        let row = rows.entry((day, record.model.clone())).or_default();
+        row.requests += 1;
+        row.prompt_tokens += record.prompt_tokens;
+        row.completion_tokens += record.completion_tokens;
+        if let Some(price) = price_for(&prices, &record.model) {
+            *row.cost.get_or_insert(0.0) += cost(price, &record);
+        }
+    }
+
+    let format_cost = |cost: Option<f64>| cost.map_or("?".to_owned(), |c| format!("{:.4}", c));
+    if csv {
+        println!("date,model,requests,prompt_tokens,completion_tokens,cost_usd");
+        for ((day, model), row) in &rows {
+            let cost = row.cost.map(|c| format!("{:.4}", c)).unwrap_or_default();
+            println!(
+                "{},{},{},{},{},{}",
+                day, model, row.requests, row.prompt_tokens, row.completion_tokens, cost
+            );
+        }
+        return Ok(());
+    }
+
+    if rows.is_empty() {
+        println!("No usage recorded for {}.", month);
+        return Ok(());
+    }
+    println!(
+        "{:<10}  {:<24} {:>8} {:>12} {:>12} {:>10}",
+        "DATE", "MODEL", "REQUESTS", "PROMPT", "COMPLETION", "COST ($)"
+    );
+    let mut total = 0.0;
+    for ((day, model), row) in &rows {
+        total += row.cost.unwrap_or(0.0);
+        println!(
+            "{:<10}  {:<24} {:>8} {:>12} {:>12} {:>10}",
+            day,
+            model,
+            row.requests,
+            row.prompt_tokens,
+            row.completion_tokens,
+            format_cost(row.cost)
+        );
+    }
+    println!("Total for {}: ${:.4}", month, total);
+    if rows.values().any(|row| row.cost.is_none()) {
+        println!(
+            "Models marked '?' have no known price; add them to {}.",
+            PRICES_FILE
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use crate::application::get_data_path;
+use crate::openai;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+pub const REQUEST_TEMPLATES_FILE: &str = "request_templates.json";
+
+/// Adjustments to the request body for providers with their own parameters.
+#[derive(Debug, Default, Deserialize)]
+pub struct RequestTemplate {
+    /// Fields added to (or overriding) the body, e.g. `{"top_k": 40}`.
+    #[serde(default)]
+    pub extra: Map<String, Value>,
+    /// Fields to rename, e.g. `{"max_tokens": "max_new_tokens"}`.
+    #[serde(default)]
+    pub rename: HashMap<String, String>,
+}
+
+impl RequestTemplate {
+    pub fn apply(&self, body: &mut Value) {
+        let Some(object) = body.as_object_mut() else {
+            return;
+        };
+        for (from, to) in &self.rename {
+            if let Some(value) = object.remove(from) {
+                object.insert(to.clone(), value);
+            }
+        }
+        for (key, value) in &self.extra {
+            object.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+/// The template of the active profile: `CHAD_LLM_PROFILE` if set, otherwise
+/// the host of the API base URL. The templates file maps profile names to
+/// templates.
+pub static ACTIVE: LazyLock<Option<RequestTemplate>> = LazyLock::new(|| {
+    let contents = std::fs::read_to_string(get_data_path(REQUEST_TEMPLATES_FILE)).ok()?;
+    let mut templates: HashMap<String, RequestTemplate> = match serde_json::from_str(&contents) {
+        Ok(x) => x,
+        Err(e) => {
+            eprint!("Failed to parse {}: {}\r\n", REQUEST_TEMPLATES_FILE, e);
+            return None;
+        }
+    };
+    templates.remove(&profile())
+});
+
+/// `CHAD_LLM_PROFILE` if set, otherwise the host of the API base URL.
+pub fn profile() -> String {
+    std::env::var("CHAD_LLM_PROFILE").unwrap_or_else(|_| openai::provider_name())
+}
@@ -1,149 +1,645 @@
-use bat::PrettyPrinter;
-use std::io::IsTerminal;
+use crate::language;
+use crate::models::{CodeBlock, StreamEvent, TokenLogprob, TokenUsage};
+use crate::theme;
+
+use bat::{Input, PrettyPrinter};
+use std::io::{IsTerminal, Write};
 use std::pin::Pin;
-use tokio::io::{self, AsyncWriteExt, Error};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::Error;
 use tokio_stream::StreamExt;
 
+/// Render code blocks without headers and grids, for narrow terminals.
+pub static COMPACT: AtomicBool = AtomicBool::new(false);
+
+/// A consumer of the streamed reply. Every sink sees every token, so
+/// rendering, persistence and bookkeeping stay independent of each other.
+pub trait Sink {
+    fn token(&mut self, token: &str);
+    fn usage(&mut self, _usage: &TokenUsage) {}
+    fn logprobs(&mut self, _logprobs: &[TokenLogprob]) {}
+    fn finish(&mut self) {}
+}
+
+/// Feeds the stream to every sink and returns the finish reason reported
+/// by the API, if any.
 pub async fn process_response(
-    stream: Pin<Box<dyn tokio_stream::Stream<Item = Result<String, Error>>>>,
-    code_blocks: &mut Vec<String>,
-    raw: bool,
-) -> Result<String, Error> {
+    stream: Pin<Box<dyn tokio_stream::Stream<Item = StreamEvent>>>,
+    sinks: &mut [&mut dyn Sink],
+) -> Result<Option<String>, Error> {
     tokio::pin!(stream);
 
-    let mut in_code_block = false;
-    let mut language_reading = false;
-    let mut language = String::new();
-    let mut full_response = String::new();
-    let mut current_code_block_content = String::new();
-    let mut tick_count = 0;
-    let mut star_cnt = 0;
-    let mut in_effect = false;
-    let mut text_effected = false;
-    let mut next_newline_reset = true;
-    let stdout_is_terminal = std::io::stdout().is_terminal();
-
-    while let Some(chunk) = stream.next().await {
-        match chunk {
-            Ok(content) => {
-                if raw {
-                    print!("{}", content);
-                } else {
-                    let mut chars = content.chars().peekable();
-
-                    while let Some(ch) = chars.next() {
-                        if ch == '\n' && next_newline_reset {
-                            print!("\x1b[0m");
-                        }
-
-                        if language_reading {
-                            if ch == '\n' {
-                                language_reading = false;
-                            } else {
-                                language.push(ch);
-                                in_code_block = true;
-                            }
-                        } else if ch == '`' {
-                            tick_count += 1;
-                            if tick_count == 3 {
-                                tick_count = 0;
-
-                                if in_code_block {
-                                    in_code_block = false;
-                                    code_blocks.push(current_code_block_content.clone());
-
-                                    if stdout_is_terminal {
-                                        let mut language = language.trim().to_owned();
-                                        if language == "csharp" {
-                                            language = "c#".to_owned();
-                                        } else if language == "fsharp" {
-                                            language = "f#".to_owned();
-                                        }
-
-                                        let mut pp = PrettyPrinter::new();
-                                        pp.input_from_bytes(current_code_block_content.as_bytes())
-                                            .colored_output(true);
-
-                                        if !language.is_empty() {
-                                            pp.language(&language);
-                                        }
-
-                                        pp.print().unwrap();
-                                    } else {
-                                        println!("{}", current_code_block_content);
-                                    }
-
-                                    current_code_block_content.clear();
-                                    language.clear();
-                                } else {
-                                    in_code_block = true;
-                                    language_reading = true;
-                                    language.clear();
-                                }
-                            }
-                        } else if !in_code_block && (ch == '*' || ch == '_') {
-                            if text_effected {
-                                star_cnt -= 1;
-                                if star_cnt == 0 {
-                                    in_effect = false;
-                                    print!("\x1b[0m");
-                                    text_effected = false;
-                                }
-                            } else {
-                                star_cnt += 1;
-                                in_effect = true;
-                                if star_cnt == 1 {
-                                    print!("\x1b[0;3m");
-                                } else if star_cnt == 2 {
-                                    print!("\x1b[0;1m");
-                                } else if star_cnt == 3 {
-                                    print!("\x1b[0;1;3m");
-                                }
-                            }
-                        } else if !in_code_block && ch == '#' {
-                            print!("\x1b[1m#");
-                            next_newline_reset = true;
-                        } else {
-                            if in_effect {
-                                text_effected = true;
-                            }
-
-                            if tick_count > 0 {
-                                full_response.push_str(&"`".repeat(tick_count));
-                                if stdout_is_terminal {
-                                    print!("{}", "`".repeat(tick_count));
-                                    io::stdout().flush().await.unwrap();
-                                }
-                                tick_count = 0;
-                            }
-
-                            if in_code_block {
-                                if language.is_empty() {
-                                    if ch == '\n' {
-                                        language = " ".to_string();
-                                    } else {
-                                        language.push(ch);
-                                    }
-                                } else {
-                                    current_code_block_content.push(ch);
-                                }
-                            } else {
-                                full_response.push(ch);
-                                if stdout_is_terminal {
-                                    print!("{}", ch);
-                                    io::stdout().flush().await.unwrap();
-                                }
-                            }
-                        }
+    let stall = stall_timeout();
+    let show_thinking = std::io::stdout().is_terminal();
+    let started = Instant::now();
+    let mut last_event = Instant::now();
+    let mut first_token = false;
+    let mut thinking_shown = false;
+    let mut finish_reason = None;
+    loop {
+        let event = match tokio::time::timeout(Duration::from_secs(1), stream.next()).await {
+            Ok(Some(event)) => event,
+            Ok(None) => break,
+            Err(_) => {
+                if last_event.elapsed() >= stall {
+                    for sink in sinks.iter_mut() {
+                        sink.finish();
                     }
+                    // Not `ConnectionAborted`: the partial reply never
+                    // reached the context, so it can't be resumed.
+                    return Err(Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        format!("no data for {}s", stall.as_secs()),
+                    ));
+                }
+                // Reasoning models can take minutes before the first token.
+                if show_thinking && !first_token && started.elapsed() >= THINKING_AFTER {
+                    print!(
+                        "\r\x1b[2K\x1b[2mstill thinking ({}s)\x1b[0m",
+                        started.elapsed().as_secs()
+                    );
+                    std::io::stdout().flush().unwrap();
+                    thinking_shown = true;
+                }
+                continue;
+            }
+        };
+        last_event = Instant::now();
+        if matches!(event, StreamEvent::Token(_)) {
+            first_token = true;
+            if thinking_shown {
+                print!("\r\x1b[2K");
+                thinking_shown = false;
+            }
+        }
+        match event {
+            StreamEvent::Token(content) => {
+                for sink in sinks.iter_mut() {
+                    sink.token(&content);
                 }
             }
-            Err(err) => {
-                eprint!("Error: {}\r\n", err);
+            StreamEvent::Usage(usage) => {
+                for sink in sinks.iter_mut() {
+                    sink.usage(&usage);
+                }
+            }
+            StreamEvent::Logprobs(logprobs) => {
+                for sink in sinks.iter_mut() {
+                    sink.logprobs(&logprobs);
+                }
+            }
+            StreamEvent::Done { finish_reason: r } => finish_reason = r,
+            StreamEvent::ToolCallDelta { .. } | StreamEvent::KeepAlive => {}
+            StreamEvent::Error(err) => {
+                eprint!("{} {}\r\n", theme::error("Error:"), err);
+                for sink in sinks.iter_mut() {
+                    sink.finish();
+                }
                 return Err(err);
             }
         }
     }
 
-    Ok(full_response)
+    if thinking_shown {
+        print!("\r\x1b[2K");
+    }
+    for sink in sinks.iter_mut() {
+        sink.finish();
+    }
+    Ok(finish_reason)
+}
+
+/// Wait before "still thinking" is shown.
+const THINKING_AFTER: Duration = Duration::from_secs(3);
+/// A reply that sends nothing, keep-alives included, for this long is
+/// considered stalled.
+const DEFAULT_STALL_SECS: u64 = 120;
+
+fn stall_timeout() -> Duration {
+    Duration::from_secs(
+        std::env::var("CHAD_LLM_STALL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_STALL_SECS),
+    )
+}
+
+/// Returns the language and content of the last code block in `text` if
+/// its closing fence is missing.
+pub fn open_code_block(text: &str) -> Option<(String, String)> {
+    let mut open: Option<(String, String)> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        match open.as_mut() {
+            Some(_) if trimmed.starts_with("```") => open = None,
+            Some((_, content)) => {
+                content.push_str(line);
+                content.push('\n');
+            }
+            None if trimmed.starts_with("```") => {
+                open = Some((trimmed[3..].trim().to_owned(), String::new()))
+            }
+            None => {}
+        }
+    }
+    if text.ends_with('\n') {
+        open
+    } else {
+        // The last line was cut mid-way, don't add a newline it never had.
+        open.map(|(language, mut content)| {
+            content.pop();
+            (language, content)
+        })
+    }
+}
+
+/// Number of fenced code blocks in `text`, counting one left open.
+pub fn count_code_blocks(text: &str) -> usize {
+    let fences = text
+        .lines()
+        .filter(|line| line.trim_start().starts_with("```"))
+        .count();
+    fences.div_ceil(2)
+}
+
+/// Drops a code fence the model re-opens at the start of a continuation,
+/// when the previous reply already left one open.
+pub struct LeadingFenceFilter {
+    pending: Option<String>,
+}
+
+impl LeadingFenceFilter {
+    pub fn new() -> Self {
+        Self {
+            pending: Some(String::new()),
+        }
+    }
+
+    pub fn process(&mut self, event: StreamEvent) -> Vec<StreamEvent> {
+        let Some(pending) = self.pending.as_mut() else {
+            return vec![event];
+        };
+
+        match event {
+            StreamEvent::Token(token) => {
+                pending.push_str(&token);
+                let Some(newline) = pending.find('\n') else {
+                    return vec![];
+                };
+                let mut pending = self.pending.take().unwrap();
+                if pending.trim_start().starts_with("```") {
+                    pending.drain(..=newline);
+                }
+                vec![StreamEvent::Token(pending)]
+            }
+            StreamEvent::KeepAlive => vec![StreamEvent::KeepAlive],
+            event => {
+                let pending = self.pending.take().unwrap();
+                match pending.is_empty() {
+                    true => vec![event],
+                    false => vec![StreamEvent::Token(pending), event],
+                }
+            }
+        }
+    }
+}
+
+/// Keeps the reply exactly as the model sent it.
+pub struct RawLogger {
+    pub content: String,
+}
+
+impl RawLogger {
+    pub fn new() -> Self {
+        Self {
+            content: String::new(),
+        }
+    }
+}
+
+impl Sink for RawLogger {
+    fn token(&mut self, token: &str) {
+        self.content.push_str(token);
+    }
+}
+
+/// Collects the contents of fenced code blocks.
+pub struct CodeBlockCollector<'a> {
+    code_blocks: &'a mut Vec<CodeBlock>,
+    line: String,
+    current: Option<CodeBlock>,
+}
+
+impl<'a> CodeBlockCollector<'a> {
+    pub fn new(code_blocks: &'a mut Vec<CodeBlock>) -> Self {
+        Self {
+            code_blocks,
+            line: String::new(),
+            current: None,
+        }
+    }
+
+    /// Continues a code block left open by a previous reply.
+    pub fn resume_code_block(&mut self, language: &str, content: &str) {
+        let mut lines: Vec<&str> = content.split('\n').collect();
+        self.line = lines.pop().unwrap_or_default().to_owned();
+        let mut block = lines.join("\n");
+        if !lines.is_empty() {
+            block.push('\n');
+        }
+        self.current = Some(CodeBlock {
+            language: language.to_owned(),
+            content: block,
+            truncated: false,
+        });
+    }
+
+    fn line(&mut self) {
+        let line = std::mem::take(&mut self.line);
+        let fence = line.trim_start().strip_prefix("```");
+        match self.current.as_mut() {
+            Some(_) if fence.is_some() => {
+                self.code_blocks
+                    .extend(self.current.take().map(with_language));
+            }
+            Some(block) => {
+                block.content.push_str(&line);
+                block.content.push('\n');
+            }
+            None => {
+                if let Some(language) = fence {
+                    self.current = Some(CodeBlock {
+                        language: language.trim().to_owned(),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Fills in the language of untagged blocks so highlighting and file
+/// extensions work for them too.
+fn with_language(mut block: CodeBlock) -> CodeBlock {
+    if block.language.is_empty() {
+        if let Some(language) = language::detect(&block.content) {
+            block.language = language.to_owned();
+        }
+    }
+    block
+}
+
+impl Sink for CodeBlockCollector<'_> {
+    fn token(&mut self, token: &str) {
+        for ch in token.chars() {
+            if ch == '\n' {
+                self.line();
+            } else {
+                self.line.push(ch);
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        if !self.line.is_empty() {
+            self.line();
+        }
+        // Keep what we got of a block the reply never closed.
+        if let Some(mut block) = self.current.take().map(with_language) {
+            block.truncated = true;
+            self.code_blocks.push(block);
+        }
+    }
+}
+
+/// Measures how much was streamed and how fast.
+pub struct UsageTracker {
+    started: Instant,
+    first_token: Option<Duration>,
+    pub chunks: usize,
+    pub chars: usize,
+    pub elapsed: Duration,
+    pub usage: Option<TokenUsage>,
+}
+
+impl UsageTracker {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            first_token: None,
+            chunks: 0,
+            chars: 0,
+            elapsed: Duration::ZERO,
+            usage: None,
+        }
+    }
+
+    pub fn first_token(&self) -> Option<Duration> {
+        self.first_token
+    }
+}
+
+impl Sink for UsageTracker {
+    fn token(&mut self, token: &str) {
+        if self.first_token.is_none() {
+            self.first_token = Some(self.started.elapsed());
+        }
+        self.chunks += 1;
+        self.chars += token.chars().count();
+    }
+
+    fn usage(&mut self, usage: &TokenUsage) {
+        self.usage = Some(*usage);
+    }
+
+    fn finish(&mut self) {
+        self.elapsed = self.started.elapsed();
+    }
+}
+
+/// Prints the reply colored by how likely the model found each token, and
+/// keeps the tokens for a closer look afterwards.
+pub struct ConfidenceRenderer {
+    pub tokens: Vec<TokenLogprob>,
+    /// Text already printed from logprobs that its tokens haven't caught
+    /// up with yet.
+    covered: String,
+}
+
+impl ConfidenceRenderer {
+    pub fn new() -> Self {
+        Self {
+            tokens: Vec::new(),
+            covered: String::new(),
+        }
+    }
+
+    /// ANSI color for a token probability, from the theme's confidence
+    /// colors: green when sure, red when not by default.
+    pub fn color(probability: f64) -> &'static str {
+        let colors = &theme::palette().confidence;
+        match probability {
+            p if p >= 0.9 => colors[0],
+            p if p >= 0.6 => colors[1],
+            p if p >= 0.3 => colors[2],
+            _ => colors[3],
+        }
+    }
+}
+
+impl Sink for ConfidenceRenderer {
+    // Tokens are printed from their logprobs, which carry the same text.
+    // Text without logprobs, from servers that don't send them, is printed
+    // uncolored.
+    fn token(&mut self, token: &str) {
+        if self.covered.starts_with(token) {
+            self.covered.drain(..token.len());
+        } else {
+            self.covered.clear();
+            print!("\x1b[0m{}", token.replace('\n', "\r\n"));
+            std::io::stdout().flush().unwrap();
+        }
+    }
+
+    fn logprobs(&mut self, logprobs: &[TokenLogprob]) {
+        for logprob in logprobs {
+            print!(
+                "{}{}",
+                Self::color(logprob.probability()),
+                logprob.token.replace('\n', "\r\n")
+            );
+        }
+        std::io::stdout().flush().unwrap();
+        self.covered
+            .extend(logprobs.iter().map(|l| l.token.as_str()));
+        self.tokens.extend_from_slice(logprobs);
+    }
+
+    fn finish(&mut self) {
+        print!("\x1b[0m");
+        std::io::stdout().flush().unwrap();
+    }
+}
+
+/// Prints the reply to the terminal, either as is or with light markdown
+/// styling and syntax highlighted code blocks.
+pub struct TerminalRenderer {
+    raw: bool,
+    stdout_is_terminal: bool,
+    in_code_block: bool,
+    language_reading: bool,
+    language: String,
+    current_code_block_content: String,
+    tick_count: usize,
+    star_cnt: usize,
+    in_effect: bool,
+    text_effected: bool,
+    next_newline_reset: bool,
+    line_numbers: bool,
+    block_index: usize,
+}
+
+impl TerminalRenderer {
+    pub fn new(raw: bool) -> Self {
+        Self {
+            raw,
+            stdout_is_terminal: std::io::stdout().is_terminal(),
+            in_code_block: false,
+            language_reading: false,
+            language: String::new(),
+            current_code_block_content: String::new(),
+            tick_count: 0,
+            star_cnt: 0,
+            in_effect: false,
+            text_effected: false,
+            next_newline_reset: true,
+            line_numbers: false,
+            block_index: 0,
+        }
+    }
+
+    /// Renders as for a terminal even when stdout is piped, for
+    /// `chad-gpt render`, which captures the output.
+    pub fn force_terminal(&mut self) {
+        self.stdout_is_terminal = true;
+    }
+
+    /// Prints code blocks with line numbers under a header holding the
+    /// language and the block's number, starting at `first_block`.
+    pub fn show_line_numbers(&mut self, first_block: usize) {
+        self.line_numbers = true;
+        self.block_index = first_block;
+    }
+
+    /// Continues a code block left open by a previous reply. The whole block
+    /// is printed again once it is closed.
+    pub fn resume_code_block(&mut self, language: &str, content: &str) {
+        self.in_code_block = true;
+        self.language = match language.is_empty() {
+            true => " ".to_owned(),
+            false => language.to_owned(),
+        };
+        self.current_code_block_content = content.to_owned();
+    }
+
+    fn print_code_block(&mut self) {
+        if self.stdout_is_terminal {
+            let mut language = language::resolve_alias(self.language.trim());
+            if language.is_empty() {
+                if let Some(detected) = language::detect(&self.current_code_block_content) {
+                    language = detected.to_owned();
+                }
+            }
+
+            let content = self.current_code_block_content.as_bytes();
+            let title = format!(
+                "#{} {}",
+                self.block_index,
+                match language.is_empty() {
+                    true => "text",
+                    false => &language,
+                }
+            );
+            let decorations = self.line_numbers && !COMPACT.load(Ordering::Relaxed);
+            let palette = theme::palette();
+            let printer = || {
+                let mut pp = PrettyPrinter::new();
+                pp.input(Input::from_bytes(content).title(title.clone()))
+                    .colored_output(palette.code_colors)
+                    .header(decorations)
+                    .line_numbers(decorations)
+                    .grid(decorations);
+                if let Some(code_theme) = palette.code_theme {
+                    pp.theme(code_theme);
+                }
+                pp
+            };
+
+            let mut pp = printer();
+            if !language.is_empty() {
+                pp.language(&language);
+            }
+            if pp.print().is_err() {
+                // bat doesn't know the language, show it as plain text.
+                printer().print().unwrap();
+            }
+            self.block_index += 1;
+        } else {
+            println!("{}", self.current_code_block_content);
+        }
+    }
+
+    fn char(&mut self, ch: char) {
+        if ch == '\n' && self.next_newline_reset {
+            print!("\x1b[0m");
+        }
+
+        if self.language_reading {
+            if ch == '\n' {
+                self.language_reading = false;
+            } else {
+                self.language.push(ch);
+                self.in_code_block = true;
+            }
+        } else if ch == '`' {
+            self.tick_count += 1;
+            if self.tick_count == 3 {
+                self.tick_count = 0;
+
+                if self.in_code_block {
+                    self.in_code_block = false;
+                    self.print_code_block();
+                    self.current_code_block_content.clear();
+                    self.language.clear();
+                } else {
+                    self.in_code_block = true;
+                    self.language_reading = true;
+                    self.language.clear();
+                }
+            }
+        } else if !self.in_code_block && (ch == '*' || ch == '_') {
+            if self.text_effected {
+                self.star_cnt -= 1;
+                if self.star_cnt == 0 {
+                    self.in_effect = false;
+                    print!("\x1b[0m");
+                    self.text_effected = false;
+                }
+            } else {
+                self.star_cnt += 1;
+                self.in_effect = true;
+                let palette = theme::palette();
+                if self.star_cnt == 1 {
+                    print!("{}", palette.emphasis);
+                } else if self.star_cnt == 2 {
+                    print!("{}", palette.strong);
+                } else if self.star_cnt == 3 {
+                    print!("{}", palette.strong_emphasis);
+                }
+            }
+        } else if !self.in_code_block && ch == '#' {
+            print!("{}#", theme::palette().heading);
+            self.next_newline_reset = true;
+        } else {
+            if self.in_effect {
+                self.text_effected = true;
+            }
+
+            if self.tick_count > 0 {
+                if self.stdout_is_terminal {
+                    print!("{}", "`".repeat(self.tick_count));
+                    std::io::stdout().flush().unwrap();
+                }
+                self.tick_count = 0;
+            }
+
+            if self.in_code_block {
+                if self.language.is_empty() {
+                    if ch == '\n' {
+                        self.language = " ".to_string();
+                    } else {
+                        self.language.push(ch);
+                    }
+                } else {
+                    self.current_code_block_content.push(ch);
+                }
+            } else if self.stdout_is_terminal {
+                print!("{}", ch);
+                std::io::stdout().flush().unwrap();
+            }
+        }
+    }
+}
+
+impl Sink for TerminalRenderer {
+    fn finish(&mut self) {
+        if self.in_code_block && !self.raw {
+            // The reply ended inside a code block, show what there is.
+            self.in_code_block = false;
+            self.language_reading = false;
+            self.print_code_block();
+            self.current_code_block_content.clear();
+            self.language.clear();
+            print!("(code block truncated)\r\n");
+        }
+        self.tick_count = 0;
+        self.star_cnt = 0;
+        self.in_effect = false;
+        self.text_effected = false;
+        print!("\x1b[0m");
+        std::io::stdout().flush().unwrap();
+    }
+
+    fn token(&mut self, token: &str) {
+        if self.raw {
+            print!("{}", token);
+            std::io::stdout().flush().unwrap();
+            return;
+        }
+
+        for ch in token.chars() {
+            self.char(ch);
+        }
+    }
 }
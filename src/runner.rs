@@ -0,0 +1,111 @@
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::{Child, Command};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `CHAD_LLM_RUN_TIMEOUT` seconds, or `DEFAULT_TIMEOUT`.
+pub fn timeout() -> Duration {
+    std::env::var("CHAD_LLM_RUN_TIMEOUT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map_or(DEFAULT_TIMEOUT, Duration::from_secs)
+}
+
+/// Program and script extension used to run code in `language`.
+pub fn interpreter(language: &str) -> Option<(&'static str, &'static str)> {
+    match language.to_lowercase().as_str() {
+        "python" | "py" | "python3" => Some(("python3", "py")),
+        "bash" | "sh" | "shell" | "zsh" => Some(("bash", "sh")),
+        "javascript" | "js" | "node" => Some(("node", "js")),
+        _ => None,
+    }
+}
+
+pub struct RunOutput {
+    /// `None` when the process was killed by a signal or the timeout.
+    pub status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+}
+
+impl RunOutput {
+    /// The output as a message for the model.
+    pub fn to_message(&self) -> String {
+        let mut message = match (self.timed_out, self.status) {
+            (true, _) => "I ran the code, it timed out.\n".to_owned(),
+            (false, Some(code)) => format!("I ran the code, it exited with status {}.\n", code),
+            (false, None) => "I ran the code, it was killed.\n".to_owned(),
+        };
+        for (name, output) in [("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            if !output.trim().is_empty() {
+                message.push_str(&format!("\n{}:\n```\n{}\n```\n", name, output.trim_end()));
+            }
+        }
+        message
+    }
+}
+
+/// Runs `code` with the language's interpreter inside a fresh temporary
+/// directory, without stdin, and kills it once `timeout` is exceeded.
+pub async fn run(language: &str, code: &str, timeout: Duration) -> io::Result<RunOutput> {
+    let (program, extension) = interpreter(language).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("don't know how to run '{}'", language),
+        )
+    })?;
+
+    let dir = tempfile::tempdir()?;
+    let script = dir.path().join(format!("main.{}", extension));
+    std::fs::write(&script, code)?;
+
+    let child = Command::new(program)
+        .arg(&script)
+        .current_dir(dir.path())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    wait(child, timeout).await
+}
+
+/// Runs `command` with the user's shell in `dir`, without stdin, and kills
+/// it once `timeout` is exceeded.
+pub async fn run_shell(command: &str, dir: &Path, timeout: Duration) -> io::Result<RunOutput> {
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_owned());
+    let child = Command::new(shell)
+        .arg("-c")
+        .arg(command)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    wait(child, timeout).await
+}
+
+async fn wait(child: Child, timeout: Duration) -> io::Result<RunOutput> {
+    match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(output) => {
+            let output = output?;
+            Ok(RunOutput {
+                status: output.status.code(),
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+                timed_out: false,
+            })
+        }
+        Err(_) => Ok(RunOutput {
+            status: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            timed_out: true,
+        }),
+    }
+}
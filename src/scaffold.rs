@@ -0,0 +1,173 @@
+//! Files spread over the code blocks of a reply, for `/scaffold`. A block
+//! belongs to a file when its fence (```` ```rust src/main.rs ````), its first
+//! line (`// src/main.rs`) or the line just before it (`File: Dockerfile`,
+//! `**src/lib.rs**`) names one. `/save_code` uses the same markers to name a
+//! single block.
+use crate::language;
+use crate::models::CodeBlock;
+
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+pub struct File {
+    pub path: String,
+    pub content: String,
+}
+
+/// Files without an extension that still make a recognizable name.
+const BARE_NAMES: &[&str] = &[
+    "Dockerfile",
+    "Makefile",
+    "Justfile",
+    "Procfile",
+    "Gemfile",
+    "Rakefile",
+    "Vagrantfile",
+    "LICENSE",
+    "README",
+];
+
+/// Whether `path` looks like a relative file path that stays inside the
+/// target directory.
+fn is_file_path(path: &str) -> bool {
+    static PATH: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[\w.\-]+(/[\w.\-]+)*$").unwrap());
+    let name = path.rsplit('/').next().unwrap_or(path);
+    PATH.is_match(path)
+        && !path.split('/').any(|part| part == "..")
+        && (BARE_NAMES.contains(&name)
+            || name.trim_start_matches('.').contains('.') && !name.ends_with('.'))
+}
+
+/// A file name in the prose line before a block, e.g. `File: src/main.rs`,
+/// `### Dockerfile` or `` `app.py`: ``.
+fn path_in_heading(line: &str) -> Option<String> {
+    static HEADING: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?i)^(?:#+\s*|[-*]\s+)?(?:\d+\.\s*)?(?:\*\*|__)?(?:file(?:name)?\s*:\s*)?[`*_]*([^\s`*_:]+)[`*_]*:?(?:\*\*|__)?:?$").unwrap()
+    });
+    let path = HEADING.captures(line.trim())?.get(1)?.as_str();
+    is_file_path(path).then(|| path.to_owned())
+}
+
+/// A file name in a comment on the first line of a block, e.g.
+/// `// src/main.rs` or `<!-- index.html -->`.
+fn path_in_comment(line: &str) -> Option<String> {
+    static COMMENT: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(
+            r"(?i)^\s*(?://|#|--|;|/\*|<!--)\s*(?:file(?:name)?\s*:\s*)?(\S+?)\s*(?:\*/|-->)?\s*$",
+        )
+        .unwrap()
+    });
+    let path = COMMENT.captures(line)?.get(1)?.as_str();
+    is_file_path(path).then(|| path.to_owned())
+}
+
+/// A file name after the language on the fence, e.g. `rust src/main.rs` or
+/// `rust:src/main.rs`.
+fn path_in_fence(info: &str) -> Option<String> {
+    let (_, path) = info.trim().split_once([' ', ':'])?;
+    let path = path.trim();
+    is_file_path(path).then(|| path.to_owned())
+}
+
+/// The files named in `reply`, in order. A file given more than once keeps
+/// its last contents.
+pub fn files(reply: &str) -> Vec<File> {
+    let mut files: Vec<File> = Vec::new();
+    let mut prose = String::new();
+    let mut lines = reply.lines();
+    while let Some(line) = lines.next() {
+        let Some(info) = line.trim_start().strip_prefix("```") else {
+            if !line.trim().is_empty() {
+                prose = line.to_owned();
+            }
+            continue;
+        };
+
+        let mut content: Vec<&str> = lines
+            .by_ref()
+            .take_while(|l| !l.trim_start().starts_with("```"))
+            .collect();
+        let path = match path_in_fence(info) {
+            Some(path) => Some(path),
+            None => match content.first().and_then(|l| path_in_comment(l)) {
+                Some(path) => {
+                    content.remove(0);
+                    Some(path)
+                }
+                None => path_in_heading(&prose),
+            },
+        };
+        prose.clear();
+
+        if let Some(path) = path {
+            let mut content = content.join("\n");
+            content.push('\n');
+            files.retain(|f| f.path != path);
+            files.push(File { path, content });
+        }
+    }
+    files
+}
+
+/// Draws `paths` as an indented tree, directories first appearing where
+/// their first file does.
+pub fn tree(paths: &[&str]) -> Vec<String> {
+    let mut paths = paths.to_vec();
+    paths.sort();
+    let mut shown = HashSet::new();
+    let mut lines = Vec::new();
+    for path in paths {
+        let parts: Vec<&str> = path.split('/').collect();
+        for depth in 0..parts.len() {
+            if !shown.insert(parts[..=depth].join("/")) {
+                continue;
+            }
+            let slash = match depth + 1 < parts.len() {
+                true => "/",
+                false => "",
+            };
+            lines.push(format!("{}{}{}", "  ".repeat(depth), parts[depth], slash));
+        }
+    }
+    lines
+}
+
+/// A file name the conversation asks for, e.g. "save this as utils.py".
+fn requested_path(text: &str) -> Option<String> {
+    static REQUEST: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?i)\b(?:save|call|name|put|store|write)\b[^.\n]{0,20}?\b(?:as|to|in|into|called|named)\s+[`'\x22*]*([\w./\-]+\w)").unwrap()
+    });
+    REQUEST
+        .captures_iter(text)
+        .map(|c| c[1].to_owned())
+        .filter(|path| is_file_path(path))
+        .last()
+}
+
+/// A file name for `block`, block number `number`, from markers around it
+/// in `reply`, a name asked for in `reply` or `request`, or otherwise its
+/// language.
+pub fn suggest_name(block: &CodeBlock, number: usize, reply: &str, request: &str) -> String {
+    let content = block.content.trim();
+    let marked = files(reply)
+        .into_iter()
+        .find(|f| !f.content.trim().is_empty() && content.contains(f.content.trim()));
+    if let Some(file) = marked {
+        return file.path;
+    }
+
+    let before = reply.find(content).map_or(reply, |end| &reply[..end]);
+    if let Some(path) = requested_path(before).or_else(|| requested_path(request)) {
+        return path;
+    }
+
+    match language::resolve_alias(&block.language).as_str() {
+        "Dockerfile" => "Dockerfile".to_owned(),
+        _ => format!(
+            "snippet_{}.{}",
+            number,
+            language::extension(&block.language)
+        ),
+    }
+}
@@ -0,0 +1,293 @@
+//! `chad-gpt serve`: the conversation as a web page on localhost, for reading
+//! long replies in a browser while the terminal stays interactive. The page
+//! polls for changes, so replies show up as they stream.
+use crate::language;
+use crate::openai::SharedContext;
+use crate::response::Sink;
+
+use bat::assets::HighlightingAssets;
+use regex::Regex;
+use std::io;
+use std::sync::{Arc, LazyLock, Mutex};
+use syntect::highlighting::Theme;
+use syntect::parsing::SyntaxSet;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+pub const DEFAULT_PORT: u16 = 8080;
+const CODE_THEME: &str = "Monokai Extended";
+/// Requests with longer headers are dropped.
+const MAX_REQUEST: usize = 16 * 1024;
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>chad-llm</title>
+<style>
+body { background: #1e1f1c; color: #e6e6e6; font: 16px/1.6 system-ui, sans-serif; margin: 0; }
+main { max-width: 52rem; margin: 0 auto; padding: 1rem 1.5rem 4rem; }
+.message { border-top: 1px solid #3a3b36; padding: 0.5rem 0; }
+.role { color: #8f908a; font-size: 0.8rem; text-transform: uppercase; }
+.user .text { color: #a6e22e; }
+.text { white-space: pre-wrap; overflow-wrap: break-word; }
+code { background: #2d2e29; border-radius: 3px; padding: 0 0.2em; }
+pre { padding: 0.8rem; border-radius: 4px; overflow-x: auto; white-space: pre; }
+pre code { background: none; padding: 0; }
+.empty { color: #8f908a; }
+</style>
+</head>
+<body>
+<main id="conversation">{conversation}</main>
+<script>
+// Replace the conversation when it changes, following it if scrolled to the end.
+setInterval(async () => {
+  try {
+    const html = await (await fetch("/conversation", { cache: "no-store" })).text();
+    const main = document.getElementById("conversation");
+    if (html === main.innerHTML) return;
+    const atEnd = window.innerHeight + window.scrollY >= document.body.scrollHeight - 40;
+    main.innerHTML = html;
+    if (atEnd) window.scrollTo(0, document.body.scrollHeight);
+  } catch (e) {}
+}, 1000);
+</script>
+</body>
+</html>
+"#;
+
+/// The web page being served. Dropping it stops the server.
+pub struct Server {
+    /// The reply being streamed, not yet part of the context.
+    streaming: Arc<Mutex<Option<String>>>,
+    listener: JoinHandle<()>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+impl Server {
+    /// Listens on `port` on localhost only. Must be called within the runtime.
+    pub async fn start(port: u16, context: SharedContext) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+        let streaming = Arc::new(Mutex::new(None));
+        let page = Page {
+            context,
+            streaming: Arc::clone(&streaming),
+        };
+        let listener = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(page.clone().respond(stream));
+            }
+        });
+        Ok(Self {
+            streaming,
+            listener,
+        })
+    }
+
+    /// A sink showing the reply on the page as it streams.
+    pub fn live(&self) -> Live {
+        Live {
+            streaming: Some(Arc::clone(&self.streaming)),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Page {
+    context: SharedContext,
+    streaming: Arc<Mutex<Option<String>>>,
+}
+
+impl Page {
+    async fn respond(self, mut stream: TcpStream) {
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => request.extend_from_slice(&buf[..n]),
+            }
+            if request.len() > MAX_REQUEST {
+                return;
+            }
+        }
+        let request = String::from_utf8_lossy(&request);
+
+        let (status, body) = match request_target(&request) {
+            // Other sites could otherwise read the page through DNS rebinding.
+            _ if !local_host(&request) => ("403 Forbidden", "Forbidden\n".to_owned()),
+            Some("/") => (
+                "200 OK",
+                PAGE.replace("{conversation}", &self.conversation().await),
+            ),
+            Some("/conversation") => ("200 OK", self.conversation().await),
+            _ => ("404 Not Found", "Not found\n".to_owned()),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\n\
+             Cache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        let _ = stream.write_all(response.as_bytes()).await;
+    }
+
+    async fn conversation(&self) -> String {
+        let mut messages: Vec<(String, String)> = self
+            .context
+            .lock()
+            .await
+            .iter()
+            .filter(|m| m.role != "system")
+            .map(|m| (m.role.clone(), m.content.clone()))
+            .collect();
+        if let Some(reply) = self.streaming.lock().unwrap().clone() {
+            messages.push(("assistant".to_owned(), reply));
+        }
+        if messages.is_empty() {
+            return r#"<p class="empty">No messages yet.</p>"#.to_owned();
+        }
+        messages
+            .iter()
+            .map(|(role, content)| {
+                format!(
+                    "<div class=\"message {0}\"><div class=\"role\">{0}</div>{1}</div>\n",
+                    escape(role),
+                    markdown(content)
+                )
+            })
+            .collect()
+    }
+}
+
+/// The path of a GET request.
+fn request_target(request: &str) -> Option<&str> {
+    let mut parts = request.lines().next()?.split(' ');
+    match parts.next()? {
+        "GET" => parts.next(),
+        _ => None,
+    }
+}
+
+/// Whether the request's Host header, if any, names this machine.
+pub fn local_host(request: &str) -> bool {
+    let host = request.lines().skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("host").then(|| value.trim())
+    });
+    let Some(host) = host else {
+        return true;
+    };
+    let name = match host.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    matches!(name, "localhost" | "127.0.0.1" | "::1")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Inline code and bold in escaped prose.
+fn inline(text: &str) -> String {
+    static STRONG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\*\*([^*]+)\*\*").unwrap());
+    let mut html = String::new();
+    for (i, part) in escape(text).split('`').enumerate() {
+        match i % 2 {
+            1 => html.push_str(&format!("<code>{}</code>", part)),
+            _ => html.push_str(&STRONG.replace_all(part, "<strong>$1</strong>")),
+        }
+    }
+    html
+}
+
+/// Replies as HTML: prose keeps its line breaks, code blocks are highlighted.
+fn markdown(content: &str) -> String {
+    let mut html = String::new();
+    let mut prose = String::new();
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        let Some(language) = line.trim_start().strip_prefix("```") else {
+            prose.push_str(line);
+            prose.push('\n');
+            continue;
+        };
+        if !prose.trim().is_empty() {
+            html.push_str(&format!(
+                "<div class=\"text\">{}</div>",
+                inline(prose.trim())
+            ));
+        }
+        prose.clear();
+        let code: Vec<&str> = lines
+            .by_ref()
+            .take_while(|l| !l.trim_start().starts_with("```"))
+            .collect();
+        html.push_str(&highlight(&(code.join("\n") + "\n"), language.trim()));
+    }
+    if !prose.trim().is_empty() {
+        html.push_str(&format!(
+            "<div class=\"text\">{}</div>",
+            inline(prose.trim())
+        ));
+    }
+    html
+}
+
+fn highlight(code: &str, language: &str) -> String {
+    static ASSETS: LazyLock<(SyntaxSet, Theme)> = LazyLock::new(|| {
+        let assets = HighlightingAssets::from_binary();
+        let syntaxes = assets.get_syntax_set().unwrap().clone();
+        (syntaxes, assets.get_theme(CODE_THEME).clone())
+    });
+    let (syntaxes, theme) = &*ASSETS;
+    // The fence may name a file after the language.
+    let language = language.split([' ', ':']).next().unwrap_or_default();
+    let syntax = syntaxes
+        .find_syntax_by_token(&language::resolve_alias(language))
+        .or_else(|| syntaxes.find_syntax_by_token(language))
+        .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+    syntect::html::highlighted_html_for_string(code, syntaxes, syntax, theme)
+        .unwrap_or_else(|_| format!("<pre><code>{}</code></pre>", escape(code)))
+}
+
+/// Shows each token of a reply on the page, or nothing when not serving.
+pub struct Live {
+    streaming: Option<Arc<Mutex<Option<String>>>>,
+}
+
+impl Live {
+    pub fn none() -> Self {
+        Self { streaming: None }
+    }
+}
+
+impl Sink for Live {
+    fn token(&mut self, token: &str) {
+        if let Some(streaming) = &self.streaming {
+            streaming
+                .lock()
+                .unwrap()
+                .get_or_insert_with(String::new)
+                .push_str(token);
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(streaming) = &self.streaming {
+            *streaming.lock().unwrap() = None;
+        }
+    }
+}
@@ -0,0 +1,307 @@
+//! Experimental shared sessions, see `/share` and `chad-gpt attach`. The host
+//! streams its conversation as JSON lines to everyone attached over a Unix
+//! socket or TCP. Guests of a writable share can send prompts, which the host
+//! sends in turn, whenever its own input line is empty.
+use crate::openai::SharedContext;
+use crate::response::{Sink, TerminalRenderer};
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::JoinHandle;
+
+/// A guest falling this many events behind misses them.
+const BACKLOG: usize = 4096;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A whole message: the conversation so far on attach, then each prompt.
+    Message {
+        role: String,
+        content: String,
+    },
+    /// Part of the reply being streamed.
+    Token {
+        text: String,
+    },
+    /// The reply is complete.
+    Done,
+    /// A guest's prompt for the host to send.
+    Prompt {
+        content: String,
+    },
+    Notice {
+        text: String,
+    },
+}
+
+impl Event {
+    fn line(&self) -> String {
+        let mut line = serde_json::to_string(self).unwrap();
+        line.push('\n');
+        line
+    }
+}
+
+/// A session being hosted. Dropping it stops the listener.
+pub struct Share {
+    pub address: String,
+    pub writable: bool,
+    events: broadcast::Sender<Event>,
+    /// Guest prompts waiting for the host's turn to end.
+    pub prompts: Arc<Mutex<VecDeque<String>>>,
+    listener: JoinHandle<()>,
+    /// Dropped with the share, which disconnects the guests.
+    _stop: watch::Sender<()>,
+}
+
+impl Drop for Share {
+    fn drop(&mut self) {
+        self.listener.abort();
+        if !is_tcp(&self.address) {
+            let _ = std::fs::remove_file(&self.address);
+        }
+    }
+}
+
+/// `host:port` addresses are TCP, anything else a Unix socket path.
+pub fn is_tcp(address: &str) -> bool {
+    address.contains(':') && !address.contains('/')
+}
+
+impl Share {
+    /// Starts listening on `address`. Must be called within the runtime.
+    pub async fn host(address: &str, context: SharedContext, writable: bool) -> io::Result<Self> {
+        let (events, _) = broadcast::channel(BACKLOG);
+        let (stop, stopped) = watch::channel(());
+        let prompts = Arc::new(Mutex::new(VecDeque::new()));
+        let guest = Guest {
+            context,
+            events: events.clone(),
+            prompts: writable.then(|| Arc::clone(&prompts)),
+            stopped,
+        };
+
+        let listener = match is_tcp(address) {
+            true => {
+                let listener = tokio::net::TcpListener::bind(address).await?;
+                tokio::spawn(async move {
+                    while let Ok((stream, _)) = listener.accept().await {
+                        tokio::spawn(guest.clone().serve(stream));
+                    }
+                })
+            }
+            false => unix_listener(address, guest)?,
+        };
+        Ok(Self {
+            address: address.to_owned(),
+            writable,
+            events,
+            prompts,
+            listener,
+            _stop: stop,
+        })
+    }
+
+    pub fn send(&self, event: Event) {
+        // Fails only when nobody is attached.
+        let _ = self.events.send(event);
+    }
+
+    /// A sink relaying the reply to the guests.
+    pub fn relay(&self) -> Relay {
+        Relay {
+            events: Some(self.events.clone()),
+        }
+    }
+
+    pub fn guests(&self) -> usize {
+        self.events.receiver_count()
+    }
+}
+
+#[cfg(unix)]
+fn unix_listener(path: &str, guest: Guest) -> io::Result<JoinHandle<()>> {
+    // A socket left behind by an earlier run would make bind fail.
+    if std::os::unix::net::UnixStream::connect(path).is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    Ok(tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(guest.clone().serve(stream));
+        }
+    }))
+}
+
+#[cfg(not(unix))]
+fn unix_listener(_path: &str, _guest: Guest) -> io::Result<JoinHandle<()>> {
+    Err(io::Error::other(
+        "Unix sockets aren't supported here, use a host:port address",
+    ))
+}
+
+/// What each connection needs from the host.
+#[derive(Clone)]
+struct Guest {
+    context: SharedContext,
+    events: broadcast::Sender<Event>,
+    /// `None` for read-only shares.
+    prompts: Option<Arc<Mutex<VecDeque<String>>>>,
+    stopped: watch::Receiver<()>,
+}
+
+impl Guest {
+    async fn serve(mut self, stream: impl AsyncRead + AsyncWrite + Send + 'static) {
+        let (reader, mut writer) = tokio::io::split(stream);
+        // Subscribe before taking the snapshot so nothing falls in between.
+        let mut events = self.events.subscribe();
+        let history: String = self
+            .context
+            .lock()
+            .await
+            .iter()
+            .filter(|m| m.role != "system")
+            .map(|m| {
+                Event::Message {
+                    role: m.role.clone(),
+                    content: m.content.clone(),
+                }
+                .line()
+            })
+            .collect();
+        if writer.write_all(history.as_bytes()).await.is_err() {
+            return;
+        }
+
+        // Notices for this guest only.
+        let (notices, mut notices_rx) = mpsc::unbounded_channel();
+        let prompts = self.prompts.clone();
+        let mut lines = BufReader::new(reader).lines();
+        let incoming = tokio::spawn(async move {
+            while let Ok(Some(line)) = lines.next_line().await {
+                let Ok(Event::Prompt { content }) = serde_json::from_str(&line) else {
+                    continue;
+                };
+                match &prompts {
+                    Some(prompts) => prompts.lock().unwrap().push_back(content),
+                    None => {
+                        let _ = notices.send(Event::Notice {
+                            text: "This session is read-only.".to_owned(),
+                        });
+                    }
+                }
+            }
+        });
+
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                Some(notice) = notices_rx.recv() => notice,
+                _ = self.stopped.changed() => break,
+            };
+            if writer.write_all(event.line().as_bytes()).await.is_err() {
+                break;
+            }
+        }
+        incoming.abort();
+    }
+}
+
+/// Sends each token of a reply to the guests, or nothing when not sharing.
+pub struct Relay {
+    events: Option<broadcast::Sender<Event>>,
+}
+
+impl Relay {
+    pub fn none() -> Self {
+        Self { events: None }
+    }
+}
+
+impl Sink for Relay {
+    fn token(&mut self, token: &str) {
+        if let Some(events) = &self.events {
+            let _ = events.send(Event::Token {
+                text: token.to_owned(),
+            });
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(events) = &self.events {
+            let _ = events.send(Event::Done);
+        }
+    }
+}
+
+/// Follows a shared session at `address` until it ends, sending lines typed
+/// on stdin as prompts.
+pub async fn attach(address: &str) -> io::Result<()> {
+    match is_tcp(address) {
+        true => follow(tokio::net::TcpStream::connect(address).await?).await,
+        false => attach_unix(address).await,
+    }
+}
+
+#[cfg(unix)]
+async fn attach_unix(path: &str) -> io::Result<()> {
+    follow(tokio::net::UnixStream::connect(path).await?).await
+}
+
+#[cfg(not(unix))]
+async fn attach_unix(_path: &str) -> io::Result<()> {
+    Err(io::Error::other(
+        "Unix sockets aren't supported here, use a host:port address",
+    ))
+}
+
+async fn follow(stream: impl AsyncRead + AsyncWrite + Send + 'static) -> io::Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    tokio::spawn(async move {
+        let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(line)) = stdin.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let prompt = Event::Prompt { content: line };
+            if writer.write_all(prompt.line().as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut renderer = TerminalRenderer::new(false);
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let Ok(event) = serde_json::from_str::<Event>(&line) else {
+            continue;
+        };
+        match event {
+            Event::Message { role, content } if role == "assistant" => {
+                renderer.token(&content);
+                renderer.finish();
+                print!("\r\n\r\n");
+            }
+            Event::Message { content, .. } => print!("> {}\r\n", content.replace('\n', "\r\n")),
+            Event::Token { text } => renderer.token(&text),
+            Event::Done => {
+                renderer.finish();
+                print!("\r\n\r\n");
+            }
+            Event::Notice { text } => print!("({})\r\n", text),
+            Event::Prompt { .. } => {}
+        }
+        std::io::Write::flush(&mut std::io::stdout())?;
+    }
+    print!("The session has ended.\r\n");
+    Ok(())
+}
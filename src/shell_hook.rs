@@ -0,0 +1,219 @@
+//! The last command run in the user's shell, for `/debug_last_cmd`. A hook
+//! from `chad-gpt shell-hook <shell>` records each command with its exit
+//! status and directory; without it the shell's history file is used.
+use crate::application::get_data_path;
+use crate::runner::RunOutput;
+
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+pub const LAST_COMMAND_FILE: &str = "last_command";
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+/// Longer and the version is left out.
+const VERSION_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct LastCommand {
+    pub command: String,
+    /// Unknown when read from the history file.
+    pub status: Option<i32>,
+    pub dir: Option<PathBuf>,
+}
+
+/// The snippet to `eval` in the shell's rc file.
+pub fn hook(shell: &str) -> Option<String> {
+    let path = get_data_path(LAST_COMMAND_FILE).display().to_string();
+    let quoted = format!("'{}'", path.replace('\'', r"'\''"));
+    match shell {
+        "bash" => Some(format!(
+            r#"__chad_llm_last_cmd() {{
+    local code=$?
+    printf '%s\n%s\n%s\n' "$code" "$PWD" "$(HISTTIMEFORMAT= history 1 | sed 's/^ *[0-9]* *//')" > {}
+    return $code
+}}
+PROMPT_COMMAND="__chad_llm_last_cmd${{PROMPT_COMMAND:+;$PROMPT_COMMAND}}""#,
+            quoted
+        )),
+        "zsh" => Some(format!(
+            r#"__chad_llm_last_cmd() {{
+    local code=$?
+    printf '%s\n%s\n%s\n' "$code" "$PWD" "$(fc -ln -1)" > {}
+}}
+precmd_functions=(__chad_llm_last_cmd $precmd_functions)"#,
+            quoted
+        )),
+        "fish" => Some(format!(
+            r#"function __chad_llm_last_cmd --on-event fish_postexec
+    printf '%s\n%s\n%s\n' $status $PWD "$argv" > '{}'
+end"#,
+            path.replace('\'', r"\'")
+        )),
+        _ => None,
+    }
+}
+
+/// The command recorded by the hook, or else the last one in the shell's
+/// history file.
+pub fn load() -> Option<LastCommand> {
+    if let Ok(contents) = std::fs::read_to_string(get_data_path(LAST_COMMAND_FILE)) {
+        let mut lines = contents.splitn(3, '\n');
+        let status = lines.next()?.trim().parse().ok();
+        let dir = lines.next().map(PathBuf::from);
+        let command = lines.next().unwrap_or_default().trim().to_owned();
+        if !command.is_empty() {
+            return Some(LastCommand {
+                command,
+                status,
+                dir,
+            });
+        }
+    }
+    from_history()
+}
+
+fn from_history() -> Option<LastCommand> {
+    let home = dirs::home_dir()?;
+    let path = std::env::var("HISTFILE")
+        .map(PathBuf::from)
+        .ok()
+        .or_else(|| {
+            [".zsh_history", ".bash_history"]
+                .iter()
+                .map(|name| home.join(name))
+                .find(|path| path.exists())
+        })?;
+    let bytes = std::fs::read(path).ok()?;
+    let this = std::env::args().next().unwrap_or_default();
+    let this = this.rsplit('/').next().unwrap_or_default().to_owned();
+    String::from_utf8_lossy(&bytes)
+        .lines()
+        .rev()
+        // zsh's extended history puts `: <time>:<duration>;` in front.
+        .map(|line| match line.starts_with(": ") {
+            true => line.split_once(';').map_or(line, |(_, command)| command),
+            false => line,
+        })
+        .map(str::trim)
+        .find(|command| !command.is_empty() && program(command) != Some(this.as_str()))
+        .map(|command| LastCommand {
+            command: command.to_owned(),
+            status: None,
+            dir: None,
+        })
+}
+
+/// The program a command line runs, past variable assignments and `sudo`.
+fn program(command: &str) -> Option<&str> {
+    command
+        .split_whitespace()
+        .find(|word| !word.contains('=') && *word != "sudo")
+        .map(|word| word.rsplit('/').next().unwrap_or(word))
+}
+
+/// The first line `tool --version` prints, if it does within
+/// `VERSION_TIMEOUT`.
+fn version(tool: &str) -> Option<String> {
+    let arg = match tool {
+        "go" => "version",
+        _ => "--version",
+    };
+    let mut child = Command::new(tool)
+        .arg(arg)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .ok()?;
+    let started = Instant::now();
+    while child.try_wait().ok()?.is_none() {
+        if started.elapsed() >= VERSION_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    // A version line fits in the pipe, the exited child can't block on it.
+    let mut text = String::new();
+    child.stdout.take()?.read_to_string(&mut text).ok()?;
+    if text.trim().is_empty() {
+        child.stderr.take()?.read_to_string(&mut text).ok()?;
+    }
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_owned)
+}
+
+/// OS, shell and versions of the toolchain the command belongs to.
+pub fn environment(command: &str) -> Vec<String> {
+    let mut lines = vec![format!(
+        "OS: {} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )];
+    if let Ok(release) = std::fs::read_to_string("/etc/os-release") {
+        if let Some(name) = release.lines().find_map(|l| l.strip_prefix("PRETTY_NAME=")) {
+            lines.push(format!("Distribution: {}", name.trim_matches('"')));
+        }
+    }
+    if let Ok(shell) = std::env::var("SHELL") {
+        lines.push(format!("Shell: {}", shell));
+    }
+
+    let Some(program) = program(command) else {
+        return lines;
+    };
+    // Only known toolchains: running an arbitrary program with `--version`
+    // could do anything.
+    let tools: &[&str] = match program {
+        "cargo" | "rustc" | "rustup" => &["cargo", "rustc"],
+        "python" | "python3" | "pip" | "pip3" | "pytest" => &["python3", "pip3"],
+        "node" | "npm" | "npx" => &["node", "npm"],
+        "yarn" => &["node", "yarn"],
+        "pnpm" => &["node", "pnpm"],
+        "gcc" | "g++" | "make" => &["gcc", "make"],
+        "clang" | "clang++" => &["clang"],
+        "cmake" => &["cmake"],
+        "go" => &["go"],
+        "ruby" | "gem" | "bundle" => &["ruby", "gem"],
+        "java" | "javac" => &["java"],
+        "git" => &["git"],
+        "docker" => &["docker"],
+        _ => &[],
+    };
+    for tool in tools {
+        if let Some(version) = version(tool) {
+            lines.push(format!("{}: {}", tool, version));
+        }
+    }
+    lines
+}
+
+/// The message asking the model what went wrong.
+pub fn message(last: &LastCommand, output: Option<&RunOutput>, environment: &[String]) -> String {
+    let mut message = format!(
+        "This command failed in my shell. What went wrong, and how do I fix it?\n\n```\n{}\n```\n",
+        last.command
+    );
+    if let Some(status) = last.status {
+        message.push_str(&format!("Exit status: {}\n", status));
+    }
+    if let Some(dir) = &last.dir {
+        message.push_str(&format!("Directory: {}\n", dir.display()));
+    }
+    if let Some(output) = output {
+        match output.timed_out {
+            true => message.push_str("\nRun again, it timed out.\n"),
+            false => message.push_str("\nRun again, it printed:\n"),
+        }
+        for (name, text) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+            if !text.trim().is_empty() {
+                message.push_str(&format!("\n{}:\n```\n{}\n```\n", name, text.trim_end()));
+            }
+        }
+    }
+    message.push_str(&format!("\nEnvironment:\n{}\n", environment.join("\n")));
+    message
+}
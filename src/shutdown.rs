@@ -0,0 +1,141 @@
+//! Leaving the terminal as it was found however chad-llm stops: a panic or
+//! a signal mid-reply resets raw mode, colors and the window title, and the
+//! reply streamed so far is kept in the transcript.
+use crate::history::History;
+use crate::response::Sink;
+
+use crossterm::{cursor, execute, terminal};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tokio::runtime::Runtime;
+
+/// Whether the window title was saved, and so is to be put back.
+static TITLE_SAVED: AtomicBool = AtomicBool::new(false);
+/// The reply being streamed, see `Spool`.
+static UNFINISHED: Mutex<Option<Unfinished>> = Mutex::new(None);
+
+struct Unfinished {
+    session: String,
+    model: String,
+    prompt: Option<String>,
+    reply: String,
+}
+
+/// Installs the panic hook and, within `rt`, the signal handlers. With
+/// `title`, the window title saved by `cli::save_title` is restored too.
+pub fn install(rt: &Runtime, title: bool) {
+    TITLE_SAVED.store(title, Ordering::Relaxed);
+
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // Panics in background tasks leave chad-llm running.
+        if std::thread::current().name() == Some("main") {
+            restore_terminal();
+            save_unfinished();
+        }
+        previous(info);
+    }));
+
+    rt.spawn(async {
+        let code = signalled().await;
+        restore_terminal();
+        save_unfinished();
+        std::process::exit(code);
+    });
+}
+
+/// Waits for a signal that ends chad-llm and gives the exit code for it.
+#[cfg(unix)]
+async fn signalled() -> i32 {
+    use tokio::signal::unix::{signal, SignalKind};
+    let (Ok(mut terminate), Ok(mut hangup)) = (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::hangup()),
+    ) else {
+        return std::future::pending().await;
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => 130,
+        _ = terminate.recv() => 143,
+        _ = hangup.recv() => 129,
+    }
+}
+
+#[cfg(not(unix))]
+async fn signalled() -> i32 {
+    match tokio::signal::ctrl_c().await {
+        Ok(()) => 130,
+        Err(_) => std::future::pending().await,
+    }
+}
+
+/// Leaves raw mode and resets colors, the cursor and the title. Errors are
+/// ignored: this runs when something already went wrong.
+fn restore_terminal() {
+    let _ = terminal::disable_raw_mode();
+    let mut stdout = io::stdout();
+    let _ = write!(stdout, "\x1b[0m");
+    let _ = execute!(stdout, cursor::Show);
+    if TITLE_SAVED.load(Ordering::Relaxed) {
+        let _ = write!(stdout, "\x1b[23;0t");
+    }
+    let _ = writeln!(stdout);
+    let _ = stdout.flush();
+}
+
+/// Adds the reply cut short, and the prompt it answers, to its transcript.
+fn save_unfinished() {
+    let Some(unfinished) = UNFINISHED.lock().ok().and_then(|mut u| u.take()) else {
+        return;
+    };
+    if unfinished.reply.is_empty() {
+        return;
+    }
+    let mut history = History::new();
+    history.resume(&unfinished.session);
+    let saved = match &unfinished.prompt {
+        Some(prompt) => history.save_message("user", prompt, None),
+        None => Ok(()),
+    }
+    .and_then(|_| history.save_message("assistant", &unfinished.reply, Some(&unfinished.model)));
+    match saved {
+        Ok(()) => eprintln!("Saved the unfinished reply to the transcript."),
+        Err(e) => eprintln!("Failed to save the unfinished reply: {}", e),
+    }
+}
+
+/// Keeps the reply as it streams, to be saved if chad-llm is stopped before
+/// it completes. Completed replies are saved by the caller, so dropping the
+/// spool forgets it.
+pub struct Spool;
+
+impl Spool {
+    /// `prompt` is saved along with the reply, unless the reply continues an
+    /// earlier one.
+    pub fn new(session: &str, model: &str, prompt: Option<&str>) -> Self {
+        *UNFINISHED.lock().unwrap() = Some(Unfinished {
+            session: session.to_owned(),
+            model: model.to_owned(),
+            prompt: prompt.map(String::from),
+            reply: String::new(),
+        });
+        Spool
+    }
+}
+
+impl Sink for Spool {
+    fn token(&mut self, token: &str) {
+        if let Some(unfinished) = UNFINISHED.lock().unwrap().as_mut() {
+            unfinished.reply.push_str(token);
+        }
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        if let Ok(mut unfinished) = UNFINISHED.lock() {
+            *unfinished = None;
+        }
+    }
+}
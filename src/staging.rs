@@ -0,0 +1,112 @@
+use std::path::Path;
+
+pub enum StagedPart {
+    File {
+        path: String,
+        contents: String,
+    },
+    Clipboard(String),
+    Note(String),
+    /// Input piped in with `-i`.
+    Stdin(String),
+    /// A chunk of a digested document, see `/digest`.
+    Excerpt {
+        path: String,
+        index: usize,
+        contents: String,
+    },
+}
+
+impl StagedPart {
+    pub fn summary(&self) -> String {
+        match self {
+            StagedPart::File { path, contents } => {
+                format!("file {} ({} lines)", path, contents.lines().count())
+            }
+            StagedPart::Clipboard(contents) => {
+                format!("clipboard ({} lines)", contents.lines().count())
+            }
+            StagedPart::Note(note) => format!("note \"{}\"", note),
+            StagedPart::Stdin(contents) => {
+                format!("piped input ({} lines)", contents.lines().count())
+            }
+            StagedPart::Excerpt { path, index, .. } => {
+                format!("part {} of {}", index + 1, path)
+            }
+        }
+    }
+
+    fn render(&self) -> String {
+        match self {
+            StagedPart::File { path, contents } => {
+                let language = Path::new(path)
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or("");
+                format!(
+                    "File `{}`:\n```{}\n{}\n```",
+                    path,
+                    language,
+                    contents.trim_end()
+                )
+            }
+            StagedPart::Clipboard(contents) => {
+                format!("Clipboard contents:\n```\n{}\n```", contents.trim_end())
+            }
+            StagedPart::Note(note) => format!("Note: {}", note),
+            StagedPart::Stdin(contents) => {
+                format!("Piped input:\n```\n{}\n```", contents.trim_end())
+            }
+            StagedPart::Excerpt {
+                path,
+                index,
+                contents,
+            } => format!(
+                "Part {} of `{}`:\n```\n{}\n```",
+                index + 1,
+                path,
+                contents.trim_end()
+            ),
+        }
+    }
+}
+
+pub struct Staging {
+    parts: Vec<StagedPart>,
+}
+
+impl Staging {
+    pub fn new() -> Self {
+        Self { parts: Vec::new() }
+    }
+
+    pub fn add(&mut self, part: StagedPart) {
+        self.parts.push(part);
+    }
+
+    pub fn parts(&self) -> &[StagedPart] {
+        &self.parts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.parts.clear();
+    }
+
+    /// Combines the staged parts and `message` into a single user message.
+    pub fn build(&self, message: &str) -> String {
+        let mut out = String::new();
+        if !message.trim().is_empty() {
+            out.push_str(message.trim_end());
+            out.push_str("\n\n");
+        }
+        for part in self.parts.iter() {
+            out.push_str(&part.render());
+            out.push_str("\n\n");
+        }
+        out.trim_end().to_owned()
+    }
+}
@@ -0,0 +1,163 @@
+use crate::application::get_data_path;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const STATS_FILE: &str = "stats.jsonl";
+
+/// Timing of a single streamed reply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestStats {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub provider: String,
+    pub model: String,
+    pub ttft_ms: u64,
+    pub total_ms: u64,
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub cached_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl RequestStats {
+    pub fn tokens_per_second(&self) -> f64 {
+        let generating = self.total_ms.saturating_sub(self.ttft_ms);
+        match generating {
+            0 => 0.0,
+            ms => self.completion_tokens as f64 * 1000.0 / ms as f64,
+        }
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Converts a Unix timestamp to a `YYYY-MM-DD` date (UTC).
+pub fn date(timestamp: u64) -> String {
+    // Howard Hinnant's civil_from_days.
+    let z = (timestamp / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// A Unix timestamp broken down in the local time zone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalTime {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    /// 0 is Sunday.
+    pub weekday: u32,
+    pub hour: u32,
+    pub minute: u32,
+}
+
+#[cfg(unix)]
+pub fn local_time(timestamp: u64) -> LocalTime {
+    let seconds = timestamp as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&seconds, &mut tm) };
+    LocalTime {
+        year: tm.tm_year + 1900,
+        month: tm.tm_mon as u32 + 1,
+        day: tm.tm_mday as u32,
+        weekday: tm.tm_wday as u32,
+        hour: tm.tm_hour as u32,
+        minute: tm.tm_min as u32,
+    }
+}
+
+/// UTC where the local time zone isn't available.
+#[cfg(not(unix))]
+pub fn local_time(timestamp: u64) -> LocalTime {
+    let date = date(timestamp);
+    let clock = clock(timestamp);
+    let part = |s: &str| s.parse().unwrap_or(0);
+    LocalTime {
+        year: part(&date[..4]) as i32,
+        month: part(&date[5..7]),
+        day: part(&date[8..10]),
+        // 1970-01-01 was a Thursday.
+        weekday: ((timestamp / 86_400 + 4) % 7) as u32,
+        hour: part(&clock[..2]),
+        minute: part(&clock[3..5]),
+    }
+}
+
+/// Converts a Unix timestamp to a `YYYY-MM-DD` date in the local time zone.
+pub fn local_date(timestamp: u64) -> String {
+    let time = local_time(timestamp);
+    format!("{:04}-{:02}-{:02}", time.year, time.month, time.day)
+}
+
+/// Converts a Unix timestamp to a `HH:MM` time of day (UTC).
+pub fn clock(timestamp: u64) -> String {
+    let seconds = timestamp % 86_400;
+    format!("{:02}:{:02}", seconds / 3600, seconds % 3600 / 60)
+}
+
+pub fn record(stats: &RequestStats) -> Result<(), Box<dyn Error>> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(get_data_path(STATS_FILE))?;
+    writeln!(file, "{}", serde_json::to_string(stats)?)?;
+    Ok(())
+}
+
+pub fn load() -> Result<Vec<RequestStats>, Box<dyn Error>> {
+    let contents = std::fs::read_to_string(get_data_path(STATS_FILE))?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+pub struct Aggregate {
+    pub requests: usize,
+    pub avg_ttft_ms: f64,
+    pub avg_total_ms: f64,
+    pub avg_tokens_per_second: f64,
+}
+
+/// Averages the records per (provider, model).
+pub fn aggregate(records: &[RequestStats]) -> BTreeMap<(String, String), Aggregate> {
+    let mut groups: BTreeMap<(String, String), Vec<&RequestStats>> = BTreeMap::new();
+    for record in records {
+        groups
+            .entry((record.provider.clone(), record.model.clone()))
+            .or_default()
+            .push(record);
+    }
+
+    groups
+        .into_iter()
+        .map(|(key, group)| {
+            let n = group.len() as f64;
+            let aggregate = Aggregate {
+                requests: group.len(),
+                avg_ttft_ms: group.iter().map(|r| r.ttft_ms as f64).sum::<f64>() / n,
+                avg_total_ms: group.iter().map(|r| r.total_ms as f64).sum::<f64>() / n,
+                avg_tokens_per_second: group.iter().map(|r| r.tokens_per_second()).sum::<f64>() / n,
+            };
+            (key, aggregate)
+        })
+        .collect()
+}
@@ -0,0 +1,40 @@
+//! Data files shared by every chad-llm running at the same time. A file that
+//! is read, changed and written back is locked for the whole update, and
+//! replaced in one step so another instance never reads it half written.
+use crate::application::get_data_path;
+
+use std::fs::{File, OpenOptions};
+use std::io;
+
+/// An exclusive lock on a data file, released when dropped.
+pub struct Lock {
+    _file: File,
+}
+
+/// Waits for the lock on the data file `name`. The lock is held on a
+/// `.lock` file next to it, as the data file itself is replaced on writes.
+pub fn lock(name: &str) -> io::Result<Lock> {
+    let path = get_data_path(&format!("{}.lock", name));
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    file.lock()?;
+    Ok(Lock { _file: file })
+}
+
+/// Replaces the data file `name` with `contents` in one step. Take its lock
+/// first when the contents depend on what was there.
+pub fn replace(name: &str, contents: &str) -> io::Result<()> {
+    let path = get_data_path(name);
+    let mut temporary = path.clone().into_os_string();
+    temporary.push(format!(".{}.tmp", std::process::id()));
+    std::fs::write(&temporary, contents)?;
+    std::fs::rename(&temporary, &path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temporary);
+    })
+}
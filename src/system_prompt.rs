@@ -1,14 +1,31 @@
-use dirs::data_dir;
+use crate::application::get_data_path;
+use crate::crypto;
+use crate::storage;
+
 use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
 use std::error::Error;
+use std::io;
 
-const FILE_NAME: &'static str = "system_prompts.json";
+pub const SYSTEM_PROMPTS_FILE: &str = "system_prompts.json";
 
 #[derive(Serialize, Deserialize)]
 pub struct SystemPrompts {
     prompts: HashMap<String, String>,
+    #[serde(skip)]
+    purged: bool,
+    /// Prompts set, or removed (`None`), by this instance since it last
+    /// wrote the file.
+    #[serde(skip)]
+    changed: HashMap<String, Option<String>>,
+}
+
+/// The file's contents. Reading them into `SystemPrompts` would write them
+/// back when dropped.
+#[derive(Deserialize)]
+struct Stored {
+    prompts: HashMap<String, String>,
 }
 
 #[derive(Debug)]
@@ -36,6 +53,8 @@ impl SystemPrompts {
     pub fn new() -> Self {
         let mut this = Self {
             prompts: HashMap::new(),
+            purged: false,
+            changed: HashMap::new(),
         };
         if let Err(err) = this.import() {
             print!("Failed to import system prompts. Reason: {}\r\n", err);
@@ -64,6 +83,8 @@ impl SystemPrompts {
             None => return Err(Box::new(SystemPromptsError::FailedToFindPrompt)),
             Some(string) => {
                 *string = contents.to_string();
+                self.changed
+                    .insert(name.to_owned(), Some(contents.to_owned()));
                 self.export()
             }
         }
@@ -74,6 +95,8 @@ impl SystemPrompts {
             Ok(()) => Ok(()),
             Err(_) => {
                 self.prompts.insert(name.to_owned(), contents.to_owned());
+                self.changed
+                    .insert(name.to_owned(), Some(contents.to_owned()));
                 self.export()?;
                 Ok(())
             }
@@ -82,37 +105,62 @@ impl SystemPrompts {
 
     pub fn remove(&mut self, name: &str) {
         self.prompts.remove(name);
+        self.changed.insert(name.to_owned(), None);
+    }
+
+    /// Stops writing the prompts back on exit after their file was purged.
+    pub fn forget(&mut self) {
+        self.purged = true;
     }
 
     fn get_file_path() -> std::path::PathBuf {
-        let mut path = data_dir().unwrap();
-        path.push("./chad-llm/");
-        path.push(FILE_NAME);
-        path
+        get_data_path(SYSTEM_PROMPTS_FILE)
     }
 
     fn import(&mut self) -> Result<(), Box<dyn Error>> {
         let path = Self::get_file_path();
-        let file_contents = std::fs::read_to_string(path)?;
-        let read: Self = serde_json::from_str(&file_contents)?;
+        let file_contents = crypto::open(&std::fs::read_to_string(path)?)?;
+        let read: Stored = serde_json::from_str(&file_contents)?;
 
-        self.prompts = read.prompts.clone();
+        self.prompts = read.prompts;
 
         Ok(())
     }
 
-    fn export(&self) -> Result<(), Box<dyn Error>> {
-        let path = Self::get_file_path();
+    /// Writes the changes made here over the prompts in the file, so those
+    /// saved by other instances meanwhile are kept, and picks those up.
+    fn export(&mut self) -> Result<(), Box<dyn Error>> {
+        let _lock = storage::lock(SYSTEM_PROMPTS_FILE)?;
+        let on_disk = match std::fs::read_to_string(Self::get_file_path()) {
+            Ok(contents) => crypto::open(&contents)
+                .ok()
+                .and_then(|contents| serde_json::from_str::<Stored>(&contents).ok())
+                .map(|read| read.prompts),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Some(HashMap::new()),
+            Err(e) => return Err(e.into()),
+        };
+        // A file that can't be read back is replaced by the prompts known here.
+        let mut prompts = on_disk.unwrap_or_else(|| self.prompts.clone());
+        for (name, contents) in self.changed.drain() {
+            match contents {
+                Some(contents) => prompts.insert(name, contents),
+                None => prompts.remove(&name),
+            };
+        }
+        self.prompts = prompts;
 
-        let j = serde_json::to_string(&self)?;
-        let _ = std::fs::remove_file(&path);
-        std::fs::write(path, j)?;
+        storage::replace(
+            SYSTEM_PROMPTS_FILE,
+            &crypto::seal(&serde_json::to_string(&self)?),
+        )?;
         Ok(())
     }
 }
 
 impl Drop for SystemPrompts {
     fn drop(&mut self) {
-        self.export().unwrap();
+        if !self.purged {
+            self.export().unwrap();
+        }
     }
 }
@@ -0,0 +1,157 @@
+//! `chad-gpt --tail app.log`: follows a log file and has the model summarize
+//! each batch of new lines as it fills up.
+use crate::models::{Message, ReplyFormat, Sampling};
+use crate::openai::{self, AVAILABLE_MODELS};
+use crate::redact;
+use crate::response::{self, TerminalRenderer};
+use crate::stats;
+use crate::theme;
+
+use std::error::Error;
+use std::fs::{File, Metadata};
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+pub const DEFAULT_EVERY: usize = 200;
+const DEFAULT_PROMPT: &str =
+    "Summarize the errors and anything unusual in these new log lines. Say so briefly if there is nothing notable.";
+/// A batch that stops growing is sent after this long anyway.
+const IDLE_FLUSH: Duration = Duration::from_secs(30);
+const POLL: Duration = Duration::from_millis(500);
+
+const USAGE: &str = "--tail <file> [--every <lines>] [-p <prompt>] [--model <model>]";
+
+/// Entry point of `chad-gpt --tail`.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut path = None;
+    let mut every = DEFAULT_EVERY;
+    let mut prompt = DEFAULT_PROMPT.to_owned();
+    let mut model = AVAILABLE_MODELS[0].to_owned();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or(format!("{} needs a value, usage: {}", arg, USAGE));
+        match arg.as_str() {
+            "--tail" => path = Some(value?.clone()),
+            "--every" => {
+                every = value?
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or("--every needs a number of lines")?
+            }
+            "-p" | "--prompt" => prompt = value?.clone(),
+            "--model" => model = value?.clone(),
+            other => return Err(format!("unknown argument '{}', usage: {}", other, USAGE).into()),
+        }
+    }
+    let path = path.ok_or(format!("usage: {}", USAGE))?;
+    let redact = std::env::var("CHAD_LLM_REDACT").is_ok_and(|v| v == "1");
+
+    let rt = tokio::runtime::Runtime::new()?;
+    let mut file = File::open(&path).map_err(|e| format!("{}: {}", path, e))?;
+    // Only lines written from now on.
+    let mut position = file.seek(SeekFrom::End(0))?;
+    let mut partial = String::new();
+    let mut batch: Vec<String> = Vec::new();
+    let mut last_line = Instant::now();
+    println!("Following {}, summarizing every {} new lines.", path, every);
+
+    loop {
+        std::thread::sleep(POLL);
+        // Missing for a moment while being rotated.
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+        // Truncated or rotated: start over from the top of the new file.
+        if metadata.len() < position || replaced(&file, &metadata) {
+            let Ok(reopened) = File::open(&path) else {
+                continue;
+            };
+            file = reopened;
+            position = 0;
+            partial.clear();
+        }
+
+        let mut new = Vec::new();
+        file.seek(SeekFrom::Start(position))?;
+        position += file.read_to_end(&mut new)? as u64;
+        partial.push_str(&String::from_utf8_lossy(&new));
+        while let Some(end) = partial.find('\n') {
+            let line: String = partial.drain(..=end).collect();
+            batch.push(line.trim_end().to_owned());
+            last_line = Instant::now();
+        }
+
+        while batch.len() >= every {
+            let lines: Vec<String> = batch.drain(..every).collect();
+            rt.block_on(summarize(&model, &prompt, &lines, redact));
+        }
+        if !batch.is_empty() && last_line.elapsed() >= IDLE_FLUSH {
+            let lines = std::mem::take(&mut batch);
+            rt.block_on(summarize(&model, &prompt, &lines, redact));
+        }
+    }
+}
+
+/// Whether the path now holds another file than the one open, as after log
+/// rotation.
+#[cfg(unix)]
+fn replaced(file: &File, current: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    file.metadata()
+        .is_ok_and(|open| open.ino() != current.ino() || open.dev() != current.dev())
+}
+
+#[cfg(not(unix))]
+fn replaced(_file: &File, _current: &Metadata) -> bool {
+    false
+}
+
+/// Has `model` summarize `lines`, with secrets masked when `redact` is set.
+async fn summarize(model: &str, prompt: &str, lines: &[String], redact: bool) {
+    let time = stats::local_time(stats::now());
+    print!(
+        "{}\r\n",
+        theme::format(&format!(
+            "[$dim]--- {} new line(s) at {:02}:{:02} ---[$/]",
+            lines.len(),
+            time.hour,
+            time.minute
+        ))
+    );
+    let mut input = format!("{}\n\n```\n{}\n```", prompt, lines.join("\n"));
+    if redact {
+        let findings = redact::find_secrets(&input);
+        if !findings.is_empty() {
+            let previews: Vec<String> = findings.iter().map(|f| f.preview()).collect();
+            print!("Redacted {}\r\n", previews.join(", "));
+            input = redact::redact(&input, &findings.iter().collect::<Vec<_>>());
+        }
+    }
+    let context: openai::SharedContext = Arc::new(Mutex::new(Vec::<Message>::new()));
+    let stream = match openai::send_request(
+        &input,
+        context,
+        model,
+        &[],
+        Sampling::default(),
+        &ReplyFormat::default(),
+    )
+    .await
+    {
+        Ok((_, stream)) => stream,
+        Err(e) => {
+            eprintln!("Request failed: {}", e);
+            return;
+        }
+    };
+    let mut renderer = TerminalRenderer::new(false);
+    if let Err(e) = response::process_response(Box::pin(stream), &mut [&mut renderer]).await {
+        eprintln!("Request failed: {}", e);
+    }
+    print!("\r\n");
+}
@@ -0,0 +1,107 @@
+//! Colors for replies and markup, picked with `CHAD_LLM_THEME`. `NO_COLOR`
+//! (https://no-color.org) turns colors off, leaving bold, italics and the
+//! like.
+use std::sync::LazyLock;
+
+pub struct Palette {
+    pub emphasis: &'static str,
+    pub strong: &'static str,
+    pub strong_emphasis: &'static str,
+    pub heading: &'static str,
+    /// Whether code blocks are syntax highlighted.
+    pub code_colors: bool,
+    /// bat theme for code blocks, bat's default when `None`.
+    pub code_theme: Option<&'static str>,
+    /// Token probability colors, from sure to unsure.
+    pub confidence: [&'static str; 4],
+    pub removed: &'static str,
+    pub added: &'static str,
+    pub highlight: &'static str,
+}
+
+const DEFAULT: Palette = Palette {
+    emphasis: "\x1b[0;3m",
+    strong: "\x1b[0;1m",
+    strong_emphasis: "\x1b[0;1;3m",
+    heading: "\x1b[1m",
+    code_colors: true,
+    code_theme: None,
+    confidence: ["\x1b[32m", "\x1b[33m", "\x1b[38;5;208m", "\x1b[31m"],
+    removed: "\x1b[31;9m",
+    added: "\x1b[32m",
+    highlight: "\x1b[1;33m",
+};
+
+/// Blue and orange instead of green and red, safe for deuteranopia and
+/// protanopia. Code uses the terminal's own palette.
+const DEUTERANOPIA: Palette = Palette {
+    emphasis: "\x1b[0;3m",
+    strong: "\x1b[0;1m",
+    strong_emphasis: "\x1b[0;1;3m",
+    heading: "\x1b[1;38;5;33m",
+    code_colors: true,
+    code_theme: Some("ansi"),
+    confidence: [
+        "\x1b[38;5;33m",
+        "\x1b[38;5;117m",
+        "\x1b[38;5;214m",
+        "\x1b[38;5;202m",
+    ],
+    removed: "\x1b[38;5;208;9m",
+    added: "\x1b[38;5;33m",
+    highlight: "\x1b[1;38;5;214m",
+};
+
+const NO_COLOR: Palette = Palette {
+    emphasis: "\x1b[0;3m",
+    strong: "\x1b[0;1m",
+    strong_emphasis: "\x1b[0;1;3m",
+    heading: "\x1b[1m",
+    code_colors: false,
+    code_theme: None,
+    confidence: ["", "", "\x1b[4m", "\x1b[1;4m"],
+    removed: "\x1b[9m",
+    added: "\x1b[4m",
+    highlight: "\x1b[1m",
+};
+
+pub const THEMES: &[&str] = &["default", "deuteranopia", "protanopia"];
+
+pub fn no_color() -> bool {
+    std::env::var("NO_COLOR").is_ok_and(|v| !v.is_empty())
+}
+
+pub fn palette() -> &'static Palette {
+    static PALETTE: LazyLock<&'static Palette> = LazyLock::new(|| {
+        if no_color() {
+            return &NO_COLOR;
+        }
+        match std::env::var("CHAD_LLM_THEME").as_deref() {
+            Ok("deuteranopia" | "protanopia") => &DEUTERANOPIA,
+            Ok("default") | Err(_) => &DEFAULT,
+            Ok(other) => {
+                eprint!(
+                    "Unknown theme {}, expected one of {}.\r\n",
+                    other,
+                    THEMES.join(", ")
+                );
+                &DEFAULT
+            }
+        }
+    });
+    &PALETTE
+}
+
+/// Like `vari::format`, without the escape codes under `NO_COLOR`.
+pub fn format(text: &str) -> String {
+    let formatted = vari::format(text);
+    match no_color() {
+        true => strip_ansi_escapes::strip_str(formatted),
+        false => formatted,
+    }
+}
+
+/// `label` in bold red, to lead a failure that shouldn't go unnoticed.
+pub fn error(label: &str) -> String {
+    format(&format!("[$bold][$red]{}[$/]", label))
+}